@@ -1,6 +1,9 @@
 module agger::query {
+    use std::bcs;
+    use std::hash;
     use std::option::{Self, Option};
     use std::signer::address_of;
+    use std::vector;
 
     use aptos_std::table_with_length;
     use aptos_framework::account;
@@ -39,7 +42,6 @@ module agger::query {
         id: u64
     }
 
-
     fun init_module(sender: &signer) {
         assert!(address_of(sender) == @agger, 401);
         move_to(sender, EventHandles {
@@ -93,11 +95,13 @@ module agger::query {
         proof: vector<u8>
     ) acquires Queries, EventHandles {
         // todo: assert sender is prover?
-        // todo: if success, validate proof. if proof is valid write the result to Query. else, do nothing.
-        // todo: if failure, write result to Query.
         // if it's deadline, failure.
         let queries = borrow_global_mut<Queries>(user);
         let q = table_with_length::borrow_mut(&mut queries.queries, query_id);
+        // failures are written as they are, they have no proof.
+        if (success) {
+            assert_bound(user, query_id, q, &result, &proof);
+        };
 
         write_query_result(q, success, result);
 
@@ -105,6 +109,69 @@ module agger::query {
         event::emit_event(&mut event_handles.reply_event_handle, ReplyQueryEvent { user, id: query_id });
     }
 
+    /// reply queries of one entry function proved by one aggregated proof, in one transaction.
+    /// the instances of each query are at `instance_indices` of the proof,
+    /// identical queries may share the same instances.
+    public entry fun reply_queries(
+        sender: signer,
+        users: vector<address>,
+        query_ids: vector<u64>,
+        results: vector<vector<u8>>,
+        instance_indices: vector<u64>,
+        proof: vector<u8>
+    ) acquires Queries, EventHandles {
+        // todo: assert sender is prover?
+        let n = vector::length(&users);
+        assert!(
+            vector::length(&query_ids) == n && vector::length(&results) == n && vector::length(&instance_indices) == n,
+            400
+        );
+        let i = 0;
+        while (i < n) {
+            let user = *vector::borrow(&users, i);
+            let query_id = *vector::borrow(&query_ids, i);
+            let queries = borrow_global_mut<Queries>(user);
+            let q = table_with_length::borrow_mut(&mut queries.queries, query_id);
+            let result = *vector::borrow(&results, i);
+            assert_bound(user, query_id, q, &result, &proof);
+            write_query_result(q, true, result);
+
+            let event_handles = borrow_global_mut<EventHandles>(@agger);
+            event::emit_event(&mut event_handles.reply_event_handle, ReplyQueryEvent { user, id: query_id });
+            i = i + 1;
+        };
+    }
+
+    /// check the binding hash of `result`, so that results of a query are replied with its own proof.
+    /// `result` is a bcs serialized `QueryResult`, which ends with the 32 bytes read set hash
+    /// and binding hash, the return values, events and state version before them are taken
+    /// as they are serialized.
+    fun assert_bound(user: address, id: u64, q: &Query, result: &vector<u8>, proof: &vector<u8>) {
+        assert!(!vector::is_empty(proof), 422);
+        let n = vector::length(result);
+        assert!(n >= 66 && *vector::borrow(result, n - 66) == 32 && *vector::borrow(result, n - 33) == 32, 422);
+        let data = bcs::to_bytes(&user);
+        vector::append(&mut data, bcs::to_bytes(&id));
+        vector::append(&mut data, bcs::to_bytes(&q.module_address));
+        vector::append(&mut data, bcs::to_bytes(&q.module_name));
+        vector::append(&mut data, bcs::to_bytes(&q.function_name));
+        vector::append(&mut data, bcs::to_bytes(&q.args));
+        vector::append(&mut data, bcs::to_bytes(&q.ty_args));
+        vector::append(&mut data, sub(result, 0, n - 66));
+        vector::append(&mut data, bcs::to_bytes(proof));
+        assert!(hash::sha3_256(data) == sub(result, n - 32, n), 422);
+    }
+
+    fun sub(v: &vector<u8>, start: u64, end: u64): vector<u8> {
+        let r = vector::empty();
+        let i = start;
+        while (i < end) {
+            vector::push_back(&mut r, *vector::borrow(v, i));
+            i = i + 1;
+        };
+        r
+    }
+
     fun write_query_result(q: &mut Query, success: bool, result: vector<u8>) {
         option::fill(&mut q.success, success);
         option::fill(&mut q.result, result);
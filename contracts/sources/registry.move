@@ -23,7 +23,8 @@ module agger::registry {
         param: vector<u8>,
         /// verify key
         vk: vector<u8>,
-
+        /// bcs serialized layout of proof public inputs
+        instance_layout: vector<u8>,
     }
 
     struct Registry has key {
@@ -79,6 +80,16 @@ module agger::registry {
         table::borrow(mkeys, function_index).config
     }
 
+    #[view]
+    /// all verification parameters of an entry function, read in one call.
+    public fun get_verification_parameters(addr: vector<u8>, name: vector<u8>, function_index: u16): VerificationParameters
+    acquires Registry {
+        let id = ModuleId { addr, name: string::utf8(name) };
+        let registry = borrow_global<Registry>(@agger);
+        let mkeys = table::borrow(&registry.verify_keys, id);
+        *table::borrow(mkeys, function_index)
+    }
+
     /// verify_key is composed with vk+function_index+circuit_configs
     /// TODO: add circuit configuration.
     public entry fun register_module(
@@ -88,12 +99,13 @@ module agger::registry {
         configs: vector<vector<u8>>,
         func_verify_keys: vector<vector<u8>>,
         params: vector<vector<u8>>,
+        instance_layouts: vector<vector<u8>>,
     )
     acquires Modules, Registry {
         let module_id = ModuleId { addr, name: string::utf8(name) };
 
         add_module(module_id, code);
-        add_entry_function_verify_keys(module_id, configs, func_verify_keys, params);
+        add_entry_function_verify_keys(module_id, configs, func_verify_keys, params, instance_layouts);
     }
 
 
@@ -108,7 +120,8 @@ module agger::registry {
         module_id: ModuleId,
         configs: vector<vector<u8>>,
         verify_keys: vector<vector<u8>>,
-        params: vector<vector<u8>>
+        params: vector<vector<u8>>,
+        instance_layouts: vector<vector<u8>>
     )
     acquires Registry {
         let registry = borrow_global_mut<Registry>(@agger);
@@ -118,12 +131,13 @@ module agger::registry {
             let config = vector::pop_back(&mut configs);
             let vk = vector::pop_back(&mut verify_keys);
             let param = vector::pop_back(&mut params);
+            let instance_layout = vector::pop_back(&mut instance_layouts);
 
             // le encoding of function_index
             let hi = vector::pop_back(&mut vk);
             let lo = vector::pop_back(&mut vk);
             let func_index = (lo as u16) + ((hi as u16) << 8);
-            add_entry_function_verify_key(&mut registry.verify_keys, module_id, func_index, config, vk, param);
+            add_entry_function_verify_key(&mut registry.verify_keys, module_id, func_index, config, vk, param, instance_layout);
         };
         event::emit_event(&mut registry.event_handle, ModuleRegisterEvent { module_id });
     }
@@ -135,13 +149,14 @@ module agger::registry {
         config: vector<u8>,
         vk: vector<u8>,
         param: vector<u8>,
+        instance_layout: vector<u8>,
     ) {
         if (table::contains(verify_keys, module_id)) {
             let t = table::borrow_mut(verify_keys, module_id);
-            table::add(t, function_index, VerificationParameters { config, vk, param });
+            table::add(t, function_index, VerificationParameters { config, vk, param, instance_layout });
         } else {
             let t = table::new();
-            table::add(&mut t, function_index, VerificationParameters { config, vk, param });
+            table::add(&mut t, function_index, VerificationParameters { config, vk, param, instance_layout });
             table::add(verify_keys, module_id, t);
         }
     }
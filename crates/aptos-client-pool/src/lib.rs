@@ -0,0 +1,414 @@
+use anyhow::{anyhow, ensure, Result};
+pub use aptos_sdk::rest_client::AptosBaseUrl;
+use aptos_sdk::rest_client::{
+    error::{AptosErrorResponse, RestError},
+    Client,
+};
+use futures_util::future::join_all;
+use std::{
+    future::Future,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::time::sleep;
+use tracing::{info, warn};
+
+type AptosResult<T> = Result<T, RestError>;
+
+/// weight of the latest sample in moving averages.
+const EWMA_ALPHA: f64 = 0.2;
+/// endpoints whose ledger version lags behind the highest one by more than this are deprioritized.
+const DEFAULT_MAX_VERSION_LAG: u64 = 1000;
+const DEFAULT_HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// A pool of aptos rest clients.
+/// Requests are sent to the healthiest endpoint, and fail over to the others when it fails.
+#[derive(Clone, Debug)]
+pub struct AptosClientPool {
+    endpoints: Arc<Vec<Endpoint>>,
+    max_version_lag: u64,
+}
+
+#[derive(Debug)]
+struct Endpoint {
+    url: String,
+    client: Client,
+    health: Mutex<EndpointHealth>,
+}
+
+#[derive(Clone, Debug, Default)]
+struct EndpointHealth {
+    /// moving average of request latency in millis.
+    latency_ms: f64,
+    /// moving average of failure rate, in [0, 1].
+    error_rate: f64,
+    chain_id: Option<u8>,
+    ledger_version: Option<u64>,
+    /// ledger version lags too much behind other endpoints.
+    lagging: bool,
+    /// chain id differs from the majority of endpoints.
+    inconsistent: bool,
+}
+
+impl EndpointHealth {
+    fn score(&self) -> f64 {
+        let mut score = self.latency_ms * (1.0 + 10.0 * self.error_rate);
+        if self.lagging {
+            score += 1_000_000.0;
+        }
+        score
+    }
+
+    fn record(&mut self, latency: Duration, failed: bool) {
+        let latency_ms = latency.as_secs_f64() * 1000.0;
+        self.latency_ms = EWMA_ALPHA * latency_ms + (1.0 - EWMA_ALPHA) * self.latency_ms;
+        let failure = if failed { 1.0 } else { 0.0 };
+        self.error_rate = EWMA_ALPHA * failure + (1.0 - EWMA_ALPHA) * self.error_rate;
+    }
+}
+
+impl AptosClientPool {
+    pub fn new(urls: Vec<AptosBaseUrl>) -> Result<Self> {
+        ensure!(!urls.is_empty(), "at least one aptos endpoint is required");
+        let endpoints = urls
+            .into_iter()
+            .map(|url| Endpoint {
+                url: url.to_url().to_string(),
+                client: Client::builder(url).build(),
+                health: Mutex::new(EndpointHealth::default()),
+            })
+            .collect();
+        Ok(Self {
+            endpoints: Arc::new(endpoints),
+            max_version_lag: DEFAULT_MAX_VERSION_LAG,
+        })
+    }
+
+    pub fn with_max_version_lag(mut self, max_version_lag: u64) -> Self {
+        self.max_version_lag = max_version_lag;
+        self
+    }
+
+    /// send request to the healthiest endpoint, fail over to the others on endpoint failures.
+    pub async fn call<T, F, Fut>(&self, f: F) -> AptosResult<T>
+    where
+        F: Fn(Client) -> Fut,
+        Fut: Future<Output = AptosResult<T>>,
+    {
+        self.call_inner(None, f).await
+    }
+
+    /// like `call`, but prefer endpoints which have synced to `version`.
+    pub async fn call_at_version<T, F, Fut>(&self, version: u64, f: F) -> AptosResult<T>
+    where
+        F: Fn(Client) -> Fut,
+        Fut: Future<Output = AptosResult<T>>,
+    {
+        self.call_inner(Some(version), f).await
+    }
+
+    async fn call_inner<T, F, Fut>(&self, version: Option<u64>, f: F) -> AptosResult<T>
+    where
+        F: Fn(Client) -> Fut,
+        Fut: Future<Output = AptosResult<T>>,
+    {
+        let mut last_error = None;
+        for endpoint in self.ranked_endpoints(version) {
+            let start = Instant::now();
+            let result = f(endpoint.client.clone()).await;
+            let failed = matches!(&result, Err(e) if is_endpoint_failure(e));
+            endpoint
+                .health
+                .lock()
+                .unwrap()
+                .record(start.elapsed(), failed);
+            match result {
+                Err(e) if failed => {
+                    warn!(
+                        "aptos endpoint {} failed, try next one. {:?}",
+                        &endpoint.url, e
+                    );
+                    last_error = Some(e);
+                },
+                result => return result,
+            }
+        }
+        Err(last_error
+            .unwrap_or_else(|| RestError::Unknown(anyhow!("no available aptos endpoint"))))
+    }
+
+    /// endpoints ordered by health, inconsistent ones are excluded.
+    fn ranked_endpoints(&self, version: Option<u64>) -> Vec<&Endpoint> {
+        let mut endpoints: Vec<_> = self
+            .endpoints
+            .iter()
+            .map(|e| (e, e.health.lock().unwrap().clone()))
+            .filter(|(_, h)| !h.inconsistent)
+            .collect();
+        endpoints.sort_by(|(_, a), (_, b)| {
+            // endpoints which are known to be behind `version` go last.
+            let behind = |h: &EndpointHealth| match (version, h.ledger_version) {
+                (Some(v), Some(lv)) => lv < v,
+                _ => false,
+            };
+            behind(a)
+                .cmp(&behind(b))
+                .then(a.score().total_cmp(&b.score()))
+        });
+        endpoints.into_iter().map(|(e, _)| e).collect()
+    }
+
+    /// refresh ledger info of all endpoints, and check they are consistent with each other.
+    pub async fn check_ledger_versions(&self) {
+        let infos = join_all(self.endpoints.iter().map(|endpoint| async move {
+            let start = Instant::now();
+            let info = endpoint.client.get_ledger_information().await;
+            endpoint
+                .health
+                .lock()
+                .unwrap()
+                .record(start.elapsed(), info.is_err());
+            match info {
+                Ok(info) => {
+                    let state = info.into_inner();
+                    Some((state.chain_id, state.version))
+                },
+                Err(e) => {
+                    warn!(
+                        "get ledger info from aptos endpoint {} error. {:?}",
+                        &endpoint.url, e
+                    );
+                    None
+                },
+            }
+        }))
+        .await;
+        self.update_ledger_infos(&infos);
+    }
+
+    /// update chain id and ledger version of each endpoint, none if they are unknown.
+    fn update_ledger_infos(&self, infos: &[Option<(u8, u64)>]) {
+        let chain_ids: Vec<_> = infos.iter().flatten().map(|(id, _)| *id).collect();
+        let majority_chain_id = chain_ids
+            .iter()
+            .max_by_key(|id| chain_ids.iter().filter(|i| i == id).count())
+            .copied();
+        let highest_version = infos
+            .iter()
+            .flatten()
+            .filter(|(id, _)| Some(*id) == majority_chain_id)
+            .map(|(_, version)| *version)
+            .max();
+
+        for (endpoint, info) in self.endpoints.iter().zip(infos) {
+            let (chain_id, version) = match info {
+                Some(info) => *info,
+                None => continue,
+            };
+            let mut health = endpoint.health.lock().unwrap();
+            health.chain_id = Some(chain_id);
+            health.ledger_version = Some(version);
+            health.inconsistent = Some(chain_id) != majority_chain_id;
+            health.lagging = highest_version
+                .map(|v| v.saturating_sub(version) > self.max_version_lag)
+                .unwrap_or(false);
+            if health.inconsistent {
+                warn!(
+                    "aptos endpoint {} is on chain {}, others are on {:?}",
+                    &endpoint.url, chain_id, majority_chain_id
+                );
+            } else if health.lagging {
+                warn!(
+                    "aptos endpoint {} is lagging, version: {}, highest: {:?}",
+                    &endpoint.url, version, highest_version
+                );
+            }
+        }
+    }
+
+    /// check ledger versions of endpoints periodically.
+    pub async fn run_health_check(self, interval: Option<Duration>) {
+        let interval = interval.unwrap_or(DEFAULT_HEALTH_CHECK_INTERVAL);
+        info!(
+            "start health check of {} aptos endpoints",
+            self.endpoints.len()
+        );
+        loop {
+            self.check_ledger_versions().await;
+            sleep(interval).await;
+        }
+    }
+}
+
+/// Whether the error is caused by the endpoint itself, and the request can be served by others.
+/// Other errors, e.g. a resource not found or a pruned version, are replied by the endpoint
+/// as they are, and returned to callers to handle.
+fn is_endpoint_failure(e: &RestError) -> bool {
+    match e {
+        RestError::Api(AptosErrorResponse { status_code, .. }) => status_code.is_server_error(),
+        RestError::Http(status_code, _) => status_code.is_server_error(),
+        // the endpoint is unreachable, or doesn't respond in time.
+        RestError::Unknown(_) | RestError::Timeout(_) => true,
+        // bcs or json errors are the same on every endpoint.
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aptos_sdk::{
+        move_types::account_address::AccountAddress, rest_client::aptos_api_types::AptosErrorCode,
+    };
+    use std::{
+        net::SocketAddr,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+    use test_helpers::fullnode::{start_mock_fullnode, Ledger, StatusCode};
+
+    /// fullnode replying every request with `status`, counting the requests.
+    async fn endpoint(status: StatusCode) -> (SocketAddr, Arc<AtomicUsize>) {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+        let ledger = Ledger::default();
+        let addr = start_mock_fullnode(move |_| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            match status {
+                StatusCode::OK => ledger.bcs(bcs::to_bytes(&7u64).unwrap()),
+                StatusCode::GONE => ledger.error(status, "version_pruned", "Version is pruned"),
+                _ => ledger.error(status, "internal_error", "Internal error"),
+            }
+        })
+        .await;
+        (addr, requests)
+    }
+
+    fn pool(addrs: &[SocketAddr]) -> AptosClientPool {
+        AptosClientPool::new(
+            addrs
+                .iter()
+                .map(|addr| AptosBaseUrl::Custom(format!("http://{}", addr).parse().unwrap()))
+                .collect(),
+        )
+        .unwrap()
+    }
+
+    async fn get_resource(pool: &AptosClientPool) -> AptosResult<u64> {
+        pool.call(|c| async move {
+            c.get_account_resource_bcs::<u64>(AccountAddress::ONE, "0x1::counter::Counter")
+                .await
+        })
+        .await
+        .map(|r| r.into_inner())
+    }
+
+    fn health(pool: &AptosClientPool, i: usize) -> EndpointHealth {
+        pool.endpoints[i].health.lock().unwrap().clone()
+    }
+
+    #[test]
+    fn test_pool_requires_endpoints() {
+        assert!(AptosClientPool::new(vec![]).is_err());
+    }
+
+    #[test]
+    fn test_score_is_moving_average() {
+        let mut health = EndpointHealth::default();
+        health.record(Duration::from_millis(100), false);
+        assert!((health.latency_ms - 20.0).abs() < 1e-9);
+        assert_eq!(health.error_rate, 0.0);
+        health.record(Duration::from_millis(100), true);
+        assert!((health.latency_ms - 36.0).abs() < 1e-9);
+        assert!((health.error_rate - 0.2).abs() < 1e-9);
+        // failures weigh on latency.
+        assert!((health.score() - 36.0 * 3.0).abs() < 1e-9);
+        // failures fade as requests succeed again.
+        health.record(Duration::from_millis(100), false);
+        assert!((health.error_rate - 0.16).abs() < 1e-9);
+
+        let lagging = EndpointHealth {
+            lagging: true,
+            ..Default::default()
+        };
+        assert!(lagging.score() > health.score());
+    }
+
+    #[tokio::test]
+    async fn test_fail_over_on_server_errors() {
+        let (failing, failing_requests) = endpoint(StatusCode::INTERNAL_SERVER_ERROR).await;
+        let (healthy, healthy_requests) = endpoint(StatusCode::OK).await;
+        let pool = pool(&[failing, healthy]);
+
+        assert_eq!(get_resource(&pool).await.unwrap(), 7);
+        assert_eq!(failing_requests.load(Ordering::SeqCst), 1);
+        assert_eq!(healthy_requests.load(Ordering::SeqCst), 1);
+        assert!(health(&pool, 0).error_rate > 0.0);
+        assert_eq!(health(&pool, 1).error_rate, 0.0);
+
+        // errors of the last endpoint are returned when all fail.
+        let (other_failing, _) = endpoint(StatusCode::SERVICE_UNAVAILABLE).await;
+        let e = get_resource(&pool(&[failing, other_failing]))
+            .await
+            .unwrap_err();
+        assert!(is_endpoint_failure(&e));
+    }
+
+    #[tokio::test]
+    async fn test_no_fail_over_on_request_errors() {
+        let (pruned, pruned_requests) = endpoint(StatusCode::GONE).await;
+        let (healthy, healthy_requests) = endpoint(StatusCode::OK).await;
+        let pool = pool(&[pruned, healthy]);
+
+        // the version is pruned on the endpoint, callers see it rather than another answer.
+        match get_resource(&pool).await.unwrap_err() {
+            RestError::Api(AptosErrorResponse { error, .. }) => {
+                assert!(matches!(error.error_code, AptosErrorCode::VersionPruned))
+            },
+            e => panic!("unexpected error {:?}", e),
+        }
+        assert_eq!(pruned_requests.load(Ordering::SeqCst), 1);
+        assert_eq!(healthy_requests.load(Ordering::SeqCst), 0);
+        assert_eq!(health(&pool, 0).error_rate, 0.0);
+    }
+
+    #[test]
+    fn test_update_ledger_infos() {
+        // endpoints are never requested.
+        let addrs: Vec<SocketAddr> = (0..4)
+            .map(|i| format!("127.0.0.1:{}", 1 + i).parse().unwrap())
+            .collect();
+        let pool = pool(&addrs).with_max_version_lag(100);
+        pool.update_ledger_infos(&[
+            Some((4, 1000)),
+            Some((4, 950)),
+            // lags behind the highest version of the majority chain.
+            Some((4, 800)),
+            // not on the majority chain, its higher version is not counted.
+            Some((5, 5000)),
+        ]);
+
+        assert!(!health(&pool, 0).lagging);
+        assert!(!health(&pool, 1).lagging);
+        assert!(health(&pool, 2).lagging);
+        assert!(health(&pool, 3).inconsistent);
+        assert_eq!(health(&pool, 2).ledger_version, Some(800));
+        // inconsistent endpoints are excluded, lagging ones go last.
+        let ranked: Vec<_> = pool
+            .ranked_endpoints(None)
+            .into_iter()
+            .map(|e| e.url.clone())
+            .collect();
+        assert_eq!(ranked.len(), 3);
+        assert_eq!(ranked[2], pool.endpoints[2].url);
+
+        // endpoints known to be behind a version go last for requests at it.
+        let ranked = pool.ranked_endpoints(Some(990));
+        assert_eq!(ranked[0].url, pool.endpoints[0].url);
+
+        // unknown infos leave the endpoint as it was.
+        pool.update_ledger_infos(&[Some((4, 1000)), None, Some((4, 990)), None]);
+        assert!(!health(&pool, 2).lagging);
+        assert_eq!(health(&pool, 1).ledger_version, Some(950));
+        assert!(health(&pool, 3).inconsistent);
+    }
+}
@@ -1,30 +1,68 @@
+use agger_chain_source::QuerySource;
 use agger_contract_types::*;
+pub use aptos_client_pool::{AptosBaseUrl, AptosClientPool};
+pub use aptos_sdk::types::account_address::AccountAddress as AptosAccountAddress;
 use aptos_sdk::{
     bcs,
-    rest_client::{error::RestError, Client},
+    rest_client::{
+        aptos_api_types::{AptosError, AptosErrorCode},
+        error::{AptosErrorResponse, RestError},
+    },
     types::contract_event::{ContractEvent, EventWithVersion},
 };
-pub use aptos_sdk::{
-    rest_client::AptosBaseUrl, types::account_address::AccountAddress as AptosAccountAddress,
-};
 use async_stream::stream;
 use futures_core::Stream;
-use log::info;
+use futures_util::{stream::BoxStream, StreamExt, TryStreamExt};
 use std::time::Duration;
 use tokio::time::sleep;
+use tracing::{info, warn};
+
+mod replier;
+
+pub use replier::{parse_private_key, AggerReplier};
+
+/// interval of polling new events once all are read.
+const POLL_INTERVAL: Duration = Duration::from_secs(30);
+const MIN_RETRY_INTERVAL: Duration = Duration::from_secs(1);
+const MAX_RETRY_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Retry interval of reading events, doubled on each failure in a row,
+/// so that an unreachable fullnode is not hammered.
+struct Backoff {
+    interval: Duration,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            interval: MIN_RETRY_INTERVAL,
+        }
+    }
+}
+
+impl Backoff {
+    async fn wait(&mut self) {
+        sleep(self.interval).await;
+        self.interval = (self.interval * 2).min(MAX_RETRY_INTERVAL);
+    }
+
+    fn reset(&mut self) {
+        self.interval = MIN_RETRY_INTERVAL;
+    }
+}
 
 #[derive(Clone, Debug)]
 pub struct AggerQueries {
-    client: Client,
+    client: AptosClientPool,
     agger_address: AptosAccountAddress,
 }
 
 type AptosResult<T> = Result<T, RestError>;
 
 impl AggerQueries {
-    pub fn new(aptos_url: AptosBaseUrl, agger_address: AptosAccountAddress) -> Self {
+    pub fn new(client: AptosClientPool, agger_address: AptosAccountAddress) -> Self {
         Self {
-            client: Client::builder(aptos_url).build(),
+            client,
             agger_address,
         }
     }
@@ -32,19 +70,23 @@ impl AggerQueries {
     pub fn get_query_stream(self, start: u64) -> impl Stream<Item = AptosResult<UserQuery>> {
         stream! {
             let mut cur = start;
+            let mut backoff = Backoff::default();
             loop {
                 let event = self.get_event(cur).await;
                 match event {
                     Ok(Some(evt)) => {
+                        backoff.reset();
                         let q = self.handle_new_query_event(evt).await;
                         yield q;
                         cur += 1;
                     }
                     Ok(None) => {
-                        sleep(Duration::from_secs(30)).await;
+                        backoff.reset();
+                        sleep(POLL_INTERVAL).await;
                     }
                     Err(e) => {
-                        yield Err(e)
+                        yield Err(e);
+                        backoff.wait().await;
                     }
                 }
             }
@@ -65,61 +107,265 @@ impl AggerQueries {
             event.sequence_number()
         );
         let new_query_event: NewQueryEvent = bcs::from_bytes(event.event_data())?;
-        let response = self
+        // latest version the query is read at, if `transaction_version` is pruned.
+        let mut read_at_latest = None;
+        let queries_resource = &format!(
+            "{:#x}::{}::{}", // {:#x} to format using hex with '0x' prefix
+            self.agger_address, AGGER_QUERY_MODULE_NAME, AGGER_QUERY_QUERIES_STRUCT_NAME
+        );
+        let queries: Queries = match self
             .client
-            .get_account_resource_at_version_bcs(
-                new_query_event.user,
-                format!(
-                    "{:#x}::{}::{}", // {:#x} to format using hex with '0x' prefix
-                    self.agger_address, AGGER_QUERY_MODULE_NAME, AGGER_QUERY_QUERIES_STRUCT_NAME
+            .call_at_version(transaction_version, |c| async move {
+                c.get_account_resource_at_version_bcs::<Queries>(
+                    new_query_event.user,
+                    queries_resource.as_str(),
+                    transaction_version,
                 )
-                .as_str(),
-                transaction_version,
-            )
-            .await?;
-        let queries: Queries = response.into_inner();
+                .await
+            })
+            .await
+        {
+            Ok(response) => response.into_inner(),
+            Err(e) if is_version_pruned(&e) => {
+                let response = self
+                    .client
+                    .call(|c| async move {
+                        c.get_account_resource_bcs::<Queries>(
+                            new_query_event.user,
+                            queries_resource.as_str(),
+                        )
+                        .await
+                    })
+                    .await?;
+                warn!(
+                    "version {} is pruned, read queries of user {:#x} at latest version {}",
+                    transaction_version,
+                    new_query_event.user,
+                    response.state().version
+                );
+                read_at_latest = Some(response.state().version);
+                response.into_inner()
+            },
+            Err(e) => return Err(e),
+        };
 
-        let query = self
-            .client
-            .get_table_item_bcs_at_version::<_, Query>(
-                queries.queries.inner.handle,
-                "u64",
-                format!(
-                    "{:#x}::{}::{}",
-                    self.agger_address, AGGER_QUERY_MODULE_NAME, AGGER_QUERY_QUERY_STRUCT_NAME
+        let query_struct = &format!(
+            "{:#x}::{}::{}",
+            self.agger_address, AGGER_QUERY_MODULE_NAME, AGGER_QUERY_QUERY_STRUCT_NAME
+        );
+        // to_string is needed, because aptos represent u64/u128 as string.
+        let query_key = &new_query_event.id.to_string();
+        let handle = queries.queries.inner.handle;
+        let get_query_at = |version: u64| {
+            self.client.call_at_version(version, move |c| async move {
+                c.get_table_item_bcs_at_version::<_, Query>(
+                    handle,
+                    "u64",
+                    query_struct.as_str(),
+                    query_key.as_str(),
+                    version,
                 )
-                .as_str(),
-                new_query_event.id.to_string(), // to_string is needed, because aptos represent u64/u128 as string.
-                transaction_version,
-            )
-            .await?;
+                .await
+            })
+        };
+        let query_at_version = match read_at_latest {
+            Some(_) => None,
+            None => match get_query_at(transaction_version).await {
+                Ok(response) => Some(response.into_inner()),
+                Err(e) if is_version_pruned(&e) => None,
+                Err(e) => return Err(e),
+            },
+        };
+        let query = match query_at_version {
+            Some(query) => query,
+            None => {
+                let mut query = match read_at_latest {
+                    Some(version) => get_query_at(version).await?.into_inner(),
+                    None => {
+                        let response = self
+                            .client
+                            .call(|c| async move {
+                                c.get_table_item_bcs::<_, Query>(
+                                    handle,
+                                    "u64",
+                                    query_struct.as_str(),
+                                    query_key.as_str(),
+                                )
+                                .await
+                            })
+                            .await?;
+                        warn!(
+                            "version {} is pruned, read query {:#x}/{} at latest version {}",
+                            transaction_version,
+                            new_query_event.user,
+                            new_query_event.id,
+                            response.state().version
+                        );
+                        read_at_latest = Some(response.state().version);
+                        response.into_inner()
+                    },
+                };
+                // a query is immutable except its result fields,
+                // reset them to what they are at `transaction_version`.
+                query.success = None;
+                query.result = None;
+                query
+            },
+        };
         Ok(UserQuery {
             version: transaction_version,
             sequence_number: event.sequence_number(),
             id: new_query_event.id,
             user: new_query_event.user,
-            query: query.into_inner(),
+            query,
+            read_at_latest,
         })
     }
 
     async fn get_event(&self, at: u64) -> AptosResult<Option<EventWithVersion>> {
-        let response = self
-            .client
-            .get_account_events_bcs(
-                self.agger_address,
-                format!(
-                    "{:#x}::{}::{}",
-                    self.agger_address,
-                    AGGER_QUERY_MODULE_NAME,
-                    AGGER_QUERY_EVENT_HANDLES_STRUCT_NAME
-                )
-                .as_str(),
-                AGGER_QUERY_FIELD_NAME_NEW_EVENT_HANDLE,
+        get_event(
+            &self.client,
+            self.agger_address,
+            AGGER_QUERY_MODULE_NAME,
+            AGGER_QUERY_EVENT_HANDLES_STRUCT_NAME,
+            AGGER_QUERY_FIELD_NAME_NEW_EVENT_HANDLE,
+            at,
+        )
+        .await
+    }
+}
+
+impl QuerySource for AggerQueries {
+    fn query_stream(&self, start: u64) -> BoxStream<'static, anyhow::Result<UserQuery>> {
+        self.clone()
+            .get_query_stream(start)
+            .map_err(anyhow::Error::new)
+            .boxed()
+    }
+
+    fn module_registration_stream(
+        &self,
+        start: u64,
+    ) -> BoxStream<'static, anyhow::Result<ModuleRegistration>> {
+        AggerRegistry::new(self.client.clone(), self.agger_address)
+            .get_module_register_stream(start)
+            .map_err(anyhow::Error::new)
+            .boxed()
+    }
+}
+
+/// Watch `ModuleRegisterEvent`s emitted by agger registry.
+#[derive(Clone, Debug)]
+pub struct AggerRegistry {
+    client: AptosClientPool,
+    agger_address: AptosAccountAddress,
+}
+
+impl AggerRegistry {
+    pub fn new(client: AptosClientPool, agger_address: AptosAccountAddress) -> Self {
+        Self {
+            client,
+            agger_address,
+        }
+    }
+
+    pub fn get_module_register_stream(
+        self,
+        start: u64,
+    ) -> impl Stream<Item = AptosResult<ModuleRegistration>> {
+        stream! {
+            let mut cur = start;
+            let mut backoff = Backoff::default();
+            loop {
+                let event = self.get_event(cur).await;
+                match event {
+                    Ok(Some(evt)) => {
+                        backoff.reset();
+                        let r = handle_module_register_event(evt);
+                        yield r;
+                        cur += 1;
+                    }
+                    Ok(None) => {
+                        backoff.reset();
+                        sleep(POLL_INTERVAL).await;
+                    }
+                    Err(e) => {
+                        yield Err(e);
+                        backoff.wait().await;
+                    }
+                }
+            }
+        }
+    }
+
+    async fn get_event(&self, at: u64) -> AptosResult<Option<EventWithVersion>> {
+        get_event(
+            &self.client,
+            self.agger_address,
+            AGGER_REGISTRY_MODULE_NAME,
+            AGGER_REGISTRY_REGISTRY_STRUCT_NAME,
+            AGGER_REGISTRY_FIELD_NAME_EVENT_HANDLE,
+            at,
+        )
+        .await
+    }
+}
+
+/// Fullnodes with pruning enabled reject reading states at pruned versions.
+fn is_version_pruned(e: &RestError) -> bool {
+    matches!(
+        e,
+        RestError::Api(AptosErrorResponse {
+            error: AptosError {
+                error_code: AptosErrorCode::VersionPruned,
+                ..
+            },
+            ..
+        })
+    )
+}
+
+fn handle_module_register_event(
+    EventWithVersion {
+        transaction_version,
+        event: ContractEvent::V0(event),
+    }: EventWithVersion,
+) -> AptosResult<ModuleRegistration> {
+    info!(
+        "new module register event, key: {}, {}/{}",
+        &event.key(),
+        &event.type_tag(),
+        event.sequence_number()
+    );
+    let module_register_event: ModuleRegisterEvent = bcs::from_bytes(event.event_data())?;
+    Ok(ModuleRegistration {
+        version: transaction_version,
+        sequence_number: event.sequence_number(),
+        module_id: module_register_event.module_id,
+    })
+}
+
+async fn get_event(
+    client: &AptosClientPool,
+    agger_address: AptosAccountAddress,
+    module_name: &str,
+    struct_name: &str,
+    field_name: &str,
+    at: u64,
+) -> AptosResult<Option<EventWithVersion>> {
+    let event_handle_struct = &format!("{:#x}::{}::{}", agger_address, module_name, struct_name);
+    let response = client
+        .call(|c| async move {
+            c.get_account_events_bcs(
+                agger_address,
+                event_handle_struct.as_str(),
+                field_name,
                 Some(at),
                 Some(1),
             )
-            .await?;
-        let mut events = response.into_inner();
-        Ok(events.pop())
-    }
+            .await
+        })
+        .await?;
+    let mut events = response.into_inner();
+    Ok(events.pop())
 }
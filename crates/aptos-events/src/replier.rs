@@ -0,0 +1,139 @@
+use agger_contract_types::{
+    BatchReply, AGGER_QUERY_FUNC_NAME_REPLY_QUERIES, AGGER_QUERY_FUNC_NAME_REPLY_QUERY,
+    AGGER_QUERY_MODULE_NAME,
+};
+use aptos_client_pool::AptosClientPool;
+use aptos_sdk::{
+    bcs,
+    crypto::{ed25519::Ed25519PrivateKey, PrivateKey, ValidCryptoMaterialStringExt},
+    move_types::{identifier::Identifier, language_storage::ModuleId},
+    transaction_builder::TransactionFactory,
+    types::{
+        account_address::AccountAddress,
+        chain_id::ChainId,
+        transaction::{authenticator::AuthenticationKey, EntryFunction, TransactionPayload},
+        LocalAccount,
+    },
+};
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+
+/// Replier submits query results and proofs to agger contracts on aptos.
+pub struct AggerReplier {
+    client: AptosClientPool,
+    agger_address: AccountAddress,
+    /// replies are signed one by one, to keep sequence number in order.
+    account: Mutex<LocalAccount>,
+    transaction_factory: TransactionFactory,
+}
+
+impl AggerReplier {
+    pub async fn new(
+        client: AptosClientPool,
+        agger_address: AccountAddress,
+        private_key: Ed25519PrivateKey,
+    ) -> anyhow::Result<Self> {
+        let address = AuthenticationKey::ed25519(&private_key.public_key()).derived_address();
+        let chain_id = client
+            .call(|c| async move { c.get_index().await })
+            .await?
+            .into_inner()
+            .chain_id;
+        let sequence_number = get_sequence_number(&client, address).await?;
+        info!(
+            "replier account: {}, sequence number: {}",
+            address, sequence_number
+        );
+        Ok(Self {
+            client,
+            agger_address,
+            account: Mutex::new(LocalAccount::new(address, private_key, sequence_number)),
+            transaction_factory: TransactionFactory::new(ChainId::new(chain_id)),
+        })
+    }
+
+    /// call `query::reply_query` and wait for the transaction to be committed.
+    pub async fn reply(
+        &self,
+        user: AccountAddress,
+        query_id: u64,
+        success: bool,
+        result: &[u8],
+        proof: &[u8],
+    ) -> anyhow::Result<()> {
+        let payload = TransactionPayload::EntryFunction(EntryFunction::new(
+            ModuleId::new(
+                self.agger_address,
+                Identifier::new(AGGER_QUERY_MODULE_NAME)?,
+            ),
+            Identifier::new(AGGER_QUERY_FUNC_NAME_REPLY_QUERY)?,
+            vec![],
+            vec![
+                bcs::to_bytes(&user)?,
+                bcs::to_bytes(&query_id)?,
+                bcs::to_bytes(&success)?,
+                bcs::to_bytes(result)?,
+                bcs::to_bytes(proof)?,
+            ],
+        ));
+        self.submit(payload).await
+    }
+
+    /// call `query::reply_queries` with replies sharing one aggregated proof,
+    /// and wait for the transaction to be committed.
+    pub async fn reply_batch(&self, batch: &BatchReply) -> anyhow::Result<()> {
+        let payload = TransactionPayload::EntryFunction(EntryFunction::new(
+            ModuleId::new(
+                self.agger_address,
+                Identifier::new(AGGER_QUERY_MODULE_NAME)?,
+            ),
+            Identifier::new(AGGER_QUERY_FUNC_NAME_REPLY_QUERIES)?,
+            vec![],
+            vec![
+                bcs::to_bytes(&batch.users)?,
+                bcs::to_bytes(&batch.query_ids)?,
+                bcs::to_bytes(&batch.results)?,
+                bcs::to_bytes(&batch.instance_indices)?,
+                bcs::to_bytes(&batch.proof)?,
+            ],
+        ));
+        self.submit(payload).await
+    }
+
+    async fn submit(&self, payload: TransactionPayload) -> anyhow::Result<()> {
+        let mut account = self.account.lock().await;
+        let txn = account.sign_with_transaction_builder(self.transaction_factory.payload(payload));
+        let submitted = self
+            .client
+            .call(|c| {
+                let txn = &txn;
+                async move { c.submit_and_wait(txn).await }
+            })
+            .await;
+        if let Err(e) = submitted {
+            // the transaction may not be committed, resync sequence number from chain.
+            match get_sequence_number(&self.client, account.address()).await {
+                Ok(sequence_number) => *account.sequence_number_mut() = sequence_number,
+                Err(e) => warn!("resync sequence number of replier error. {:?}", e),
+            }
+            return Err(e.into());
+        }
+        Ok(())
+    }
+}
+
+/// parse hex encoded ed25519 private key.
+pub fn parse_private_key(s: &str) -> anyhow::Result<Ed25519PrivateKey> {
+    Ok(Ed25519PrivateKey::from_encoded_string(s.trim())?)
+}
+
+async fn get_sequence_number(
+    client: &AptosClientPool,
+    address: AccountAddress,
+) -> anyhow::Result<u64> {
+    Ok(client
+        .call(|c| async move { c.get_account(address).await })
+        .await?
+        .into_inner()
+        .sequence_number)
+}
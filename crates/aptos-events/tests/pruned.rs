@@ -0,0 +1,138 @@
+use agger_contract_types::{NewQueryEvent, Queries, Query, Table, TableWithLength};
+use aptos_events::{AggerQueries, AptosAccountAddress, AptosBaseUrl, AptosClientPool};
+use aptos_sdk::{
+    bcs,
+    move_types::{language_storage::TypeTag, parser::parse_struct_tag},
+    types::{
+        contract_event::{ContractEvent, EventWithVersion},
+        event::EventKey,
+    },
+};
+use futures_util::{pin_mut, StreamExt};
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+use test_helpers::fullnode::{
+    bad_request, ledger_version, path_segments, start_mock_fullnode, Ledger, StatusCode,
+};
+
+/// version of the new query event, pruned by the mocked fullnode.
+const EVENT_VERSION: u64 = 5;
+const LATEST_VERSION: u64 = 100;
+
+/// Versions the query table item is requested at, none if at latest.
+#[derive(Default)]
+struct MockChain {
+    table_item_versions: Mutex<Vec<Option<u64>>>,
+}
+
+fn agger_address() -> AptosAccountAddress {
+    AptosAccountAddress::from_hex_literal("0xa66e2").unwrap()
+}
+
+fn user() -> AptosAccountAddress {
+    AptosAccountAddress::from_hex_literal("0xb0b").unwrap()
+}
+
+fn query() -> Query {
+    Query {
+        module_address: AptosAccountAddress::from_hex_literal("0xcafe")
+            .unwrap()
+            .to_vec(),
+        module_name: b"counter".to_vec(),
+        function_name: b"get".to_vec(),
+        deadline: 0,
+        args: vec![],
+        ty_args: vec![],
+        // replied since the event.
+        success: Some(true),
+        result: Some(vec![1]),
+    }
+}
+
+fn new_query_event() -> EventWithVersion {
+    let data = bcs::to_bytes(&NewQueryEvent {
+        user: user(),
+        id: 7,
+    })
+    .unwrap();
+    let type_tag = TypeTag::Struct(Box::new(
+        parse_struct_tag(&format!("{:#x}::query::NewQueryEvent", agger_address())).unwrap(),
+    ));
+    EventWithVersion::new(
+        EVENT_VERSION,
+        ContractEvent::new(EventKey::new(0, agger_address()), 0, type_tag, data),
+    )
+}
+
+/// serve the new query event, and the queries of the user at latest version only.
+async fn start_mock_chain(chain: Arc<MockChain>) -> SocketAddr {
+    let ledger = Ledger {
+        version: LATEST_VERSION,
+        oldest_version: 50,
+    };
+    let pruned = move || ledger.error(StatusCode::GONE, "version_pruned", "Version is pruned");
+    start_mock_fullnode(move |request| {
+        let version = ledger_version(&request);
+        match path_segments(&request).as_slice() {
+            ["", "v1", "accounts", _, "events", ..] => {
+                let start = request.uri().query().unwrap_or_default();
+                let events = match start.contains("start=0") {
+                    true => vec![new_query_event()],
+                    false => vec![],
+                };
+                ledger.bcs(bcs::to_bytes(&events).unwrap())
+            },
+            ["", "v1", "accounts", _, "resource", _] => match version {
+                Some(v) if v < LATEST_VERSION => pruned(),
+                _ => ledger.bcs(
+                    bcs::to_bytes(&Queries {
+                        query_counter: 8,
+                        queries: TableWithLength {
+                            inner: Table {
+                                handle: AptosAccountAddress::from_hex_literal("0x7ab1e").unwrap(),
+                            },
+                            length: 8,
+                        },
+                    })
+                    .unwrap(),
+                ),
+            },
+            ["", "v1", "tables", _, "item"] => {
+                chain.table_item_versions.lock().unwrap().push(version);
+                match version {
+                    Some(v) if v < LATEST_VERSION => pruned(),
+                    _ => ledger.bcs(bcs::to_bytes(&query()).unwrap()),
+                }
+            },
+            _ => bad_request(),
+        }
+    })
+    .await
+}
+
+#[tokio::test]
+async fn test_pruned_query_is_read_at_latest() {
+    let chain = Arc::new(MockChain::default());
+    let addr = start_mock_chain(chain.clone()).await;
+    let url = format!("http://{}", addr).parse().unwrap();
+    let client = AptosClientPool::new(vec![AptosBaseUrl::Custom(url)]).unwrap();
+
+    let queries = AggerQueries::new(client, agger_address()).get_query_stream(0);
+    pin_mut!(queries);
+    let user_query = queries.next().await.unwrap().unwrap();
+
+    assert_eq!(user_query.version, EVENT_VERSION);
+    assert_eq!(user_query.read_at_latest, Some(LATEST_VERSION));
+    // modules and state are read where the query is.
+    assert_eq!(user_query.state_version(), LATEST_VERSION);
+    assert_eq!(user_query.user, user());
+    assert_eq!(user_query.id, 7);
+    // results replied since the event are reset.
+    assert_eq!(user_query.query.success, None);
+    assert_eq!(user_query.query.result, None);
+    // the query is read at the same version as the queries of the user.
+    let versions = chain.table_item_versions.lock().unwrap().clone();
+    assert_eq!(versions, vec![Some(LATEST_VERSION)]);
+}
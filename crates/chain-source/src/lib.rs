@@ -0,0 +1,61 @@
+pub use agger_contract_types::VerificationParameters;
+use agger_contract_types::{ModuleRegistration, UserQuery};
+use async_trait::async_trait;
+use futures_util::stream::BoxStream;
+
+pub mod replay;
+
+#[derive(Debug, thiserror::Error)]
+pub enum SourceError {
+    #[error("transport error: {0}")]
+    Transport(anyhow::Error),
+    #[error("decode error: {0}")]
+    Decode(anyhow::Error),
+}
+
+pub type SourceResult<T> = Result<T, SourceError>;
+
+/// Source of user queries and module registrations, in the order they happen onchain.
+pub trait QuerySource {
+    /// stream user queries, starting from the `start`-th new query event.
+    fn query_stream(&self, start: u64) -> BoxStream<'static, anyhow::Result<UserQuery>>;
+
+    /// stream module registrations, starting from the `start`-th module register event.
+    fn module_registration_stream(
+        &self,
+        start: u64,
+    ) -> BoxStream<'static, anyhow::Result<ModuleRegistration>>;
+}
+
+/// Source of registered modules and verification parameters of their entry functions.
+/// `None` is returned if the module or function is not registered.
+#[async_trait]
+pub trait ModuleSource: Send + Sync {
+    async fn get_module(
+        &self,
+        module_address: Vec<u8>,
+        module_name: Vec<u8>,
+        version: u64,
+    ) -> SourceResult<Option<Vec<u8>>>;
+
+    async fn get_verification_parameters(
+        &self,
+        module_address: Vec<u8>,
+        module_name: Vec<u8>,
+        function_index: u16,
+        version: u64,
+    ) -> SourceResult<Option<VerificationParameters>>;
+}
+
+/// Source of global resources of accounts, at a ledger version.
+/// `None` is returned if the account doesn't hold the resource at the version.
+#[async_trait]
+pub trait StateSource: Send + Sync {
+    /// `struct_tag` is the resource type, e.g. `0x1::coin::CoinInfo<0x1::aptos_coin::AptosCoin>`.
+    async fn get_resource(
+        &self,
+        address: Vec<u8>,
+        struct_tag: String,
+        version: u64,
+    ) -> SourceResult<Option<Vec<u8>>>;
+}
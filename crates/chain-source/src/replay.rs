@@ -0,0 +1,386 @@
+//! Replay recorded queries and registry entries from disk,
+//! so that the node can run offline, e.g. in CI or to reproduce production incidents.
+//! Fixtures are recorded by wrapping the sources of a node in `RecordingSource`.
+
+use crate::{
+    ModuleSource, QuerySource, SourceError, SourceResult, StateSource, VerificationParameters,
+};
+use agger_contract_types::{ModuleId, ModuleRegistration, UserQuery};
+use async_trait::async_trait;
+use futures_util::stream::{self, BoxStream, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+use tracing::warn;
+
+/// Recorded chain data, stored in json, or in bcs if the file extension is `bcs`.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct ReplayFixture {
+    /// user queries, in the order of new query events.
+    pub queries: Vec<UserQuery>,
+    /// module registrations, in the order of module register events.
+    pub module_registrations: Vec<ModuleRegistration>,
+    pub modules: Vec<RegisteredModule>,
+    pub functions: Vec<RegisteredFunction>,
+    /// resources read by queries, reads of resources never held are not recorded.
+    #[serde(default)]
+    pub resources: Vec<RecordedResource>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct RegisteredModule {
+    pub module_id: ModuleId,
+    pub code: Vec<u8>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct RegisteredFunction {
+    pub module_id: ModuleId,
+    pub function_index: u16,
+    pub verification_parameters: VerificationParameters,
+}
+
+/// Resource of an account as it is since `version`.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct RecordedResource {
+    pub address: Vec<u8>,
+    pub struct_tag: String,
+    pub version: u64,
+    /// absent if the resource is moved out of the account at the version.
+    pub value: Option<Vec<u8>>,
+}
+
+impl ReplayFixture {
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let data = std::fs::read(path)?;
+        if is_bcs(path) {
+            Ok(bcs::from_bytes(&data)?)
+        } else {
+            Ok(serde_json::from_slice(&data)?)
+        }
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        let data = if is_bcs(path) {
+            bcs::to_bytes(self)?
+        } else {
+            serde_json::to_vec_pretty(self)?
+        };
+        std::fs::write(path, data)?;
+        Ok(())
+    }
+}
+
+fn is_bcs(path: &Path) -> bool {
+    path.extension().map(|e| e == "bcs").unwrap_or(false)
+}
+
+/// Query and module source backed by a `ReplayFixture`.
+/// Streams end when all recorded events are replayed.
+#[derive(Clone, Debug)]
+pub struct ReplaySource {
+    fixture: Arc<ReplayFixture>,
+}
+
+impl ReplaySource {
+    pub fn new(fixture: ReplayFixture) -> Self {
+        Self {
+            fixture: Arc::new(fixture),
+        }
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        Ok(Self::new(ReplayFixture::load(path)?))
+    }
+}
+
+impl QuerySource for ReplaySource {
+    fn query_stream(&self, start: u64) -> BoxStream<'static, anyhow::Result<UserQuery>> {
+        let queries: Vec<_> = self
+            .fixture
+            .queries
+            .iter()
+            .filter(|q| q.sequence_number >= start)
+            .cloned()
+            .map(Ok)
+            .collect();
+        stream::iter(queries).boxed()
+    }
+
+    fn module_registration_stream(
+        &self,
+        start: u64,
+    ) -> BoxStream<'static, anyhow::Result<ModuleRegistration>> {
+        let registrations: Vec<_> = self
+            .fixture
+            .module_registrations
+            .iter()
+            .filter(|r| r.sequence_number >= start)
+            .cloned()
+            .map(Ok)
+            .collect();
+        stream::iter(registrations).boxed()
+    }
+}
+
+#[async_trait]
+impl ModuleSource for ReplaySource {
+    async fn get_module(
+        &self,
+        module_address: Vec<u8>,
+        module_name: Vec<u8>,
+        _version: u64,
+    ) -> SourceResult<Option<Vec<u8>>> {
+        let module_id = module_id(module_address, module_name)?;
+        Ok(self
+            .fixture
+            .modules
+            .iter()
+            .find(|m| m.module_id == module_id)
+            .map(|m| m.code.clone()))
+    }
+
+    async fn get_verification_parameters(
+        &self,
+        module_address: Vec<u8>,
+        module_name: Vec<u8>,
+        function_index: u16,
+        _version: u64,
+    ) -> SourceResult<Option<VerificationParameters>> {
+        let module_id = module_id(module_address, module_name)?;
+        Ok(self
+            .fixture
+            .functions
+            .iter()
+            .find(|f| f.module_id == module_id && f.function_index == function_index)
+            .map(|f| f.verification_parameters.clone()))
+    }
+}
+
+#[async_trait]
+impl StateSource for ReplaySource {
+    async fn get_resource(
+        &self,
+        address: Vec<u8>,
+        struct_tag: String,
+        version: u64,
+    ) -> SourceResult<Option<Vec<u8>>> {
+        // the latest record at or before the version.
+        Ok(self
+            .fixture
+            .resources
+            .iter()
+            .filter(|r| r.address == address && r.struct_tag == struct_tag && r.version <= version)
+            .max_by_key(|r| r.version)
+            .and_then(|r| r.value.clone()))
+    }
+}
+
+fn module_id(module_address: Vec<u8>, module_name: Vec<u8>) -> SourceResult<ModuleId> {
+    Ok(ModuleId {
+        addr: module_address,
+        name: String::from_utf8(module_name).map_err(|e| SourceError::Decode(e.into()))?,
+    })
+}
+
+/// Fixture shared by the recording sources of a node, saved to `path` on each new record.
+#[derive(Clone, Debug)]
+pub struct Recorder {
+    fixture: Arc<Mutex<ReplayFixture>>,
+    path: PathBuf,
+}
+
+impl Recorder {
+    /// record into the fixture at `path`, appending to it if it exists.
+    pub fn open(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let fixture = if path.exists() {
+            ReplayFixture::load(&path)?
+        } else {
+            ReplayFixture::default()
+        };
+        Ok(Self {
+            fixture: Arc::new(Mutex::new(fixture)),
+            path,
+        })
+    }
+
+    pub fn fixture(&self) -> ReplayFixture {
+        self.fixture.lock().unwrap().clone()
+    }
+
+    /// apply `record` to the fixture, and save it if anything new is recorded.
+    /// recording is best effort, save failures don't fail reads of the source.
+    fn record(&self, record: impl FnOnce(&mut ReplayFixture) -> bool) {
+        let mut fixture = self.fixture.lock().unwrap();
+        if record(&mut fixture) {
+            if let Err(e) = fixture.save(&self.path) {
+                warn!("save replay fixture {} error. {:?}", self.path.display(), e);
+            }
+        }
+    }
+}
+
+/// Source that records what `inner` returns with `recorder`, to replay it with `ReplaySource`.
+#[derive(Clone, Debug)]
+pub struct RecordingSource<S> {
+    inner: S,
+    recorder: Recorder,
+}
+
+impl<S> RecordingSource<S> {
+    pub fn new(inner: S, recorder: Recorder) -> Self {
+        Self { inner, recorder }
+    }
+}
+
+impl<S: QuerySource> QuerySource for RecordingSource<S> {
+    fn query_stream(&self, start: u64) -> BoxStream<'static, anyhow::Result<UserQuery>> {
+        let recorder = self.recorder.clone();
+        self.inner
+            .query_stream(start)
+            .inspect(move |query| {
+                if let Ok(query) = query {
+                    recorder.record(|fixture| {
+                        let new = !fixture
+                            .queries
+                            .iter()
+                            .any(|q| q.sequence_number == query.sequence_number);
+                        if new {
+                            fixture.queries.push(query.clone());
+                        }
+                        new
+                    });
+                }
+            })
+            .boxed()
+    }
+
+    fn module_registration_stream(
+        &self,
+        start: u64,
+    ) -> BoxStream<'static, anyhow::Result<ModuleRegistration>> {
+        let recorder = self.recorder.clone();
+        self.inner
+            .module_registration_stream(start)
+            .inspect(move |registration| {
+                if let Ok(registration) = registration {
+                    recorder.record(|fixture| {
+                        let new = !fixture
+                            .module_registrations
+                            .iter()
+                            .any(|r| r.sequence_number == registration.sequence_number);
+                        if new {
+                            fixture.module_registrations.push(registration.clone());
+                        }
+                        new
+                    });
+                }
+            })
+            .boxed()
+    }
+}
+
+#[async_trait]
+impl<S: ModuleSource> ModuleSource for RecordingSource<S> {
+    async fn get_module(
+        &self,
+        module_address: Vec<u8>,
+        module_name: Vec<u8>,
+        version: u64,
+    ) -> SourceResult<Option<Vec<u8>>> {
+        let code = self
+            .inner
+            .get_module(module_address.clone(), module_name.clone(), version)
+            .await?;
+        if let Some(code) = &code {
+            let module_id = module_id(module_address, module_name)?;
+            self.recorder.record(|fixture| {
+                let new = !fixture.modules.iter().any(|m| m.module_id == module_id);
+                if new {
+                    fixture.modules.push(RegisteredModule {
+                        module_id,
+                        code: code.clone(),
+                    });
+                }
+                new
+            });
+        }
+        Ok(code)
+    }
+
+    async fn get_verification_parameters(
+        &self,
+        module_address: Vec<u8>,
+        module_name: Vec<u8>,
+        function_index: u16,
+        version: u64,
+    ) -> SourceResult<Option<VerificationParameters>> {
+        let verification_parameters = self
+            .inner
+            .get_verification_parameters(
+                module_address.clone(),
+                module_name.clone(),
+                function_index,
+                version,
+            )
+            .await?;
+        if let Some(verification_parameters) = &verification_parameters {
+            let module_id = module_id(module_address, module_name)?;
+            self.recorder.record(|fixture| {
+                let new = !fixture
+                    .functions
+                    .iter()
+                    .any(|f| f.module_id == module_id && f.function_index == function_index);
+                if new {
+                    fixture.functions.push(RegisteredFunction {
+                        module_id,
+                        function_index,
+                        verification_parameters: verification_parameters.clone(),
+                    });
+                }
+                new
+            });
+        }
+        Ok(verification_parameters)
+    }
+}
+
+#[async_trait]
+impl<S: StateSource> StateSource for RecordingSource<S> {
+    async fn get_resource(
+        &self,
+        address: Vec<u8>,
+        struct_tag: String,
+        version: u64,
+    ) -> SourceResult<Option<Vec<u8>>> {
+        let value = self
+            .inner
+            .get_resource(address.clone(), struct_tag.clone(), version)
+            .await?;
+        self.recorder.record(|fixture| {
+            let versions: Vec<_> = fixture
+                .resources
+                .iter()
+                .filter(|r| r.address == address && r.struct_tag == struct_tag)
+                .map(|r| r.version)
+                .collect();
+            // resources never held replay as absent without records.
+            let new = !versions.contains(&version) && (value.is_some() || !versions.is_empty());
+            if new {
+                fixture.resources.push(RecordedResource {
+                    address,
+                    struct_tag,
+                    version,
+                    value: value.clone(),
+                });
+            }
+            new
+        });
+        Ok(value)
+    }
+}
@@ -0,0 +1,160 @@
+use agger_chain_source::{
+    replay::{
+        RecordedResource, Recorder, RecordingSource, RegisteredFunction, RegisteredModule,
+        ReplayFixture, ReplaySource,
+    },
+    ModuleSource, QuerySource, StateSource, VerificationParameters,
+};
+use agger_contract_types::{ModuleId, ModuleRegistration, Query, UserQuery};
+use aptos_move_core_types::account_address::AccountAddress;
+use futures_util::StreamExt;
+
+const ADDRESS: [u8; 2] = [0xCA, 0xFE];
+const RESOURCE: &str = "0xcafe::counter::Counter";
+
+/// what a node reads of the chain, see `read`.
+type Reads = (
+    Vec<u64>,
+    Vec<u64>,
+    Option<Vec<u8>>,
+    Option<VerificationParameters>,
+    Vec<Option<Vec<u8>>>,
+);
+
+fn module_id() -> ModuleId {
+    ModuleId {
+        addr: ADDRESS.to_vec(),
+        name: "counter".to_string(),
+    }
+}
+
+fn query(sequence_number: u64) -> UserQuery {
+    UserQuery {
+        version: 5,
+        sequence_number,
+        user: AccountAddress::ONE,
+        id: sequence_number,
+        query: Query {
+            module_address: ADDRESS.to_vec(),
+            module_name: b"counter".to_vec(),
+            function_name: b"get".to_vec(),
+            deadline: 0,
+            args: vec![],
+            ty_args: vec![],
+            success: None,
+            result: None,
+        },
+        read_at_latest: None,
+    }
+}
+
+/// chain data the recorded node reads.
+fn chain() -> ReplaySource {
+    ReplaySource::new(ReplayFixture {
+        queries: vec![query(0), query(1)],
+        module_registrations: vec![ModuleRegistration {
+            version: 1,
+            sequence_number: 0,
+            module_id: module_id(),
+        }],
+        modules: vec![RegisteredModule {
+            module_id: module_id(),
+            code: vec![1, 2, 3],
+        }],
+        functions: vec![RegisteredFunction {
+            module_id: module_id(),
+            function_index: 0,
+            verification_parameters: VerificationParameters {
+                config: vec![4],
+                param: vec![5],
+                vk: vec![6],
+                instance_layout: vec![7],
+            },
+        }],
+        resources: vec![
+            RecordedResource {
+                address: ADDRESS.to_vec(),
+                struct_tag: RESOURCE.to_string(),
+                version: 2,
+                value: Some(vec![8]),
+            },
+            RecordedResource {
+                address: ADDRESS.to_vec(),
+                struct_tag: RESOURCE.to_string(),
+                version: 6,
+                value: None,
+            },
+        ],
+    })
+}
+
+async fn read<S: QuerySource + ModuleSource + StateSource>(source: &S) -> Reads {
+    let queries = source
+        .query_stream(0)
+        .map(|q| q.unwrap().sequence_number)
+        .collect()
+        .await;
+    let registrations = source
+        .module_registration_stream(0)
+        .map(|r| r.unwrap().sequence_number)
+        .collect()
+        .await;
+    let module = source
+        .get_module(ADDRESS.to_vec(), b"counter".to_vec(), 5)
+        .await
+        .unwrap();
+    let verification_parameters = source
+        .get_verification_parameters(ADDRESS.to_vec(), b"counter".to_vec(), 0, 5)
+        .await
+        .unwrap();
+    let mut resources = vec![];
+    for (struct_tag, version) in [(RESOURCE, 5), (RESOURCE, 7), ("0xcafe::counter::Never", 5)] {
+        resources.push(
+            source
+                .get_resource(ADDRESS.to_vec(), struct_tag.to_string(), version)
+                .await
+                .unwrap(),
+        );
+    }
+    (
+        queries,
+        registrations,
+        module,
+        verification_parameters,
+        resources,
+    )
+}
+
+#[tokio::test]
+async fn test_recorded_fixture_replays_reads() {
+    let path = std::env::temp_dir().join(format!("agger-recording-{}.json", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let recorder = Recorder::open(&path).unwrap();
+    let recording = RecordingSource::new(chain(), recorder.clone());
+    let read_from_chain = read(&recording).await;
+    assert_eq!(read_from_chain.0, vec![0, 1]);
+    assert_eq!(read_from_chain.4, vec![Some(vec![8]), None, None]);
+
+    // reading again records nothing new.
+    read(&recording).await;
+    let fixture = recorder.fixture();
+    assert_eq!(fixture.queries.len(), 2);
+    assert_eq!(fixture.module_registrations.len(), 1);
+    assert_eq!(fixture.modules.len(), 1);
+    assert_eq!(fixture.functions.len(), 1);
+    // resources are recorded at the versions read, never held ones are not.
+    let resources: Vec<_> = fixture
+        .resources
+        .iter()
+        .map(|r| (r.version, r.value.clone()))
+        .collect();
+    assert_eq!(resources, vec![(5, Some(vec![8])), (7, None)]);
+
+    assert_eq!(
+        read(&ReplaySource::load(&path).unwrap()).await,
+        read_from_chain
+    );
+    // recording goes on from the saved fixture.
+    assert_eq!(Recorder::open(&path).unwrap().fixture().queries.len(), 2);
+    std::fs::remove_file(&path).unwrap();
+}
@@ -1,4 +1,5 @@
 use agger_cli::circuit_config::{parse_entry_function_config, parse_from_move_toml};
+use agger_srs::Srs;
 use agger_vk_generation::gen_vks;
 use clap::{value_parser, Parser, Subcommand};
 use move_compiler::compiled_unit::CompiledUnit;
@@ -34,6 +35,9 @@ struct BuildAptosDeployment {
     module: String,
     #[arg(long = "agger")]
     agger_address: String,
+    /// kzg srs file in halo2 format, params of circuits are downsized from it.
+    #[arg(long)]
+    srs: Option<PathBuf>,
 }
 
 #[derive(Parser)]
@@ -163,7 +167,9 @@ fn main() -> anyhow::Result<()> {
                 circuit_configs,
                 &pkg.compiled_package_info.address_alias_instantiation,
             )?;
+            let srs = Srs::load_or_dev(c.srs.as_ref())?;
             let vks = gen_vks(
+                &srs,
                 pkg.all_modules().map(|m| m.unit.serialize(None)).collect(),
                 entry_function_config.into_values().collect(),
             )?;
@@ -201,7 +207,10 @@ fn main() -> anyhow::Result<()> {
                     value: serde_json::Value::Array(
                         vks.iter()
                             .map(|vk| {
-                                serde_json::Value::String(format!("0x{}", hex::encode(&vk.vk)))
+                                serde_json::Value::String(format!(
+                                    "0x{}",
+                                    hex::encode(vk.registered_vk())
+                                ))
                             })
                             .collect(),
                     ),
@@ -216,6 +225,19 @@ fn main() -> anyhow::Result<()> {
                             .collect(),
                     ),
                 },
+                ArgWithTypeJSON {
+                    arg_type: "hex".to_string(),
+                    value: serde_json::Value::Array(
+                        vks.iter()
+                            .map(|vk| {
+                                serde_json::Value::String(format!(
+                                    "0x{}",
+                                    hex::encode(&vk.instance_layout)
+                                ))
+                            })
+                            .collect(),
+                    ),
+                },
             ];
 
             let json = EntryFunctionArgumentsJSON {
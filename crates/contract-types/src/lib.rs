@@ -1,16 +1,22 @@
 use aptos_move_core_types::account_address::AccountAddress as AptosAccountAddress;
 use serde::{Deserialize, Serialize};
+use sha3::{Digest, Sha3_256};
+use std::fmt;
+use tracing::{info_span, Span};
 
 pub const AGGER_REGISTRY_MODULE_NAME: &str = "registry";
+pub const AGGER_REGISTRY_REGISTRY_STRUCT_NAME: &str = "Registry";
+pub const AGGER_REGISTRY_FIELD_NAME_EVENT_HANDLE: &str = "event_handle";
 pub const AGGER_QUERY_MODULE_NAME: &str = "query";
 pub const AGGER_QUERY_QUERY_STRUCT_NAME: &str = "Query";
 pub const AGGER_QUERY_QUERIES_STRUCT_NAME: &str = "Queries";
 pub const AGGER_QUERY_EVENT_HANDLES_STRUCT_NAME: &str = "EventHandles";
 pub const AGGER_QUERY_FIELD_NAME_NEW_EVENT_HANDLE: &str = "new_event_handle";
+pub const AGGER_QUERY_FUNC_NAME_REPLY_QUERY: &str = "reply_query";
+pub const AGGER_QUERY_FUNC_NAME_REPLY_QUERIES: &str = "reply_queries";
 pub const AGGER_REGISTRY_FUNC_NAME_GET_MODULE: &str = "get_module";
-pub const AGGER_REGISTRY_FUNC_NAME_GET_VK: &str = "get_vk";
-pub const AGGER_REGISTRY_FUNC_NAME_GET_PARAM: &str = "get_param";
-pub const AGGER_REGISTRY_FUNC_NAME_GET_CONFIG: &str = "get_config";
+pub const AGGER_REGISTRY_FUNC_NAME_GET_VERIFICATION_PARAMETERS: &str =
+    "get_verification_parameters";
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct NewQueryEvent {
@@ -18,6 +24,79 @@ pub struct NewQueryEvent {
     pub id: u64,
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub struct ModuleId {
+    pub addr: Vec<u8>,
+    pub name: String,
+}
+
+/// Verification parameters of an entry function, mirroring `registry::VerificationParameters`.
+/// Field order matters, as it's decoded from bcs.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub struct VerificationParameters {
+    /// circuit config in bcs
+    pub config: Vec<u8>,
+    /// kzg param
+    pub param: Vec<u8>,
+    /// verify key
+    pub vk: Vec<u8>,
+    /// bcs serialized `InstanceLayout` of proofs
+    pub instance_layout: Vec<u8>,
+}
+
+/// Public inputs of a query proof, in the order of elements of the instance column.
+/// Each field is encoded into one field element.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub struct InstanceLayout {
+    pub fields: Vec<InstanceField>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub enum InstanceField {
+    /// hash of the entry module id.
+    ModuleId,
+    FunctionIndex,
+    /// hash of query args.
+    Args,
+    /// hash of query type args.
+    TyArgs,
+    /// hash of query result, see `query_result_hash`.
+    ResultHash,
+    /// hash of the resources the query is executed on, see `read_set_hash`.
+    ReadSetHash,
+}
+
+impl Default for InstanceLayout {
+    fn default() -> Self {
+        Self {
+            fields: vec![
+                InstanceField::ModuleId,
+                InstanceField::FunctionIndex,
+                InstanceField::Args,
+                InstanceField::TyArgs,
+                InstanceField::ResultHash,
+                InstanceField::ReadSetHash,
+            ],
+        }
+    }
+}
+
+impl InstanceLayout {
+    /// decode the layout recorded in verification parameters.
+    /// functions registered without layout have no public inputs.
+    pub fn decode(data: &[u8]) -> Result<Self, bcs::Error> {
+        if data.is_empty() {
+            return Ok(Self { fields: vec![] });
+        }
+        bcs::from_bytes(data)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ModuleRegisterEvent {
+    pub module_id: ModuleId,
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Query {
     pub module_address: Vec<u8>,
@@ -30,6 +109,21 @@ pub struct Query {
     pub result: Option<Vec<u8>>,
 }
 
+impl Query {
+    /// `0x<address>::<name>` of the module of the entry function.
+    pub fn entry_module(&self) -> String {
+        format!(
+            "0x{}::{}",
+            hex::encode(&self.module_address),
+            String::from_utf8_lossy(&self.module_name)
+        )
+    }
+
+    pub fn entry_function(&self) -> String {
+        String::from_utf8_lossy(&self.function_name).into_owned()
+    }
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Queries {
     pub query_counter: u64,
@@ -55,4 +149,214 @@ pub struct UserQuery {
     pub user: AptosAccountAddress,
     pub id: u64,
     pub query: Query,
+    /// latest ledger version the query is read at instead,
+    /// because `version` is already pruned by the fullnode.
+    /// results are computed at it, and replied with it, see `QueryResult::state_version`.
+    pub read_at_latest: Option<u64>,
+}
+
+impl UserQuery {
+    /// version modules and resources of the query are read at,
+    /// the one the query itself is read at.
+    pub fn state_version(&self) -> u64 {
+        self.read_at_latest.unwrap_or(self.version)
+    }
+
+    /// span of the query, so that it's followed through resolving, proving and replying.
+    /// spans are made again from the query wherever it goes, e.g. to prover workers.
+    pub fn span(&self) -> Span {
+        info_span!(
+            "query",
+            user = %self.user.to_hex_literal(),
+            id = self.id,
+            sequence_number = self.sequence_number,
+            module = %self.query.entry_module(),
+            function = %self.query.entry_function(),
+        )
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ModuleRegistration {
+    /// version at the module is registered
+    pub version: u64,
+    pub sequence_number: u64,
+    pub module_id: ModuleId,
+}
+
+/// Global resources the entry function of a query reads, as they are at `version`.
+/// Recorded while executing the query on chain state, provers replay it on these only.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ReadSet {
+    /// ledger version the resources are read at.
+    pub version: u64,
+    pub resources: Vec<ResourceRead>,
 }
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ResourceRead {
+    pub address: Vec<u8>,
+    /// struct tag of the resource, e.g. `0x1::coin::CoinInfo<0x1::aptos_coin::AptosCoin>`.
+    pub struct_tag: String,
+    /// bcs serialized resource, absent if the account doesn't hold it at the version.
+    /// absent reads are recorded too, as `exists` depends on them.
+    pub value: Option<Vec<u8>>,
+}
+
+/// Event emitted by the entry function of a query.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct QueryEvent {
+    pub key: Vec<u8>,
+    pub sequence_number: u64,
+    pub type_tag: String,
+    pub data: Vec<u8>,
+}
+
+/// Result of a successful query, bcs serialized into `Query.result`.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct QueryResult {
+    /// bcs serialized return values of the entry function.
+    pub return_values: Vec<Vec<u8>>,
+    pub events: Vec<QueryEvent>,
+    /// ledger version of the state the query is executed on, see `UserQuery::state_version`.
+    /// later than the version of the query if its version was pruned when it was read.
+    pub state_version: u64,
+    /// hash of the resources the query is executed on, see `read_set_hash`.
+    pub read_set_hash: Vec<u8>,
+    /// hash binding the query, its results and proof together, see `query_binding_hash`.
+    pub binding_hash: Vec<u8>,
+}
+
+/// Successful replies of queries sharing one aggregated proof, arguments of `query::reply_queries`.
+/// Queries are of the same entry function, replied in one transaction.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct BatchReply {
+    pub users: Vec<AptosAccountAddress>,
+    pub query_ids: Vec<u64>,
+    /// bcs serialized `QueryResult` of each query.
+    pub results: Vec<Vec<u8>>,
+    /// index of the instances of each query in the proof,
+    /// identical queries may share the same instances.
+    pub instance_indices: Vec<u64>,
+    pub proof: Vec<u8>,
+}
+
+impl BatchReply {
+    pub fn push(&mut self, query: &UserQuery, result: Vec<u8>, instance_index: u32) {
+        self.users.push(query.user);
+        self.query_ids.push(query.id);
+        self.results.push(result);
+        self.instance_indices.push(instance_index as u64);
+    }
+
+    pub fn len(&self) -> usize {
+        self.users.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.users.is_empty()
+    }
+}
+
+/// sha3-256 of the return values and events of a query.
+pub fn query_result_hash(return_values: &[Vec<u8>], events: &[QueryEvent]) -> Vec<u8> {
+    let data = bcs::to_bytes(&(return_values, events)).expect("query result is serializable");
+    Sha3_256::digest(data).to_vec()
+}
+
+/// sha3-256 of the resources of a read set.
+/// The version is left out, executions on the same resources are identical at any version.
+pub fn read_set_hash(read_set: &ReadSet) -> Vec<u8> {
+    let data = bcs::to_bytes(&read_set.resources).expect("read set is serializable");
+    Sha3_256::digest(data).to_vec()
+}
+
+/// sha3-256 of the query, its results, the state version they are computed at, and proof,
+/// so that results cannot be replayed with proofs of other queries.
+pub fn query_binding_hash(
+    query: &UserQuery,
+    return_values: &[Vec<u8>],
+    events: &[QueryEvent],
+    state_version: u64,
+    proof: &[u8],
+) -> Vec<u8> {
+    let q = &query.query;
+    let data = bcs::to_bytes(&(
+        query.user,
+        query.id,
+        &q.module_address,
+        &q.module_name,
+        &q.function_name,
+        &q.args,
+        &q.ty_args,
+        return_values,
+        events,
+        state_version,
+        proof,
+    ))
+    .expect("query binding data is serializable");
+    Sha3_256::digest(data).to_vec()
+}
+
+/// Failure of a query, bcs serialized into `Query.result` when the query is not successful.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum QueryFailure {
+    /// execution of the entry function failed.
+    Execution(ExecutionFailure),
+    /// other failures, e.g. the function is not registered.
+    Message(String),
+}
+
+/// Failure of executing the entry function of a query.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ExecutionFailure {
+    /// name of the vm status code, e.g. `ABORTED`.
+    pub vm_status: String,
+    pub status_code: u64,
+    /// abort code, if the execution aborted.
+    pub abort_code: Option<u64>,
+    /// where the execution failed, if known.
+    pub location: Option<ExecutionLocation>,
+    pub message: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ExecutionLocation {
+    pub module_id: ModuleId,
+    pub function: String,
+    pub code_offset: u16,
+}
+
+impl fmt::Display for ExecutionFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "execution failed with {}", &self.vm_status)?;
+        if let Some(code) = self.abort_code {
+            write!(f, ", abort code: {}", code)?;
+        }
+        if let Some(ExecutionLocation {
+            module_id,
+            function,
+            code_offset,
+        }) = &self.location
+        {
+            write!(
+                f,
+                ", at 0x{}::{}::{}+{}",
+                module_id
+                    .addr
+                    .iter()
+                    .map(|b| format!("{:02x}", b))
+                    .collect::<String>(),
+                &module_id.name,
+                function,
+                code_offset
+            )?;
+        }
+        if let Some(message) = &self.message {
+            write!(f, ", {}", message)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ExecutionFailure {}
@@ -0,0 +1,179 @@
+//! Prometheus metrics of the node and prover workers, served at `/metrics`.
+
+use agger_contract_types::Query;
+use hyper::{
+    header::CONTENT_TYPE,
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use once_cell::sync::Lazy;
+use prometheus::{
+    exponential_buckets, linear_buckets, register_histogram_vec, register_int_counter_vec,
+    register_int_gauge, register_int_gauge_vec, Encoder, HistogramTimer, HistogramVec,
+    IntCounterVec, IntGauge, IntGaugeVec, TextEncoder,
+};
+use std::{convert::Infallible, net::SocketAddr};
+use tracing::{error, info};
+
+/// Stages a query goes through, timed by `QUERY_STAGE_DURATION`.
+#[derive(Clone, Copy, Debug)]
+pub enum QueryStage {
+    /// resolve modules and verification parameters of the entry function.
+    Resolve,
+    /// execute the query and generate its witness.
+    Witness,
+    /// setup proving keys of an entry function, only on a cache miss.
+    Keygen,
+    Prove,
+    /// submit the reply onchain.
+    Submit,
+}
+
+impl QueryStage {
+    fn as_str(self) -> &'static str {
+        match self {
+            QueryStage::Resolve => "resolve",
+            QueryStage::Witness => "witness",
+            QueryStage::Keygen => "keygen",
+            QueryStage::Prove => "prove",
+            QueryStage::Submit => "submit",
+        }
+    }
+
+    /// the stage is observed when the timer is dropped.
+    pub fn start_timer(self) -> HistogramTimer {
+        QUERY_STAGE_DURATION
+            .with_label_values(&[self.as_str()])
+            .start_timer()
+    }
+}
+
+/// Result of a query, counted by `QUERIES`.
+#[derive(Clone, Copy, Debug)]
+pub enum QueryOutcome {
+    Success,
+    Failure,
+    /// modules or verification parameters of the function cannot be resolved,
+    /// counted without the module and function, as they may not exist at all.
+    Unresolved,
+}
+
+pub static QUERY_STAGE_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "agger_query_stage_duration_seconds",
+        "Time spent on each stage of queries",
+        &["stage"],
+        // 10ms to about 20 minutes.
+        exponential_buckets(0.01, 2.0, 18).unwrap()
+    )
+    .unwrap()
+});
+
+pub static CIRCUIT_K: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "agger_circuit_k",
+        "Log2 of the number of rows of the circuit of entry functions",
+        &["module", "function"]
+    )
+    .unwrap()
+});
+
+pub static CIRCUIT_ROW_USAGE: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "agger_circuit_row_usage",
+        "Ratio of the circuit rows taken by queries",
+        &["module", "function"],
+        linear_buckets(0.1, 0.1, 10).unwrap()
+    )
+    .unwrap()
+});
+
+pub static QUEUE_DEPTH: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "agger_queue_depth",
+        "Number of queries waiting in each queue",
+        &["queue"]
+    )
+    .unwrap()
+});
+
+pub static ACTIVE_WORKERS: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "agger_active_workers",
+        "Number of prover workers connected to the coordinator"
+    )
+    .unwrap()
+});
+
+pub static QUERIES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "agger_queries_total",
+        "Number of replied queries by entry function and result",
+        &["module", "function", "result"]
+    )
+    .unwrap()
+});
+
+/// module and function labels of the entry function of `query`.
+fn function_labels(query: &Query) -> [String; 2] {
+    [query.entry_module(), query.entry_function()]
+}
+
+pub fn record_query_outcome(query: &Query, outcome: QueryOutcome) {
+    let [module, function] = match outcome {
+        QueryOutcome::Unresolved => [String::new(), String::new()],
+        _ => function_labels(query),
+    };
+    let result = match outcome {
+        QueryOutcome::Success => "success",
+        QueryOutcome::Failure => "failure",
+        QueryOutcome::Unresolved => "unresolved",
+    };
+    QUERIES
+        .with_label_values(&[&module, &function, result])
+        .inc();
+}
+
+/// record k of the entry function of `query`, and the rows its execution takes.
+pub fn record_circuit_usage(query: &Query, k: u32, rows: usize) {
+    let [module, function] = function_labels(query);
+    let labels = [module.as_str(), function.as_str()];
+    CIRCUIT_K.with_label_values(&labels).set(k as i64);
+    CIRCUIT_ROW_USAGE
+        .with_label_values(&labels)
+        .observe(rows as f64 / (1u64 << k) as f64);
+}
+
+/// serve metrics in prometheus text format at `/metrics` of `addr`.
+pub async fn serve_metrics(addr: SocketAddr) -> anyhow::Result<()> {
+    let make_service =
+        make_service_fn(|_| async { Ok::<_, Infallible>(service_fn(handle_request)) });
+    let server = Server::try_bind(&addr)?.serve(make_service);
+    info!("serving metrics at http://{}/metrics", server.local_addr());
+    server.await?;
+    Ok(())
+}
+
+async fn handle_request(request: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let response = match (request.method(), request.uri().path()) {
+        (&Method::GET, "/metrics") => {
+            let encoder = TextEncoder::new();
+            let mut buffer = vec![];
+            match encoder.encode(&prometheus::gather(), &mut buffer) {
+                Ok(()) => Response::builder()
+                    .header(CONTENT_TYPE, encoder.format_type())
+                    .body(Body::from(buffer)),
+                Err(e) => {
+                    error!("encode metrics error. {:?}", e);
+                    Response::builder()
+                        .status(StatusCode::INTERNAL_SERVER_ERROR)
+                        .body(Body::empty())
+                },
+            }
+        },
+        _ => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::empty()),
+    };
+    Ok(response.expect("metrics response is valid"))
+}
@@ -1,10 +1,14 @@
 use agger_storage::{
     schemadb::{Options, DB},
-    PROOF_COLUMN_FAMILY_NAME, QUERY_COLUMN_FAMILY_NAME,
+    MODULE_REGISTRATION_COLUMN_FAMILY_NAME, PROOF_COLUMN_FAMILY_NAME,
+    PROVED_INPUT_COLUMN_FAMILY_NAME, QUERY_COLUMN_FAMILY_NAME,
 };
 use std::path::Path;
 
+pub mod module_prewarmer;
 pub mod proof_responder;
+pub mod proved_inputs;
+pub mod server;
 
 pub fn open_db(path: impl AsRef<Path>) -> anyhow::Result<DB> {
     // Set the options to create the database if it's missing
@@ -16,7 +20,12 @@ pub fn open_db(path: impl AsRef<Path>) -> anyhow::Result<DB> {
         path,
         //store_path.as_deref().unwrap_or(Path::new(".")),
         "agger-db",
-        vec![QUERY_COLUMN_FAMILY_NAME, PROOF_COLUMN_FAMILY_NAME],
+        vec![
+            QUERY_COLUMN_FAMILY_NAME,
+            PROOF_COLUMN_FAMILY_NAME,
+            PROVED_INPUT_COLUMN_FAMILY_NAME,
+            MODULE_REGISTRATION_COLUMN_FAMILY_NAME,
+        ],
         &options,
     )
 }
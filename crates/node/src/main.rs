@@ -1,13 +1,23 @@
-use agger_node::{open_db, proof_responder::ProofResponder};
-use agger_prove_dispatcher::{ProveTask, ProvingTaskDispatcher};
-use agger_storage::{AggerStore, UserQueryKey, UserQuerySchema, UserQueryValue};
-use aptos_events::{AggerQueries, AptosAccountAddress, AptosBaseUrl};
+use agger_chain_source::{
+    replay::{Recorder, RecordingSource, ReplaySource},
+    ModuleSource, QuerySource, StateSource,
+};
+use agger_metrics::serve_metrics;
+use agger_node::{
+    open_db,
+    server::{run_server, Proving},
+};
+use agger_prover_worker::ProverArgs;
+use agger_storage::AggerStore;
+use aptos_events::{
+    parse_private_key, AggerQueries, AggerReplier, AptosAccountAddress, AptosBaseUrl,
+    AptosClientPool,
+};
 use clap::Parser;
-use futures_util::{pin_mut, StreamExt, TryFutureExt, TryStreamExt};
-use log::error;
-use query_module_resolver::AggerModuleResolver;
-use std::{path::PathBuf, sync::Arc};
-use tokio::{select, sync::mpsc};
+use log_helpers::{init_logger, LogFormat};
+use query_module_resolver::{AptosModuleSource, AptosStateSource};
+use std::{net::SocketAddr, path::PathBuf, sync::Arc};
+use tracing::{error, info};
 
 #[derive(Parser, Debug)]
 enum Cli {
@@ -16,15 +26,38 @@ enum Cli {
 
 #[derive(Parser, Clone, Debug)]
 struct StartServer {
-    /// aptos rpc, or use devnet,testnet,mainnet
-    #[arg(long)]
-    aptos_rpc: String,
+    /// aptos rpc, or use devnet,testnet,mainnet.
+    /// multiple endpoints can be separated by comma, requests fail over between them.
+    #[arg(long, required_unless_present = "replay", value_delimiter = ',')]
+    aptos_rpc: Vec<String>,
     /// agger contracts address
+    #[arg(long, required_unless_present = "replay")]
+    agger_address: Option<AptosAccountAddress>,
+    /// replay queries and registry entries recorded in the fixture file, instead of reading aptos.
+    #[arg(long)]
+    replay: Option<PathBuf>,
+    /// record queries, registry entries and resources read from aptos into the fixture file,
+    /// to replay them with `--replay`. recording is appended if the file exists.
+    #[arg(long, conflicts_with = "replay")]
+    record: Option<PathBuf>,
+    /// hex encoded ed25519 private key of the account replying queries.
+    /// results are only stored locally if not set.
     #[arg(long)]
-    agger_address: AptosAccountAddress,
+    private_key: Option<String>,
     /// storage path
     #[arg(long, default_value = "aggerdb")]
     store_path: Option<PathBuf>,
+    /// listen for prover workers on the address, instead of proving queries locally.
+    #[arg(long)]
+    listen_workers: Option<SocketAddr>,
+    /// serve prometheus metrics at `/metrics` of the address.
+    #[arg(long)]
+    metrics_addr: Option<SocketAddr>,
+    /// json for log pipelines.
+    #[arg(long, value_enum, default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
+    #[command(flatten)]
+    prover: ProverArgs,
 }
 
 fn parse_aptos_url(rpc: &str) -> anyhow::Result<AptosBaseUrl> {
@@ -39,115 +72,104 @@ fn parse_aptos_url(rpc: &str) -> anyhow::Result<AptosBaseUrl> {
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    env_logger::try_init()?;
     let cli: Cli = Cli::parse();
-    println!("cmd:{:?}", &cli);
+    let Cli::StartServer(StartServer { log_format, .. }) = &cli;
+    init_logger(*log_format)?;
+    info!("cmd: {:?}", &cli);
     match cli {
         Cli::StartServer(StartServer {
             aptos_rpc,
             agger_address,
+            replay,
+            record,
+            private_key,
             store_path,
+            listen_workers,
+            metrics_addr,
+            prover,
+            ..
         }) => {
+            if let Some(addr) = metrics_addr {
+                tokio::spawn(async move {
+                    if let Err(e) = serve_metrics(addr).await {
+                        error!("metrics server error. {:?}", e);
+                    }
+                });
+            }
+            let (query_source, module_source, state_source, replier): (
+                Box<dyn QuerySource>,
+                Arc<dyn ModuleSource>,
+                Arc<dyn StateSource>,
+                Option<AggerReplier>,
+            ) = match (replay, agger_address) {
+                (Some(replay), _) => {
+                    let source = ReplaySource::load(replay)?;
+                    (
+                        Box::new(source.clone()),
+                        Arc::new(source.clone()),
+                        Arc::new(source),
+                        None,
+                    )
+                },
+                (None, Some(agger_address)) => {
+                    let aptos_client = AptosClientPool::new(
+                        aptos_rpc
+                            .iter()
+                            .map(|rpc| parse_aptos_url(rpc))
+                            .collect::<anyhow::Result<Vec<_>>>()?,
+                    )?;
+                    tokio::spawn(aptos_client.clone().run_health_check(None));
+                    let replier = match private_key {
+                        Some(key) => Some(
+                            AggerReplier::new(
+                                aptos_client.clone(),
+                                agger_address,
+                                parse_private_key(&key)?,
+                            )
+                            .await?,
+                        ),
+                        None => None,
+                    };
+                    let query_source = AggerQueries::new(aptos_client.clone(), agger_address);
+                    let module_source = AptosModuleSource::new(aptos_client.clone(), agger_address);
+                    let state_source = AptosStateSource::new(aptos_client);
+                    match record {
+                        Some(record) => {
+                            let recorder = Recorder::open(record)?;
+                            (
+                                Box::new(RecordingSource::new(query_source, recorder.clone())),
+                                Arc::new(RecordingSource::new(module_source, recorder.clone())),
+                                Arc::new(RecordingSource::new(state_source, recorder)),
+                                replier,
+                            )
+                        },
+                        None => (
+                            Box::new(query_source),
+                            Arc::new(module_source),
+                            Arc::new(state_source),
+                            replier,
+                        ),
+                    }
+                },
+                (None, None) => unreachable!("agger address is required without replay"),
+            };
+            let proving = match listen_workers {
+                Some(addr) => Proving::Remote(addr),
+                None => Proving::Local(prover),
+            };
+            let store = open_db(store_path.unwrap_or(PathBuf::from(".")))?;
             run_server(
-                aptos_rpc,
-                agger_address,
-                store_path.unwrap_or(PathBuf::from(".")),
+                query_source,
+                module_source,
+                state_source,
+                replier,
+                proving,
+                Arc::new(AggerStore::new(store)),
             )
             .await?;
-            println!("Agger stopped!");
+            info!("agger stopped");
         },
     }
 
     Ok(())
 }
-
-async fn run_server(
-    aptos_rpc: String,
-    agger_address: AptosAccountAddress,
-    store_path: PathBuf,
-) -> anyhow::Result<()> {
-    let store = open_db(&store_path)?;
-    let store = Arc::new(AggerStore::new(store));
-    let proof_responder = ProofResponder::new(store.clone());
-
-    let prover_threads = threadpool::Builder::new()
-        .thread_name("provers".to_string())
-        .build();
-    let (task_sender, task_receiver) = mpsc::channel(32);
-    let (output_sender, output_receiver) = mpsc::channel(32);
-
-    let provers = ProvingTaskDispatcher::new(prover_threads, task_receiver, output_sender);
-
-    let query_function_resolver =
-        AggerModuleResolver::new(parse_aptos_url(&aptos_rpc)?, agger_address);
-
-    let event_manager = AggerQueries::new(parse_aptos_url(&aptos_rpc)?, agger_address);
-
-    //skip proved event
-    let query_event_from = store
-        .last_proved_event_number()?
-        .map(|x| x + 1)
-        .unwrap_or(0);
-    let new_query_event_stream = event_manager
-        .clone()
-        .get_query_stream(query_event_from)
-        .map_err(anyhow::Error::new)
-        .and_then(|s| {
-            query_function_resolver
-                .clone()
-                .get_vk_for_entry_function(
-                    s.query.module_address.clone(),
-                    s.query.module_name.clone(),
-                    s.query.function_name.clone(),
-                    s.version,
-                )
-                .map_ok(|(m, vp)| ProveTask {
-                    query: s,
-                    modules: vec![m],
-                    config: vp.config,
-                    vk: vp.vk,
-                    param: vp.param,
-                })
-        })
-        .fuse();
-    pin_mut!(new_query_event_stream);
-
-    let mut dispatch_task_handle = tokio::spawn(provers.run());
-    let mut output_handle = tokio::spawn(proof_responder.start(output_receiver));
-    loop {
-        select! {
-            _output_task_result = &mut output_handle => {
-                // when output handle is gone, then output receiver is gone.
-                // then dispatcher will go down.
-            }
-            _dispatch_task_result = &mut dispatch_task_handle => {
-                // when dispatcher is gone, then task_sender cannot send any task,
-                // it will go down automatically.
-            }
-            Some(s) = new_query_event_stream.next() => {
-                match s {
-                    Ok(task) => {
-                        store.put::<UserQuerySchema>(
-                            &UserQueryKey::from(task.query.sequence_number),
-                            &UserQueryValue::from(task.query.clone()),
-                        )?;
-
-                        if let Err(_err)  = task_sender.send(task).await {
-                            error!("prover dispatcher is down");
-                            break;
-                        }
-
-                    }
-                    Err(e) => {
-                        error!("get query error. {:?}", e);
-                    }
-                }
-            }
-            else => {
-                // no query events anymore
-                break;
-            }
-        }
-    }
-    Ok(())
-}
@@ -0,0 +1,109 @@
+use agger_contract_types::ModuleRegistration;
+use agger_prove_dispatcher::Prover;
+use agger_storage::{AggerStore, ModuleRegistrationSchema};
+use anyhow::Result;
+use futures_util::{pin_mut, Stream, StreamExt};
+use query_module_resolver::AggerModuleResolver;
+use std::sync::Arc;
+use tracing::{error, info};
+
+/// Prewarmer fetches newly registered modules and their verification keys into local cache,
+/// and precompute proving keys, so that the first query of them doesn't pay the setup latency.
+/// Prewarmed registrations are stored, so that they're not prewarmed again after restart.
+pub struct ModulePrewarmer {
+    db: Arc<AggerStore>,
+    resolver: AggerModuleResolver,
+    /// proving keys are not precomputed if queries are proved by remote workers.
+    prover: Option<Arc<dyn Prover>>,
+}
+
+impl ModulePrewarmer {
+    pub fn new(
+        db: Arc<AggerStore>,
+        resolver: AggerModuleResolver,
+        prover: Option<Arc<dyn Prover>>,
+    ) -> Self {
+        Self {
+            db,
+            resolver,
+            prover,
+        }
+    }
+
+    pub async fn start<E: std::fmt::Debug>(
+        self,
+        registrations: impl Stream<Item = Result<ModuleRegistration, E>>,
+    ) -> Result<()> {
+        pin_mut!(registrations);
+        while let Some(registration) = registrations.next().await {
+            match registration {
+                Ok(registration) => {
+                    let sequence_number = registration.sequence_number;
+                    self.prewarm(registration.clone()).await;
+                    // failures are not retried either way, the cache is filled on first query.
+                    if let Err(e) = self.db.put::<ModuleRegistrationSchema>(
+                        &sequence_number.into(),
+                        &registration.into(),
+                    ) {
+                        error!(
+                            "store module registration {} error. {:?}",
+                            sequence_number, e
+                        );
+                    }
+                },
+                Err(e) => {
+                    error!("get module registration error. {:?}", e);
+                },
+            }
+        }
+        Ok(())
+    }
+
+    async fn prewarm(
+        &self,
+        ModuleRegistration {
+            version, module_id, ..
+        }: ModuleRegistration,
+    ) {
+        let verification_parameters = match self
+            .resolver
+            .clone()
+            .prewarm_module(
+                module_id.addr.clone(),
+                module_id.name.clone().into_bytes(),
+                version,
+            )
+            .await
+        {
+            Ok(p) => p,
+            Err(e) => {
+                error!("prewarm module {:?} error. {:?}", &module_id, e);
+                return;
+            },
+        };
+        let Some(prover) = &self.prover else {
+            return;
+        };
+        for (function_index, p) in verification_parameters {
+            let prover = prover.clone();
+            let result = tokio::task::spawn_blocking(move || prover.precompute(&p)).await;
+            match result {
+                Ok(Ok(())) => {
+                    info!(
+                        "proving key of module {:?} function {} is ready",
+                        &module_id, function_index
+                    );
+                },
+                Ok(Err(e)) => {
+                    error!(
+                        "precompute proving key of module {:?} function {} error. {:?}",
+                        &module_id, function_index, e
+                    );
+                },
+                Err(e) => {
+                    error!("precompute task panic. {:?}", e);
+                },
+            }
+        }
+    }
+}
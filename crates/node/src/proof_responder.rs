@@ -1,26 +1,242 @@
-use agger_contract_types::UserQuery;
-use agger_storage::{AggerStore, UserQueryProofSchema};
+use agger_contract_types::{BatchReply, UserQuery};
+use agger_metrics::{record_query_outcome, QueryOutcome, QueryStage};
+use agger_prove_dispatcher::ProveOutput;
+use agger_storage::{AggerStore, StoredBatch, UserQueryProofSchema, UserQueryProvingResult};
 use anyhow::Result;
-use std::sync::Arc;
-use tokio::sync::mpsc::Receiver;
+use aptos_events::AggerReplier;
+use query_module_resolver::ResolverError;
+use std::{
+    collections::{BTreeSet, HashMap},
+    future::Future,
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{sync::mpsc::Receiver, time::sleep};
+use tracing::{error, info, info_span, warn, Instrument};
+
+/// a failed submit is retried with the interval doubled each time,
+/// then left unsubmitted in the store, and resubmitted at next start.
+const SUBMIT_RETRIES: usize = 5;
+const SUBMIT_RETRY_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Outputs of queries sharing an aggregated proof, waiting for the rest of the batch.
+struct PendingBatch {
+    size: u32,
+    /// instances whose query is in, duplicates aside.
+    covered: BTreeSet<u32>,
+    /// query, its result, and index of its instances.
+    replies: Vec<(UserQuery, UserQueryProvingResult, u32)>,
+}
 
 /// Responder read proof from store or from message bus, and send it to chain.
 pub struct ProofResponder {
     db: Arc<AggerStore>,
+    /// results are only stored locally without replier, e.g. when replaying.
+    replier: Option<AggerReplier>,
 }
 
 impl ProofResponder {
-    pub fn new(db: Arc<AggerStore>) -> Self {
-        Self { db }
+    pub fn new(db: Arc<AggerStore>, replier: Option<AggerReplier>) -> Self {
+        Self { db, replier }
     }
 
-    pub async fn start(self, mut receiver: Receiver<(UserQuery, Result<Vec<u8>>)>) -> Result<()> {
+    pub async fn start(
+        self,
+        mut receiver: Receiver<(UserQuery, Result<ProveOutput>)>,
+    ) -> Result<()> {
+        let mut batches: HashMap<Vec<u8>, PendingBatch> = HashMap::new();
+        self.resubmit(&mut batches).await?;
         while let Some((query, output)) = receiver.recv().await {
-            println!("prove result: {:?}", output);
-            self.db
-                .put::<UserQueryProofSchema>(&query.sequence_number.into(), &output.into())?;
-            // TODO: submit proof
+            let span = query.span();
+            self.respond(query, output, &mut batches)
+                .instrument(span)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// submit results stored but not submitted before, e.g. when the chain was unreachable.
+    async fn resubmit(&self, batches: &mut HashMap<Vec<u8>, PendingBatch>) -> Result<()> {
+        if self.replier.is_none() {
+            return Ok(());
+        }
+        let results = self.db.unsubmitted_results()?;
+        if !results.is_empty() {
+            info!("resubmit {} stored results", results.len());
+        }
+        for (query, value) in results {
+            let span = query.span();
+            self.submit(query, value, batches).instrument(span).await?;
+        }
+        // the rest of these batches was lost with the last run, it never arrives.
+        for (id, pending) in batches.drain() {
+            warn!(
+                "batch {} misses {} of {} queries, it cannot be resubmitted",
+                hex::encode(id),
+                pending.size as usize - pending.covered.len(),
+                pending.size
+            );
+        }
+        Ok(())
+    }
+
+    async fn respond(
+        &self,
+        query: UserQuery,
+        output: Result<ProveOutput>,
+        batches: &mut HashMap<Vec<u8>, PendingBatch>,
+    ) -> Result<()> {
+        let value = match output {
+            Ok(output) => {
+                info!(
+                    "query proved, return values: {}, events: {}",
+                    output.return_values.len(),
+                    output.events.len()
+                );
+                record_query_outcome(&query.query, QueryOutcome::Success);
+                let value = UserQueryProvingResult::success(&output.result(), output.proof);
+                match output.batch {
+                    Some(batch) => value.with_batch(StoredBatch {
+                        id: batch.id,
+                        size: batch.size,
+                        instance_index: batch.instance_index,
+                        duplicate: batch.duplicate,
+                    }),
+                    None => value,
+                }
+            },
+            Err(e) => {
+                warn!("query failed. {:?}", e);
+                let outcome = match e.downcast_ref::<ResolverError>() {
+                    Some(_) => QueryOutcome::Unresolved,
+                    None => QueryOutcome::Failure,
+                };
+                record_query_outcome(&query.query, outcome);
+                UserQueryProvingResult::failure(&e)
+            },
+        };
+        self.db
+            .put::<UserQueryProofSchema>(&query.sequence_number.into(), &value)?;
+        self.submit(query, value, batches).await
+    }
+
+    /// reply the query, or hold it until the rest of its batch is in.
+    async fn submit(
+        &self,
+        query: UserQuery,
+        value: UserQueryProvingResult,
+        batches: &mut HashMap<Vec<u8>, PendingBatch>,
+    ) -> Result<()> {
+        let Some(batch) = value.batch().cloned() else {
+            return self.reply(query, value).await;
+        };
+        // the aggregated proof is only verifiable with all instances, reply them together.
+        let pending = batches
+            .entry(batch.id.clone())
+            .or_insert_with(|| PendingBatch {
+                size: batch.size,
+                covered: BTreeSet::new(),
+                replies: vec![],
+            });
+        if !batch.duplicate {
+            pending.covered.insert(batch.instance_index);
+        }
+        pending.replies.push((query, value, batch.instance_index));
+        if pending.covered.len() == pending.size as usize {
+            let pending = batches.remove(&batch.id).expect("batch is pending");
+            self.reply_batch(pending.replies).await?;
+        }
+        Ok(())
+    }
+
+    async fn reply(&self, query: UserQuery, mut value: UserQueryProvingResult) -> Result<()> {
+        let Some(replier) = &self.replier else {
+            return Ok(());
+        };
+        let timer = QueryStage::Submit.start_timer();
+        let replied = retry_submit(|| {
+            replier.reply(
+                query.user,
+                query.id,
+                value.is_success(),
+                value.result(),
+                value.proof(),
+            )
+        })
+        .await;
+        timer.observe_duration();
+        match replied {
+            Ok(()) => {
+                value.set_submitted();
+                self.db
+                    .put::<UserQueryProofSchema>(&query.sequence_number.into(), &value)?;
+            },
+            Err(e) => {
+                error!("reply query error. {:?}", e);
+            },
         }
         Ok(())
     }
+
+    /// reply successful queries of a batch in one transaction.
+    async fn reply_batch(
+        &self,
+        replies: Vec<(UserQuery, UserQueryProvingResult, u32)>,
+    ) -> Result<()> {
+        let Some(replier) = &self.replier else {
+            return Ok(());
+        };
+        let mut batch = BatchReply::default();
+        for (query, value, instance_index) in &replies {
+            batch.push(query, value.result().to_vec(), *instance_index);
+            batch.proof = value.proof().to_vec();
+        }
+        let sequence_numbers: Vec<_> = replies.iter().map(|(q, ..)| q.sequence_number).collect();
+        // the batch is not part of any single query.
+        let span = info_span!(parent: None, "batch", sequence_numbers = ?sequence_numbers);
+        let timer = QueryStage::Submit.start_timer();
+        let replied = retry_submit(|| replier.reply_batch(&batch))
+            .instrument(span.clone())
+            .await;
+        timer.observe_duration();
+        let _span = span.enter();
+        match replied {
+            Ok(()) => {
+                info!("queries replied in a batch");
+                for (query, mut value, _) in replies {
+                    value.set_submitted();
+                    self.db
+                        .put::<UserQueryProofSchema>(&query.sequence_number.into(), &value)?;
+                }
+            },
+            Err(e) => {
+                error!("reply batch error. {:?}", e);
+            },
+        }
+        Ok(())
+    }
+}
+
+/// run `submit` until it succeeds, or fails `SUBMIT_RETRIES` more times.
+async fn retry_submit<F, Fut>(submit: F) -> Result<()>
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = Result<()>>,
+{
+    let mut interval = SUBMIT_RETRY_INTERVAL;
+    let mut retries = 0;
+    loop {
+        match submit().await {
+            Err(e) if retries < SUBMIT_RETRIES => {
+                retries += 1;
+                warn!(
+                    "submit error, retry {}/{} in {:?}. {:?}",
+                    retries, SUBMIT_RETRIES, interval, e
+                );
+                sleep(interval).await;
+                interval *= 2;
+            },
+            result => return result,
+        }
+    }
 }
@@ -0,0 +1,37 @@
+use agger_prove_dispatcher::{InputHash, ProvedInput, ProvedInputs};
+use agger_storage::{AggerStore, ProvedInputSchema, ProvedInputValue};
+use anyhow::Result;
+use std::sync::Arc;
+
+/// Proved inputs persisted in the store, so that they're reused after restart.
+pub struct StoredProvedInputs {
+    db: Arc<AggerStore>,
+}
+
+impl StoredProvedInputs {
+    pub fn new(db: Arc<AggerStore>) -> Self {
+        Self { db }
+    }
+}
+
+impl ProvedInputs for StoredProvedInputs {
+    fn get(&self, input: &InputHash) -> Result<Option<ProvedInput>> {
+        let value = self.db.get::<ProvedInputSchema>(&input.clone().into())?;
+        Ok(value.map(|v| ProvedInput {
+            return_values: v.return_values,
+            events: v.events,
+            read_set_hash: v.read_set_hash,
+            proof: v.proof,
+        }))
+    }
+
+    fn put(&self, input: &InputHash, proved: &ProvedInput) -> Result<()> {
+        self.db
+            .put::<ProvedInputSchema>(&input.clone().into(), &ProvedInputValue {
+                return_values: proved.return_values.clone(),
+                events: proved.events.clone(),
+                read_set_hash: proved.read_set_hash.clone(),
+                proof: proved.proof.clone(),
+            })
+    }
+}
@@ -0,0 +1,220 @@
+use crate::{
+    module_prewarmer::ModulePrewarmer, proof_responder::ProofResponder,
+    proved_inputs::StoredProvedInputs,
+};
+use agger_chain_source::{ModuleSource, QuerySource, StateSource};
+use agger_contract_types::UserQuery;
+use agger_metrics::QueryStage;
+use agger_prove_dispatcher::{record_read_set, ProveTask, QueryDeduplicator};
+use agger_prover_worker::{Coordinator, ProverArgs};
+use agger_storage::{AggerStore, UserQueryKey, UserQuerySchema, UserQueryValue};
+use aptos_events::AggerReplier;
+use futures_util::{
+    future::{self, BoxFuture},
+    pin_mut, FutureExt, StreamExt, TryStreamExt,
+};
+use query_module_resolver::{AggerModuleResolver, ResolverError};
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+use tokio::{select, sync::mpsc, time::sleep};
+use tracing::{error, warn, Instrument};
+
+/// transport errors are retried until they go away, the interval is doubled on each retry.
+/// they say nothing about the query, so it is not replied with them.
+const MIN_RESOLVE_RETRY_INTERVAL: Duration = Duration::from_secs(2);
+const MAX_RESOLVE_RETRY_INTERVAL: Duration = Duration::from_secs(60);
+
+/// where queries are proved.
+pub enum Proving {
+    Local(ProverArgs),
+    /// by prover workers connected to the coordinator listening on the address.
+    Remote(SocketAddr),
+}
+
+/// resolve, prove and reply queries of `query_source` until it ends.
+/// Results are only stored in `store` without `replier`.
+pub async fn run_server(
+    query_source: Box<dyn QuerySource>,
+    module_source: Arc<dyn ModuleSource>,
+    state_source: Arc<dyn StateSource>,
+    replier: Option<AggerReplier>,
+    proving: Proving,
+    store: Arc<AggerStore>,
+) -> anyhow::Result<()> {
+    let proof_responder = ProofResponder::new(store.clone(), replier);
+
+    let (task_sender, task_receiver) = mpsc::channel(32);
+    let (output_sender, output_receiver) = mpsc::channel(32);
+
+    // queries which cannot be resolved are replied with failure directly.
+    let failure_sender = output_sender.clone();
+    // identical queries are deduped before they reach provers.
+    let (unique_task_sender, unique_task_receiver) = mpsc::channel(32);
+    let (proved_sender, proved_receiver) = mpsc::channel(32);
+    let deduplicator = QueryDeduplicator::new(Arc::new(StoredProvedInputs::new(store.clone())));
+    let deduplicator = deduplicator.run(
+        task_receiver,
+        unique_task_sender,
+        proved_receiver,
+        output_sender,
+    );
+    let (provers, prover): (BoxFuture<'static, ()>, _) = match proving {
+        Proving::Local(args) => {
+            let prover = args.prover()?;
+            let dispatcher = args.dispatcher(prover.clone(), unique_task_receiver, proved_sender);
+            (dispatcher.run().boxed(), Some(prover))
+        },
+        Proving::Remote(addr) => {
+            let coordinator = Coordinator::bind(addr).await?;
+            (
+                coordinator.run(unique_task_receiver, proved_sender).boxed(),
+                None,
+            )
+        },
+    };
+    let provers = future::join(deduplicator, provers);
+
+    let query_function_resolver = AggerModuleResolver::new(module_source);
+
+    // prewarm modules registered since the last run,
+    // the ones before are fetched on their first query, proving keys are persisted if configured.
+    let registration_from = store
+        .last_prewarmed_registration_number()?
+        .map(|x| x + 1)
+        .unwrap_or(0);
+    let module_registrations = query_source.module_registration_stream(registration_from);
+    let module_prewarmer =
+        ModulePrewarmer::new(store.clone(), query_function_resolver.clone(), prover);
+    tokio::spawn(module_prewarmer.start(module_registrations));
+
+    //skip proved event
+    let query_event_from = store
+        .last_proved_event_number()?
+        .map(|x| x + 1)
+        .unwrap_or(0);
+    let new_query_event_stream = query_source
+        .query_stream(query_event_from)
+        .and_then(|s| {
+            let resolver = query_function_resolver.clone();
+            let state_source = state_source.clone();
+            let span = s.span();
+            async move { Ok(resolve_query(resolver, state_source, s).await) }.instrument(span)
+        })
+        .fuse();
+    pin_mut!(new_query_event_stream);
+
+    let mut dispatch_task_handle = tokio::spawn(provers);
+    let mut output_handle = tokio::spawn(proof_responder.start(output_receiver));
+    let (mut output_task_done, mut dispatch_task_done) = (false, false);
+    loop {
+        select! {
+            _output_task_result = &mut output_handle, if !output_task_done => {
+                // when output handle is gone, then output receiver is gone.
+                // then dispatcher will go down.
+                output_task_done = true;
+            }
+            _dispatch_task_result = &mut dispatch_task_handle, if !dispatch_task_done => {
+                // when dispatcher is gone, then task_sender cannot send any task,
+                // it will go down automatically.
+                dispatch_task_done = true;
+            }
+            Some(s) = new_query_event_stream.next() => {
+                match s {
+                    Ok(Ok(task)) => {
+                        store.put::<UserQuerySchema>(
+                            &UserQueryKey::from(task.query.sequence_number),
+                            &UserQueryValue::from(task.query.clone()),
+                        )?;
+
+                        if let Err(_err)  = task_sender.send(task).await {
+                            error!("prover dispatcher is down");
+                            break;
+                        }
+
+                    }
+                    Ok(Err((query, e))) => {
+                        query.span().in_scope(|| warn!("resolve query error. {}", e));
+                        store.put::<UserQuerySchema>(
+                            &UserQueryKey::from(query.sequence_number),
+                            &UserQueryValue::from(query.clone()),
+                        )?;
+                        if let Err(_err) = failure_sender.send((query, Err(e.into()))).await {
+                            error!("proof responder is down");
+                            break;
+                        }
+                    }
+                    Err(e) => {
+                        error!("get query error. {:?}", e);
+                    }
+                }
+            }
+            else => {
+                // no query events anymore
+                break;
+            }
+        }
+    }
+    // let queued queries be proved and responded before stopping.
+    drop(task_sender);
+    drop(failure_sender);
+    if !dispatch_task_done {
+        let _ = dispatch_task_handle.await;
+    }
+    if !output_task_done {
+        let _ = output_handle.await;
+    }
+    Ok(())
+}
+
+/// resolve modules, zk parameters and read set of the query, retrying on transport errors.
+/// Only errors caused by the query itself are returned, to be replied as its failure.
+async fn resolve_query(
+    resolver: AggerModuleResolver,
+    state_source: Arc<dyn StateSource>,
+    s: UserQuery,
+) -> Result<ProveTask, (UserQuery, ResolverError)> {
+    // the query is only readable at a later version if its own is pruned,
+    // so are its modules and state.
+    let version = s.state_version();
+    if let Some(latest) = s.read_at_latest {
+        warn!("resolve query at latest version {}", latest);
+    }
+    let mut retries = 0;
+    let mut interval = MIN_RESOLVE_RETRY_INTERVAL;
+    loop {
+        let timer = QueryStage::Resolve.start_timer();
+        let result = match resolver.clone().resolve_query(&s.query, version).await {
+            Ok((modules, verification_parameters)) => record_read_set(
+                s.clone(),
+                modules.clone(),
+                &verification_parameters,
+                state_source.clone(),
+                version,
+            )
+            .await
+            .map(|read_set| (modules, verification_parameters, read_set))
+            .map_err(ResolverError::from),
+            Err(e) => Err(e),
+        };
+        timer.observe_duration();
+        match result {
+            Ok((modules, verification_parameters, read_set)) => {
+                return Ok(ProveTask {
+                    query: s,
+                    modules,
+                    verification_parameters,
+                    read_set,
+                });
+            },
+            Err(e) if e.is_transient() => {
+                retries += 1;
+                warn!(
+                    "resolve query error, retry {} in {:?}. {}",
+                    retries, interval, e
+                );
+                sleep(interval).await;
+                interval = (interval * 2).min(MAX_RESOLVE_RETRY_INTERVAL);
+            },
+            Err(e) => return Err((s, e)),
+        }
+    }
+}
@@ -0,0 +1,7 @@
+module 0xcafe::counter {
+    struct Counter has key { value: u64 }
+
+    public entry fun get(owner: address) acquires Counter {
+        let _ = borrow_global<Counter>(owner).value;
+    }
+}
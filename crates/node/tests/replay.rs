@@ -0,0 +1,135 @@
+use agger_chain_source::replay::{
+    RegisteredFunction, RegisteredModule, ReplayFixture, ReplaySource,
+};
+use agger_contract_types::{
+    read_set_hash, ModuleId, QueryFailure, QueryResult, ReadSet, ResourceRead,
+    VerificationParameters,
+};
+use agger_node::{
+    open_db,
+    server::{run_server, Proving},
+};
+use agger_prover_worker::{ProverArgs, ProverBackend};
+use agger_srs::SrsParam;
+use agger_storage::{AggerStore, UserQueryProofSchema, UserQueryProvingResult};
+use std::{path::PathBuf, sync::Arc, time::Duration};
+use test_helpers::{compile_module_file, serialize};
+use tokio::time::{sleep, timeout};
+use zkmove_vm_circuit::witness::CircuitConfig;
+
+const TEST_TIMEOUT: Duration = Duration::from_secs(60);
+
+fn fixture_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("fixtures")
+        .join(name)
+}
+
+fn verification_parameters() -> VerificationParameters {
+    VerificationParameters {
+        config: bcs::to_bytes(&CircuitConfig::default()).unwrap(),
+        param: SrsParam {
+            k: 10,
+            srs_hash: vec![],
+        }
+        .encode(),
+        vk: vec![],
+        instance_layout: vec![],
+    }
+}
+
+fn prover_args() -> ProverArgs {
+    ProverArgs {
+        pk_cache_size: 1,
+        pk_cache_dir: None,
+        srs: None,
+        prover_threads: 1,
+        witness_queue_size: 1,
+        memory_budget_mib: None,
+        prover: ProverBackend::ExecuteOnly,
+        batch_window_ms: 0,
+        max_batch_size: 1,
+    }
+}
+
+fn result(store: &AggerStore, sequence_number: u64) -> UserQueryProvingResult {
+    store
+        .get::<UserQueryProofSchema>(&sequence_number.into())
+        .unwrap()
+        .unwrap_or_else(|| panic!("query {} is not replied", sequence_number))
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_replay_fixture() {
+    let mut fixture = ReplayFixture::load(fixture_path("replay.json")).unwrap();
+    let counter = ModuleId {
+        addr: fixture.module_registrations[0].module_id.addr.clone(),
+        name: "counter".to_string(),
+    };
+    // modules are compiled from source, rather than recorded in the fixture as opaque bytecode.
+    fixture.modules.push(RegisteredModule {
+        module_id: counter.clone(),
+        code: serialize(&compile_module_file(&fixture_path("counter.move"))),
+    });
+    fixture.functions.push(RegisteredFunction {
+        module_id: counter,
+        function_index: 0,
+        verification_parameters: verification_parameters(),
+    });
+    let counter_resource = fixture.resources[0].clone();
+
+    let path = std::env::temp_dir().join(format!("agger-replay-{}", std::process::id()));
+    let store = Arc::new(AggerStore::new(open_db(&path).unwrap()));
+    let source = ReplaySource::new(fixture);
+    timeout(
+        TEST_TIMEOUT,
+        run_server(
+            Box::new(source.clone()),
+            Arc::new(source.clone()),
+            Arc::new(source),
+            None,
+            Proving::Local(prover_args()),
+            store.clone(),
+        ),
+    )
+    .await
+    .unwrap()
+    .unwrap();
+
+    // the query is executed on the recorded resource.
+    let replied = result(&store, 0);
+    assert!(replied.is_success());
+    let query_result: QueryResult = bcs::from_bytes(replied.result()).unwrap();
+    let read_set = ReadSet {
+        version: 5,
+        resources: vec![ResourceRead {
+            address: counter_resource.address,
+            struct_tag: counter_resource.struct_tag,
+            value: counter_resource.value,
+        }],
+    };
+    assert_eq!(query_result.read_set_hash, read_set_hash(&read_set));
+    assert_eq!(query_result.state_version, 5);
+
+    // queries of unregistered modules are replied with failure.
+    let replied = result(&store, 1);
+    assert!(!replied.is_success());
+    match bcs::from_bytes(replied.result()).unwrap() {
+        QueryFailure::Message(m) => assert!(m.contains("not registered"), "{}", m),
+        f => panic!("unexpected failure {:?}", f),
+    }
+    assert_eq!(store.last_proved_event_number().unwrap(), Some(1));
+
+    // the registration is prewarmed in the background.
+    timeout(TEST_TIMEOUT, async {
+        while store.last_prewarmed_registration_number().unwrap() != Some(0) {
+            sleep(Duration::from_millis(100)).await;
+        }
+    })
+    .await
+    .unwrap();
+
+    drop(store);
+    std::fs::remove_dir_all(&path).unwrap();
+}
@@ -0,0 +1,120 @@
+use crate::WitnessedTask;
+use agger_metrics::QUEUE_DEPTH;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, time::Duration};
+use tokio::{
+    select,
+    sync::mpsc::{Receiver, Sender},
+    time::{sleep_until, Instant},
+};
+use tracing::info;
+
+/// How witnessed queries of the same entry function are batched into one aggregated proof.
+/// Queries of different functions are proved apart, a proof is made under one vk,
+/// and aggregating proofs of different vks needs recursive accumulation, which isn't supported.
+/// todo: aggregate across functions, which batching was asked for too, once agger depends on
+/// a recursive verifier such as snark-verifier.
+#[derive(Clone, Copy, Debug)]
+pub struct BatchConfig {
+    /// max time the first query of a batch waits for others.
+    pub window: Duration,
+    /// a batch is proved as soon as it has this many queries.
+    pub max_size: usize,
+}
+
+/// Aggregated proof a query is proved by, along with other queries of the same entry function.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ProofBatch {
+    /// unique id of the batch, shared by outputs of all its queries.
+    pub id: Vec<u8>,
+    /// number of instances in the proof.
+    pub size: u32,
+    /// index of the instances of the query in the proof.
+    pub instance_index: u32,
+    /// whether the query reuses the instances of an identical query in the batch.
+    /// batches are complete once outputs of all instances which are not duplicates arrive.
+    pub duplicate: bool,
+}
+
+/// batches of queries waiting for more, keyed by vk of their entry function.
+struct Pending {
+    batches: HashMap<Vec<u8>, (Instant, Vec<WitnessedTask>)>,
+}
+
+impl Pending {
+    fn next_deadline(&self) -> Option<Instant> {
+        self.batches.values().map(|(deadline, _)| *deadline).min()
+    }
+
+    fn take_expired(&mut self, now: Instant) -> Vec<Vec<WitnessedTask>> {
+        let expired: Vec<_> = self
+            .batches
+            .iter()
+            .filter(|(_, (deadline, _))| *deadline <= now)
+            .map(|(vk, _)| vk.clone())
+            .collect();
+        expired
+            .into_iter()
+            .filter_map(|vk| self.batches.remove(&vk))
+            .map(|(_, tasks)| tasks)
+            .collect()
+    }
+}
+
+/// group witnessed tasks into batches of the same entry function,
+/// each task is a batch of its own if `config` is not set.
+pub(crate) async fn batch_tasks(
+    mut tasks: Receiver<WitnessedTask>,
+    batches: Sender<Vec<WitnessedTask>>,
+    config: Option<BatchConfig>,
+) {
+    let Some(config) = config else {
+        while let Some(task) = tasks.recv().await {
+            QUEUE_DEPTH.with_label_values(&["witnessed"]).dec();
+            if batches.send(vec![task]).await.is_err() {
+                return;
+            }
+        }
+        return;
+    };
+    let mut pending = Pending {
+        batches: HashMap::new(),
+    };
+    loop {
+        let deadline = pending.next_deadline();
+        let task = select! {
+            task = tasks.recv() => task,
+            _ = sleep_until(deadline.unwrap_or_else(Instant::now)), if deadline.is_some() => {
+                for batch in pending.take_expired(Instant::now()) {
+                    if batches.send(batch).await.is_err() {
+                        return;
+                    }
+                }
+                continue;
+            }
+        };
+        let Some(task) = task else {
+            break;
+        };
+        QUEUE_DEPTH.with_label_values(&["witnessed"]).dec();
+        let vk = task.verification_parameters.vk.clone();
+        let (_, batch) = pending
+            .batches
+            .entry(vk.clone())
+            .or_insert_with(|| (Instant::now() + config.window, vec![]));
+        batch.push(task);
+        if batch.len() >= config.max_size.max(1) {
+            let (_, batch) = pending.batches.remove(&vk).expect("batch is pending");
+            if batches.send(batch).await.is_err() {
+                return;
+            }
+        }
+    }
+    // tasks are closed, prove what's left without waiting.
+    for (_, batch) in pending.batches.into_values() {
+        if batches.send(batch).await.is_err() {
+            return;
+        }
+    }
+    info!("batcher is closed");
+}
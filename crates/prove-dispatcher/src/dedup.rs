@@ -0,0 +1,244 @@
+use crate::{ProofBatch, ProveOutput, ProveTask, QueryOutput};
+use agger_contract_types::{query_binding_hash, ExecutionFailure, QueryEvent, UserQuery};
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use sha3::{Digest, Sha3_256};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+use tokio::sync::mpsc::{Receiver, Sender};
+use tracing::{info, warn};
+
+/// hash of everything a proof depends on, see `proving_input_hash`.
+pub type InputHash = Vec<u8>;
+
+/// Results and proof of proving inputs, which don't depend on who sent the query.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ProvedInput {
+    pub return_values: Vec<Vec<u8>>,
+    pub events: Vec<QueryEvent>,
+    pub read_set_hash: Vec<u8>,
+    pub proof: Vec<u8>,
+}
+
+impl ProvedInput {
+    /// output replying `query`, which is bound to the user and id of it.
+    /// the query reads the same resources, at the state version of its own.
+    fn output_for(&self, query: &UserQuery) -> ProveOutput {
+        let state_version = query.state_version();
+        ProveOutput {
+            return_values: self.return_values.clone(),
+            events: self.events.clone(),
+            state_version,
+            read_set_hash: self.read_set_hash.clone(),
+            proof: self.proof.clone(),
+            binding_hash: query_binding_hash(
+                query,
+                &self.return_values,
+                &self.events,
+                state_version,
+                &self.proof,
+            ),
+            batch: None,
+        }
+    }
+}
+
+/// Store of proved inputs, so that identical queries reuse them instead of proving again.
+pub trait ProvedInputs: Send + Sync {
+    fn get(&self, input: &InputHash) -> Result<Option<ProvedInput>>;
+    fn put(&self, input: &InputHash, proved: &ProvedInput) -> Result<()>;
+}
+
+/// sha3-256 of the modules, resources read, verification parameters, entry function,
+/// args and ty_args of the task.
+/// Execution only reads the modules and resources, so tasks of the same hash have the same
+/// results and proof.
+/// The version of the read set is left out, queries at different versions reading the same
+/// resources are identical.
+/// Args are hashed as sent rather than parsed, as they are committed to by the proof as sent.
+pub fn proving_input_hash(task: &ProveTask) -> InputHash {
+    let query = &task.query.query;
+    let data = bcs::to_bytes(&(
+        &task.modules,
+        &task.read_set.resources,
+        &task.verification_parameters,
+        &query.module_address,
+        &query.module_name,
+        &query.function_name,
+        &query.args,
+        &query.ty_args,
+    ))
+    .expect("proving inputs are serializable");
+    Sha3_256::digest(data).to_vec()
+}
+
+/// user and id of a query.
+type QueryKey = (Vec<u8>, u64);
+
+fn query_key(query: &UserQuery) -> QueryKey {
+    (query.user.to_vec(), query.id)
+}
+
+#[derive(Default)]
+struct InFlight {
+    /// input of each query being proved.
+    inputs: HashMap<QueryKey, InputHash>,
+    /// queries waiting for the one of the same input being proved.
+    duplicates: HashMap<InputHash, Vec<UserQuery>>,
+}
+
+/// Deduplicator sits in front of the dispatcher.
+/// Only the first of identical queries in flight is proved, the others are replied with its output.
+/// Inputs proved before are replied from the store without proving.
+/// Each query is still replied separately, with its own binding hash.
+pub struct QueryDeduplicator {
+    proved: Arc<dyn ProvedInputs>,
+    in_flight: Arc<Mutex<InFlight>>,
+}
+
+impl QueryDeduplicator {
+    pub fn new(proved: Arc<dyn ProvedInputs>) -> Self {
+        Self {
+            proved,
+            in_flight: Default::default(),
+        }
+    }
+
+    /// dedup `tasks` into `task_sender` of the dispatcher,
+    /// and fan outputs of the dispatcher from `outputs` out to `output_sender`.
+    pub async fn run(
+        self,
+        tasks: Receiver<ProveTask>,
+        task_sender: Sender<ProveTask>,
+        outputs: Receiver<QueryOutput>,
+        output_sender: Sender<QueryOutput>,
+    ) {
+        // tasks and outputs are handled apart,
+        // so that a full task queue never blocks the outputs draining it.
+        tokio::join!(
+            self.dedup_tasks(tasks, task_sender, output_sender.clone()),
+            self.fan_out_outputs(outputs, output_sender),
+        );
+        info!("query deduplicator is closed");
+    }
+
+    async fn dedup_tasks(
+        &self,
+        mut tasks: Receiver<ProveTask>,
+        task_sender: Sender<ProveTask>,
+        output_sender: Sender<QueryOutput>,
+    ) {
+        while let Some(task) = tasks.recv().await {
+            let input = proving_input_hash(&task);
+            let span = task.query.span();
+            {
+                let mut in_flight = self.in_flight.lock().unwrap();
+                if let Some(duplicates) = in_flight.duplicates.get_mut(&input) {
+                    info!(parent: &span, "query is identical to one being proved");
+                    duplicates.push(task.query);
+                    continue;
+                }
+            }
+            match self.proved.get(&input) {
+                Ok(Some(proved)) => {
+                    info!(parent: &span, "query is identical to one proved before");
+                    let output = proved.output_for(&task.query);
+                    if output_sender.send((task.query, Ok(output))).await.is_err() {
+                        break;
+                    }
+                    continue;
+                },
+                Ok(None) => {},
+                Err(e) => {
+                    warn!(parent: &span, "get proved input error, prove again. {:?}", e);
+                },
+            }
+            {
+                let mut in_flight = self.in_flight.lock().unwrap();
+                in_flight
+                    .inputs
+                    .insert(query_key(&task.query), input.clone());
+                in_flight.duplicates.insert(input, vec![]);
+            }
+            if task_sender.send(task).await.is_err() {
+                break;
+            }
+        }
+    }
+
+    async fn fan_out_outputs(
+        &self,
+        mut outputs: Receiver<QueryOutput>,
+        output_sender: Sender<QueryOutput>,
+    ) {
+        while let Some((query, result)) = outputs.recv().await {
+            let input = self
+                .in_flight
+                .lock()
+                .unwrap()
+                .inputs
+                .get(&query_key(&query))
+                .cloned();
+            let proved = result.as_ref().ok().map(|output| ProvedInput {
+                return_values: output.return_values.clone(),
+                events: output.events.clone(),
+                read_set_hash: output.read_set_hash.clone(),
+                proof: output.proof.clone(),
+            });
+            let batch = result.as_ref().ok().and_then(|output| output.batch.clone());
+            // stored before leaving in flight, so that duplicates arriving meanwhile reuse it.
+            // empty proofs of dev provers must not be reused by real ones,
+            // nor aggregated proofs, which are only valid with the other queries of the batch.
+            if let (Some(input), Some(proved)) = (&input, &proved) {
+                if !proved.proof.is_empty() && batch.is_none() {
+                    if let Err(e) = self.proved.put(input, proved) {
+                        warn!(parent: &query.span(), "store proved input error. {:?}", e);
+                    }
+                }
+            }
+            let duplicates = input
+                .and_then(|input| {
+                    let mut in_flight = self.in_flight.lock().unwrap();
+                    in_flight.inputs.remove(&query_key(&query));
+                    in_flight.duplicates.remove(&input)
+                })
+                .unwrap_or_default();
+            let replies = duplicates.into_iter().map(|duplicate| {
+                let result = match (&proved, &result) {
+                    (Some(proved), _) => Ok(ProveOutput {
+                        batch: batch.clone().map(|batch| ProofBatch {
+                            duplicate: true,
+                            ..batch
+                        }),
+                        ..proved.output_for(&duplicate)
+                    }),
+                    (None, Err(e)) => Err(duplicate_error(e)),
+                    (None, Ok(_)) => unreachable!("proved input is taken from ok result"),
+                };
+                (duplicate, result)
+            });
+            let replies: Vec<_> = replies.collect();
+            // duplicates of a batch go first, so that they're in before the batch is complete.
+            let primary = std::iter::once((query, result));
+            let replies: Vec<_> = match batch {
+                Some(_) => replies.into_iter().chain(primary).collect(),
+                None => primary.chain(replies).collect(),
+            };
+            for reply in replies {
+                if output_sender.send(reply).await.is_err() {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// failure of a duplicate query, execution failures are kept so that they're replied the same.
+fn duplicate_error(e: &anyhow::Error) -> anyhow::Error {
+    match e.downcast_ref::<ExecutionFailure>() {
+        Some(f) => f.clone().into(),
+        None => anyhow!("{}", e.root_cause()),
+    }
+}
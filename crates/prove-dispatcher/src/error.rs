@@ -0,0 +1,15 @@
+use crate::limits::CircuitLimit;
+
+/// Errors of proving a query, which are reported distinctly from execution failures.
+#[derive(Debug, thiserror::Error)]
+pub enum ProveError {
+    /// the generated proof doesn't verify against the onchain vk and expected instances.
+    #[error("proof self check failed: {0}")]
+    ProofSelfCheckFailed(String),
+    /// execution of the query needs more rows of the kind than the circuit of its function has.
+    #[error("query exceeds circuit capacity: more than {max} {limit}")]
+    ExceedsCircuitCapacity { limit: CircuitLimit, max: usize },
+    /// the zkmove-vm built in cannot execute queries on chain state, see `vm`.
+    #[error("queries cannot be executed, agger is built without the unreleased-zkmove-vm feature")]
+    UnsupportedVm,
+}
@@ -0,0 +1,213 @@
+use agger_contract_types::VerificationParameters;
+use agger_metrics::QueryStage;
+use agger_srs::{Srs, SrsParam};
+use agger_types::QueryCircuit;
+use agger_verifier::keygen_circuit;
+use anyhow::{ensure, Result};
+use halo2_proofs::{
+    halo2curves::bn256::{Bn256, G1Affine},
+    plonk::{keygen_pk, keygen_vk, ProvingKey},
+    poly::kzg::commitment::ParamsKZG,
+    SerdeFormat,
+};
+use lru::LruCache;
+use sha3::{Digest, Sha3_256};
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Write},
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+use tracing::{info, warn};
+
+/// sha3-256 of the vk registered onchain.
+type VkHash = [u8; 32];
+
+/// slot of a registered function, locked while its keys are being setup,
+/// so that concurrent queries of the same function only setup once.
+type KeySlot = Arc<Mutex<Option<Arc<ProvingKeys>>>>;
+
+/// kzg params and proving key of an entry function.
+pub struct ProvingKeys {
+    pub params: ParamsKZG<Bn256>,
+    pub pk: ProvingKey<G1Affine>,
+    /// verification parameters the keys are checked against.
+    pub verification_parameters: VerificationParameters,
+}
+
+/// LRU cache of kzg params and proving keys of entry functions, indexed by vk hash,
+/// so that they are only setup once for each registered function.
+/// Evicted keys are reloaded from disk instead of setup again, if a cache dir is configured.
+pub struct ProvingKeyCache {
+    keys: Mutex<LruCache<VkHash, KeySlot>>,
+    dir: Option<PathBuf>,
+    srs: Arc<Srs>,
+}
+
+impl ProvingKeyCache {
+    pub fn new(capacity: usize, srs: Arc<Srs>) -> Self {
+        Self {
+            keys: Mutex::new(LruCache::new(
+                NonZeroUsize::new(capacity).unwrap_or(NonZeroUsize::MIN),
+            )),
+            dir: None,
+            srs,
+        }
+    }
+
+    /// persist keys into `dir`, and load them from it on cache misses.
+    pub fn with_dir(mut self, dir: impl AsRef<Path>) -> Result<Self> {
+        std::fs::create_dir_all(dir.as_ref())?;
+        self.dir = Some(dir.as_ref().to_path_buf());
+        Ok(self)
+    }
+
+    /// get the proving keys of `circuit`, setup them if not cached yet.
+    pub fn get_or_setup(
+        &self,
+        circuit: &QueryCircuit,
+        verification_parameters: &VerificationParameters,
+    ) -> Result<Arc<ProvingKeys>> {
+        let vk_hash: VkHash = Sha3_256::digest(&verification_parameters.vk).into();
+        let slot = self
+            .keys
+            .lock()
+            .unwrap()
+            .get_or_insert(vk_hash, Default::default)
+            .clone();
+        // only the slot is locked during setup, other functions are not blocked.
+        let mut slot = slot.lock().unwrap();
+        if let Some(keys) = slot.as_ref() {
+            if &keys.verification_parameters == verification_parameters {
+                return Ok(keys.clone());
+            }
+        }
+
+        let keys = match self.load(&vk_hash, verification_parameters) {
+            Ok(Some(keys)) => keys,
+            Ok(None) => self.setup_and_store(circuit, &vk_hash, verification_parameters)?,
+            Err(e) => {
+                warn!("load proving keys from disk error, setup again. {:?}", e);
+                self.setup_and_store(circuit, &vk_hash, verification_parameters)?
+            },
+        };
+        let keys = Arc::new(keys);
+        *slot = Some(keys.clone());
+        Ok(keys)
+    }
+
+    /// setup proving keys of an entry function from its circuit config,
+    /// so that the first query of the function doesn't pay for it.
+    pub fn precompute(&self, verification_parameters: &VerificationParameters) -> Result<()> {
+        let circuit = keygen_circuit(
+            &verification_parameters.config,
+            &verification_parameters.instance_layout,
+        )?;
+        self.get_or_setup(&circuit, verification_parameters)?;
+        Ok(())
+    }
+
+    fn setup_and_store(
+        &self,
+        circuit: &QueryCircuit,
+        vk_hash: &VkHash,
+        verification_parameters: &VerificationParameters,
+    ) -> Result<ProvingKeys> {
+        let keys = {
+            let _timer = QueryStage::Keygen.start_timer();
+            setup(&self.srs, circuit, verification_parameters)?
+        };
+        if let Err(e) = self.store(vk_hash, &keys) {
+            warn!("store proving keys to disk error. {:?}", e);
+        }
+        Ok(keys)
+    }
+
+    fn paths(&self, vk_hash: &VkHash) -> Option<(PathBuf, PathBuf)> {
+        self.dir.as_ref().map(|dir| {
+            let name = hex::encode(vk_hash);
+            (
+                dir.join(&name).with_extension("params"),
+                dir.join(&name).with_extension("pk"),
+            )
+        })
+    }
+
+    fn load(
+        &self,
+        vk_hash: &VkHash,
+        verification_parameters: &VerificationParameters,
+    ) -> Result<Option<ProvingKeys>> {
+        let Some((params_path, pk_path)) = self.paths(vk_hash) else {
+            return Ok(None);
+        };
+        if !params_path.exists() || !pk_path.exists() {
+            return Ok(None);
+        }
+        let params = ParamsKZG::<Bn256>::read_custom(
+            &mut BufReader::new(File::open(params_path)?),
+            SerdeFormat::RawBytes,
+        )?;
+        let pk = ProvingKey::<G1Affine>::read::<_, QueryCircuit>(
+            &mut BufReader::new(File::open(pk_path)?),
+            SerdeFormat::RawBytes,
+        )?;
+        SrsParam::decode(&verification_parameters.param)?.check(&params)?;
+        check_vk(&pk, verification_parameters)?;
+        info!("proving keys {} loaded from disk", hex::encode(vk_hash));
+        Ok(Some(ProvingKeys {
+            params,
+            pk,
+            verification_parameters: verification_parameters.clone(),
+        }))
+    }
+
+    fn store(&self, vk_hash: &VkHash, keys: &ProvingKeys) -> Result<()> {
+        let Some((params_path, pk_path)) = self.paths(vk_hash) else {
+            return Ok(());
+        };
+        // write to temp files first, so that readers never see partial files.
+        let params_tmp = params_path.with_extension("params.tmp");
+        let mut writer = BufWriter::new(File::create(&params_tmp)?);
+        keys.params
+            .write_custom(&mut writer, SerdeFormat::RawBytes)?;
+        writer.flush()?;
+        let pk_tmp = pk_path.with_extension("pk.tmp");
+        let mut writer = BufWriter::new(File::create(&pk_tmp)?);
+        keys.pk.write(&mut writer, SerdeFormat::RawBytes)?;
+        writer.flush()?;
+        std::fs::rename(params_tmp, params_path)?;
+        std::fs::rename(pk_tmp, pk_path)?;
+        Ok(())
+    }
+}
+
+fn setup(
+    srs: &Srs,
+    circuit: &QueryCircuit,
+    verification_parameters: &VerificationParameters,
+) -> Result<ProvingKeys> {
+    let params = srs.params_for(&verification_parameters.param)?;
+    let vk = keygen_vk(&params, circuit)?;
+    let pk = keygen_pk(&params, vk, circuit)?;
+    check_vk(&pk, verification_parameters)?;
+    Ok(ProvingKeys {
+        params,
+        pk,
+        verification_parameters: verification_parameters.clone(),
+    })
+}
+
+/// check vk of keys is the one registered onchain,
+/// once when they are setup or loaded into cache.
+fn check_vk(
+    pk: &ProvingKey<G1Affine>,
+    verification_parameters: &VerificationParameters,
+) -> Result<()> {
+    ensure!(
+        pk.get_vk().to_bytes(SerdeFormat::Processed) == verification_parameters.vk,
+        "vk equality checking failure"
+    );
+    Ok(())
+}
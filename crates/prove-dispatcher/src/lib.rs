@@ -1,160 +1,457 @@
-use crate::witness::witness;
-use agger_contract_types::UserQuery;
-use anyhow::{ensure, Result};
-use fake_rng::CountingRng;
-use futures_util::{stream::FuturesUnordered, StreamExt};
-use halo2_proofs::{
-    halo2curves::bn256::{Bn256, Fr},
-    poly::kzg::commitment::ParamsKZG,
-    SerdeFormat,
+use crate::{
+    state::{ReadSetResources, SourceResources},
+    witness::{witness, QueryExecution},
 };
-use log::{error, info};
-use threadpool::ThreadPool;
-use tokio::sync::{
-    mpsc::{Receiver, Sender},
-    oneshot,
+use agger_chain_source::{SourceResult, StateSource};
+use agger_contract_types::{
+    query_binding_hash, query_result_hash, read_set_hash, InstanceLayout, QueryEvent, QueryResult,
+    ReadSet, UserQuery, VerificationParameters,
+};
+use agger_metrics::{record_circuit_usage, QueryStage, QUEUE_DEPTH};
+use agger_srs::SrsParam;
+use agger_types::query_instances;
+use anyhow::{anyhow, Result};
+use batch::batch_tasks;
+pub use batch::{BatchConfig, ProofBatch};
+pub use dedup::{proving_input_hash, InputHash, ProvedInput, ProvedInputs, QueryDeduplicator};
+pub use error::ProveError;
+use halo2_proofs::halo2curves::bn256::Fr;
+pub use keys::{ProvingKeyCache, ProvingKeys};
+pub use limits::CircuitLimit;
+use memory::memory_upper_bound_mib;
+pub use memory::MemoryBudget;
+pub use prover::{ExecuteOnlyProver, KzgProver, MockCircuitProver, Prover};
+use serde::{Deserialize, Serialize};
+use sha3::{Digest, Sha3_256};
+use stage::Stage;
+use std::{
+    any::Any,
+    panic::{self, AssertUnwindSafe},
+    sync::Arc,
 };
-use zkmove_vm_circuit::{
-    circuit::VmCircuit, prove_vm_circuit_kzg, setup_vm_circuit, witness::Witness,
+use threadpool::ThreadPool;
+use tokio::{
+    runtime::Handle,
+    sync::mpsc::{self, Receiver, Sender},
 };
+use tracing::{info, info_span};
 
+mod batch;
+mod dedup;
+mod error;
+mod keys;
+mod limits;
+mod memory;
+mod prover;
+mod stage;
+mod state;
+mod verify;
+mod vm;
 mod witness;
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct ProveTask {
     pub query: UserQuery,
     pub modules: Vec<Vec<u8>>,
-    pub config: Vec<u8>,
-    pub vk: Vec<u8>,
-    pub param: Vec<u8>,
+    pub verification_parameters: VerificationParameters,
+    /// onchain resources the query reads, see `record_read_set`.
+    pub read_set: ReadSet,
+}
+
+/// execute `query` on the state of `source` at `version`, and return the resources it reads.
+/// Resources are fetched as the vm reads them, so that addresses computed at runtime are covered.
+/// Provers replay the execution on the returned read set only.
+/// Failures of the execution itself are not returned, the witness stage replays and replies them.
+pub async fn record_read_set(
+    query: UserQuery,
+    modules: Vec<Vec<u8>>,
+    verification_parameters: &VerificationParameters,
+    source: Arc<dyn StateSource>,
+    version: u64,
+) -> SourceResult<ReadSet> {
+    let resources = Arc::new(SourceResources::new(source, version, Handle::current()));
+    let recording = resources.clone();
+    let config = verification_parameters.config.clone();
+    let span = query.span();
+    // the vm reads synchronously, blocking on the source.
+    let _ = tokio::task::spawn_blocking(move || {
+        let _span = span.entered();
+        witness(&query, modules, recording, &config, false)
+    })
+    .await;
+    resources.read_set()
+}
+
+/// Results of a query, and the proof of them.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ProveOutput {
+    /// bcs serialized return values of the entry function.
+    pub return_values: Vec<Vec<u8>>,
+    pub events: Vec<QueryEvent>,
+    /// ledger version of the state the query is executed on.
+    pub state_version: u64,
+    /// hash of the resources the query is executed on.
+    pub read_set_hash: Vec<u8>,
+    pub proof: Vec<u8>,
+    /// hash binding the query, its results and proof together.
+    pub binding_hash: Vec<u8>,
+    /// set if the proof is aggregated with other queries.
+    pub batch: Option<ProofBatch>,
 }
 
-#[derive(Debug)]
+impl ProveOutput {
+    /// result to reply onchain.
+    pub fn result(&self) -> QueryResult {
+        QueryResult {
+            return_values: self.return_values.clone(),
+            events: self.events.clone(),
+            state_version: self.state_version,
+            read_set_hash: self.read_set_hash.clone(),
+            binding_hash: self.binding_hash.clone(),
+        }
+    }
+}
+
+/// whether queries can be executed and return their results, see `ProveError::UnsupportedVm`.
+pub const EXECUTES_QUERIES: bool = cfg!(feature = "unreleased-zkmove-vm");
+
+/// query and its outputs, or why it failed.
+pub type QueryOutput = (UserQuery, Result<ProveOutput>);
+
+/// Query executed and ready to be proved.
+struct WitnessedTask {
+    query: UserQuery,
+    execution: QueryExecution,
+    read_set_hash: Vec<u8>,
+    instances: Vec<Fr>,
+    verification_parameters: VerificationParameters,
+    /// upper bound of memory in MiB to prove it.
+    memory_mib: u32,
+}
+
+/// Dispatches tasks to two pipelined stages.
+/// Queries are executed and witnessed by a wide threadpool, and proved by a narrow one,
+/// as proving is much heavier on memory.
+/// Stages are connected by a bounded queue, so that execution failures are answered
+/// without waiting behind proofs, and witnesses don't pile up in memory.
 pub struct ProvingTaskDispatcher {
     task_receiver: Receiver<ProveTask>,
-    output_sender: Sender<(UserQuery, Result<Vec<u8>>)>,
-    threadpool: ThreadPool,
+    output_sender: Sender<QueryOutput>,
+    witness_threadpool: ThreadPool,
+    prover_threadpool: ThreadPool,
+    /// max number of witnessed tasks waiting to be proved.
+    queue_size: usize,
+    /// proofs are only limited by the prover threadpool if not set.
+    memory_budget: Option<MemoryBudget>,
+    /// each query is proved alone if not set.
+    batch_config: Option<BatchConfig>,
+    prover: Arc<dyn Prover>,
 }
 
 impl ProvingTaskDispatcher {
     pub fn new(
-        threadpool: ThreadPool,
+        witness_threadpool: ThreadPool,
+        prover_threadpool: ThreadPool,
+        queue_size: usize,
+        memory_budget: Option<MemoryBudget>,
         task_receiver: Receiver<ProveTask>,
-        output_sender: Sender<(UserQuery, Result<Vec<u8>>)>,
+        output_sender: Sender<QueryOutput>,
+        prover: Arc<dyn Prover>,
     ) -> Self {
         Self {
             output_sender,
-            threadpool,
+            witness_threadpool,
+            prover_threadpool,
+            queue_size,
+            memory_budget,
+            batch_config: None,
             task_receiver,
+            prover,
         }
     }
 
-    pub async fn run(mut self) {
-        let mut fs = FuturesUnordered::new();
-        loop {
-            if self.threadpool.active_count() == self.threadpool.max_count() {
-                // when ongoing tasks is full, we wait on one task finishing, then go on to receive new tasks.
-                if let Some(received_output) = fs.next().await {
-                    match received_output {
-                        Ok(output) => {
-                            if let Err(_output) = self.output_sender.send(output).await {
-                                break;
-                            }
-                        },
-                        Err(_) => {
-                            error!("task ended without sending result");
-                        },
-                    }
+    /// aggregate proofs of queries of the same entry function witnessed within the batch window.
+    /// Ignored by provers without witness, as nothing is proved.
+    pub fn with_batching(mut self, batch_config: BatchConfig) -> Self {
+        self.batch_config = Some(batch_config);
+        self
+    }
+
+    pub async fn run(self) {
+        let (witnessed_sender, witnessed_receiver) = mpsc::channel(self.queue_size.max(1));
+
+        let requires_witness = self.prover.requires_witness();
+        let witness_stage = Stage::new(
+            "witness",
+            self.witness_threadpool,
+            move |task: ProveTask| {
+                // spans are entered on the threads jobs run on.
+                let _span = task.query.span().entered();
+                catch_panic(task.query.clone(), || witness_task(task, requires_witness))
+            },
+        );
+        let failure_sender = self.output_sender.clone();
+        let witness_stage = witness_stage.run(self.task_receiver, move |output| {
+            let (witnessed_sender, failure_sender) =
+                (witnessed_sender.clone(), failure_sender.clone());
+            async move {
+                match output {
+                    // blocks the witness stage when the queue is full.
+                    Ok(task) => {
+                        QUEUE_DEPTH.with_label_values(&["witnessed"]).inc();
+                        witnessed_sender.send(task).await.is_ok()
+                    },
+                    Err(failure) => failure_sender.send(failure).await.is_ok(),
                 }
             }
-            let task = tokio::select! {
-                received_output = fs.next() => {
-                    match received_output {
-                        Some(Ok(output)) => {
-                            if let Err(_output) = self.output_sender.send(output).await {
-                                // output receiver is gone, stop dispatching
-                                break
-                            }
-                            continue
-                        }
-                        Some(Err(_)) => {
-                            error!("task ended without sending result");
-                            continue
-                        }
-                        None => {
-                            info!("empty task queues, wait for new task");
-                            self.task_receiver.recv().await
-                        }
-                    }
-                }
-                task = self.task_receiver.recv() => {
-                    task
+        });
+
+        // batches are already bounded by the witnessed queue.
+        let (batch_sender, batch_receiver) = mpsc::channel(1);
+        let batch_config = self.batch_config.filter(|_| requires_witness);
+        let batcher = batch_tasks(witnessed_receiver, batch_sender, batch_config);
+
+        let prover = self.prover;
+        let prove_stage = Stage::new(
+            "prove",
+            self.prover_threadpool,
+            move |tasks: Vec<WitnessedTask>| {
+                let queries: Vec<_> = tasks.iter().map(|task| task.query.clone()).collect();
+                let _span = match queries.as_slice() {
+                    [query] => query.span(),
+                    _ => {
+                        let sequence_numbers: Vec<_> =
+                            queries.iter().map(|q| q.sequence_number).collect();
+                        info_span!("batch", sequence_numbers = ?sequence_numbers)
+                    },
                 }
-            };
-            if let Some(task) = task {
-                info!(
-                    "new query task, user: {:#x}, id: {}",
-                    task.query.user, task.query.id
-                );
-                let (tx, rx) = oneshot::channel();
-                fs.push(rx);
-                self.threadpool.execute(|| {
-                    let query = task.query.clone();
-                    let output = run_task(task);
-                    if let Err(_v) = tx.send((query, output)) {
-                        error!("task ended, but output receiver is lost");
+                .entered();
+                panic::catch_unwind(AssertUnwindSafe(|| prove_batch(tasks, prover.as_ref())))
+                    .unwrap_or_else(|e| {
+                        let message = panic_message(&*e);
+                        queries
+                            .into_iter()
+                            .map(|query| (query, Err(anyhow!("prover panicked: {}", message))))
+                            .collect()
+                    })
+            },
+        );
+        let prove_stage = match self.memory_budget {
+            Some(budget) => prove_stage.with_budget(budget, |tasks| {
+                tasks.iter().map(|task| task.memory_mib).sum()
+            }),
+            None => prove_stage,
+        };
+        let output_sender = self.output_sender;
+        let prove_stage = prove_stage.run(batch_receiver, move |outputs| {
+            let output_sender = output_sender.clone();
+            async move {
+                for output in outputs {
+                    if output_sender.send(output).await.is_err() {
+                        return false;
                     }
-                });
-            } else {
-                // all task sender is gone, just stop dispatching.
-                break;
-            }
-        }
-        info!("prove dispatcher is closing...");
-        // before closing, wait any queued or ongoing computations to finish.
-        while let Some(received_output) = fs.next().await {
-            match received_output {
-                Ok(output) => {
-                    if let Err(_v) = self.output_sender.send(output).await {
-                        break;
-                    }
-                },
-                Err(_) => {
-                    error!("task ended without sending result");
-                },
+                }
+                true
             }
-        }
+        });
+
+        // the batcher and prove stage are closed after the witness stage drops the queue sender.
+        tokio::join!(witness_stage, batcher, prove_stage);
         info!("prove dispatcher is closed");
     }
 }
 
-fn run_task(
+/// run `job` of `query`, a panic must not lose the query, reply it as failure instead.
+fn catch_panic<O>(
+    query: UserQuery,
+    job: impl FnOnce() -> Result<O, QueryOutput>,
+) -> Result<O, QueryOutput> {
+    panic::catch_unwind(AssertUnwindSafe(job)).unwrap_or_else(|e| {
+        Err((
+            query,
+            Err(anyhow!("prover panicked: {}", panic_message(&*e))),
+        ))
+    })
+}
+
+fn panic_message(e: &(dyn Any + Send)) -> &str {
+    if let Some(s) = e.downcast_ref::<&str>() {
+        s
+    } else if let Some(s) = e.downcast_ref::<String>() {
+        s
+    } else {
+        "unknown panic"
+    }
+}
+
+/// execute the query and generate its witness, failures are replied directly.
+fn witness_task(
     ProveTask {
         query,
         modules,
-        config,
-        vk,
-        param,
+        verification_parameters,
+        read_set,
     }: ProveTask,
-) -> Result<Vec<u8>> {
-    let witness = witness(query, modules, config)?;
-    prove(witness, param, vk)
+    requires_witness: bool,
+) -> Result<WitnessedTask, QueryOutput> {
+    info!("new query task");
+    let timer = QueryStage::Witness.start_timer();
+    let resources = match ReadSetResources::new(&read_set) {
+        Ok(resources) => Arc::new(resources),
+        Err(e) => return Err((query, Err(e))),
+    };
+    let execution = match witness(
+        &query,
+        modules,
+        resources,
+        &verification_parameters.config,
+        requires_witness,
+    ) {
+        Ok(execution) => execution,
+        Err(e) => return Err((query, Err(e))),
+    };
+    timer.observe_duration();
+    match SrsParam::decode(&verification_parameters.param) {
+        Ok(param) => record_circuit_usage(&query.query, param.k, execution.rows),
+        Err(e) => return Err((query, Err(e.into()))),
+    }
+    // commit to the query and its result in the proof, see `QueryCircuit` on what it proves.
+    let layout = match InstanceLayout::decode(&verification_parameters.instance_layout) {
+        Ok(layout) => layout,
+        Err(e) => return Err((query, Err(e.into()))),
+    };
+    let read_set_hash = read_set_hash(&read_set);
+    let instances = query_instances(
+        &layout,
+        &query.query,
+        execution.function_index,
+        &query_result_hash(&execution.return_values, &execution.events),
+        &read_set_hash,
+    );
+    // nothing is proved without witness.
+    let memory_mib = if requires_witness {
+        match memory_upper_bound_mib(&verification_parameters) {
+            Ok(mib) => mib,
+            Err(e) => return Err((query, Err(e))),
+        }
+    } else {
+        0
+    };
+    Ok(WitnessedTask {
+        query,
+        execution,
+        read_set_hash,
+        instances,
+        verification_parameters,
+        memory_mib,
+    })
 }
 
-fn prove(witness: Witness<Fr>, param: Vec<u8>, onchain_vk: Vec<u8>) -> Result<Vec<u8>> {
-    let circuit = VmCircuit { witness };
-    // let params = ParamsKZG::<Bn256>::read_custom(
-    //     &mut verification_parameters.param.as_slice(),
-    //     SerdeFormat::Processed,
-    // )?;
-    let params = ParamsKZG::<Bn256>::setup(bcs::from_bytes(param.as_slice())?, CountingRng(42));
-    let (vk, pk) = setup_vm_circuit(&circuit, &params)?;
-
-    // check vk is equal to vk stored onchain.
-    ensure!(
-        vk.to_bytes(SerdeFormat::Processed) == onchain_vk,
-        "vk equality checking failure"
-    );
-    let proof = prove_vm_circuit_kzg(circuit, &[], &params, pk)?;
-    Ok(proof)
+fn prove_task(
+    WitnessedTask {
+        query,
+        execution:
+            QueryExecution {
+                witness,
+                return_values,
+                events,
+                ..
+            },
+        read_set_hash,
+        instances,
+        verification_parameters,
+        ..
+    }: WitnessedTask,
+    prover: &dyn Prover,
+) -> QueryOutput {
+    let timer = QueryStage::Prove.start_timer();
+    let proof = prover.prove(witness, &instances, &verification_parameters);
+    timer.observe_duration();
+    let proof = match proof {
+        Ok(proof) => proof,
+        Err(e) => return (query, Err(e)),
+    };
+    let state_version = query.state_version();
+    let binding_hash = query_binding_hash(&query, &return_values, &events, state_version, &proof);
+    let output = ProveOutput {
+        return_values,
+        events,
+        state_version,
+        read_set_hash,
+        proof,
+        binding_hash,
+        batch: None,
+    };
+    (query, Ok(output))
+}
+
+/// prove queries of the same entry function in one aggregated proof.
+/// The batch fails as a whole, as queries cannot be told apart by a failed proof.
+fn prove_batch(mut tasks: Vec<WitnessedTask>, prover: &dyn Prover) -> Vec<QueryOutput> {
+    if tasks.len() == 1 {
+        let task = tasks.pop().expect("batch has one task");
+        return vec![prove_task(task, prover)];
+    }
+    let size = tasks.len();
+    let verification_parameters = tasks[0].verification_parameters.clone();
+    let mut queries = Vec::with_capacity(size);
+    let mut results = Vec::with_capacity(size);
+    let mut circuits = Vec::with_capacity(size);
+    for task in tasks {
+        queries.push(task.query);
+        results.push((
+            task.execution.return_values,
+            task.execution.events,
+            task.read_set_hash,
+        ));
+        circuits.push((task.execution.witness, task.instances));
+    }
+    info!("prove a batch of {} queries", size);
+    let timer = QueryStage::Prove.start_timer();
+    let proof = prover.prove_batch(circuits, &verification_parameters);
+    timer.observe_duration();
+    let proof = match proof {
+        Ok(proof) => proof,
+        Err(e) => {
+            let message = format!("{:#}", e);
+            return queries
+                .into_iter()
+                .map(|query| (query, Err(anyhow!("prove batch error. {}", message))))
+                .collect();
+        },
+    };
+    let id = batch_id(&queries, &proof);
+    queries
+        .into_iter()
+        .zip(results)
+        .enumerate()
+        .map(|(index, (query, (return_values, events, read_set_hash)))| {
+            let state_version = query.state_version();
+            let binding_hash =
+                query_binding_hash(&query, &return_values, &events, state_version, &proof);
+            let output = ProveOutput {
+                return_values,
+                events,
+                state_version,
+                read_set_hash,
+                proof: proof.clone(),
+                binding_hash,
+                batch: Some(ProofBatch {
+                    id: id.clone(),
+                    size: size as u32,
+                    instance_index: index as u32,
+                    duplicate: false,
+                }),
+            };
+            (query, Ok(output))
+        })
+        .collect()
+}
+
+/// sha3-256 of the users and ids of the queries, and the proof.
+/// Queries are unique, so are ids even if dev provers give empty proofs.
+fn batch_id(queries: &[UserQuery], proof: &[u8]) -> Vec<u8> {
+    let keys: Vec<_> = queries.iter().map(|query| (query.user, query.id)).collect();
+    let data = bcs::to_bytes(&(keys, proof)).expect("batch is serializable");
+    Sha3_256::digest(data).to_vec()
 }
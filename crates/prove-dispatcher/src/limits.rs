@@ -0,0 +1,157 @@
+use crate::{vm::TraceUsage, ProveError};
+use std::fmt;
+use zkmove_vm_circuit::witness::CircuitConfig;
+
+/// Limits of the circuit of an entry function, a query exceeding one cannot be proved.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CircuitLimit {
+    Steps,
+    StackOps,
+    LocalsOps,
+    GlobalOps,
+}
+
+impl fmt::Display for CircuitLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            CircuitLimit::Steps => "steps",
+            CircuitLimit::StackOps => "stack ops",
+            CircuitLimit::LocalsOps => "locals ops",
+            CircuitLimit::GlobalOps => "global ops",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Rows the circuit config of an entry function has for each kind of execution record.
+/// Limits absent from the config are not checked.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ExecutionLimits {
+    pub steps: Option<usize>,
+    pub stack_ops: Option<usize>,
+    pub locals_ops: Option<usize>,
+    pub global_ops: Option<usize>,
+}
+
+impl ExecutionLimits {
+    pub fn from_config(config: &CircuitConfig) -> Self {
+        Self {
+            steps: config.max_step_row,
+            stack_ops: config.stack_ops_num,
+            locals_ops: config.locals_ops_num,
+            global_ops: config.global_ops_num,
+        }
+    }
+
+    /// check records of the execution against the rows of the circuit.
+    pub fn check(&self, usage: &TraceUsage) -> Result<(), ProveError> {
+        let used = [
+            (CircuitLimit::Steps, self.steps, usage.steps),
+            (CircuitLimit::StackOps, self.stack_ops, usage.stack_ops),
+            (CircuitLimit::LocalsOps, self.locals_ops, usage.locals_ops),
+            (CircuitLimit::GlobalOps, self.global_ops, usage.global_ops),
+        ];
+        for (limit, max, used) in used {
+            if let Some(max) = max {
+                if used > max {
+                    return Err(ProveError::ExceedsCircuitCapacity { limit, max });
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMITS: ExecutionLimits = ExecutionLimits {
+        steps: Some(10),
+        stack_ops: Some(20),
+        locals_ops: Some(30),
+        global_ops: Some(40),
+    };
+
+    const AT_LIMITS: TraceUsage = TraceUsage {
+        steps: 10,
+        stack_ops: 20,
+        locals_ops: 30,
+        global_ops: 40,
+    };
+
+    fn exceeded(usage: TraceUsage) -> Option<(CircuitLimit, usize)> {
+        match LIMITS.check(&usage) {
+            Ok(()) => None,
+            Err(ProveError::ExceedsCircuitCapacity { limit, max }) => Some((limit, max)),
+            Err(e) => panic!("unexpected error {}", e),
+        }
+    }
+
+    #[test]
+    fn test_usage_at_limits_passes() {
+        assert_eq!(exceeded(AT_LIMITS), None);
+    }
+
+    #[test]
+    fn test_each_limit_is_checked() {
+        let cases = [
+            (
+                TraceUsage {
+                    steps: 11,
+                    ..AT_LIMITS
+                },
+                (CircuitLimit::Steps, 10),
+            ),
+            (
+                TraceUsage {
+                    stack_ops: 21,
+                    ..AT_LIMITS
+                },
+                (CircuitLimit::StackOps, 20),
+            ),
+            (
+                TraceUsage {
+                    locals_ops: 31,
+                    ..AT_LIMITS
+                },
+                (CircuitLimit::LocalsOps, 30),
+            ),
+            (
+                TraceUsage {
+                    global_ops: 41,
+                    ..AT_LIMITS
+                },
+                (CircuitLimit::GlobalOps, 40),
+            ),
+        ];
+        for (usage, expected) in cases {
+            assert_eq!(exceeded(usage), Some(expected));
+        }
+    }
+
+    #[test]
+    fn test_absent_limits_are_not_checked() {
+        let usage = TraceUsage {
+            steps: usize::MAX,
+            stack_ops: usize::MAX,
+            locals_ops: usize::MAX,
+            global_ops: usize::MAX,
+        };
+        assert!(ExecutionLimits::default().check(&usage).is_ok());
+    }
+
+    #[test]
+    fn test_limits_are_taken_from_config() {
+        let config = CircuitConfig::default()
+            .max_step_row(Some(10))
+            .stack_ops_num(Some(20))
+            .locals_ops_num(Some(30))
+            .global_ops_num(Some(40));
+        let limits = ExecutionLimits::from_config(&config);
+        assert_eq!(limits.steps, Some(10));
+        assert_eq!(limits.stack_ops, Some(20));
+        assert_eq!(limits.locals_ops, Some(30));
+        assert_eq!(limits.global_ops, Some(40));
+    }
+}
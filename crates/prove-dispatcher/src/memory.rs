@@ -0,0 +1,161 @@
+use agger_contract_types::VerificationParameters;
+use agger_srs::SrsParam;
+use agger_types::QueryCircuit;
+use anyhow::Result;
+use halo2_proofs::{
+    halo2curves::bn256::Fr,
+    plonk::{Circuit, ConstraintSystem},
+};
+use std::sync::{Arc, OnceLock};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// size of a field element in memory.
+const FIELD_BYTES: u64 = 32;
+
+/// Shape of the query circuit, which doesn't change between entry functions,
+/// only the number of rows, that is k, does.
+struct CircuitShape {
+    /// polynomials committed or kept in memory during proving.
+    polys: u64,
+    /// log2 of the extended domain factor, decided by the max degree of constraints.
+    extended_k: u32,
+}
+
+fn circuit_shape() -> &'static CircuitShape {
+    static SHAPE: OnceLock<CircuitShape> = OnceLock::new();
+    SHAPE.get_or_init(|| {
+        let mut cs = ConstraintSystem::<Fr>::default();
+        QueryCircuit::configure(&mut cs);
+        let columns = cs.num_advice_columns()
+            + cs.num_fixed_columns()
+            + cs.num_selectors()
+            + cs.num_instance_columns()
+            + cs.permutation().get_columns().len();
+        // permuted input, permuted table and product of each lookup.
+        let polys = columns + 3 * cs.lookups().len();
+        let quotient_degree = cs.degree().saturating_sub(1).max(1);
+        CircuitShape {
+            polys: polys as u64,
+            extended_k: quotient_degree.next_power_of_two().trailing_zeros(),
+        }
+    })
+}
+
+/// Coarse upper bound of memory in MiB to prove a query of the entry function, from k alone.
+/// The circuit config is not read: `QueryCircuit::configure` takes no config, so columns are
+/// the same for every function, and the rows the config asks for only fill the 2^k of them.
+/// Small configs proved at a large k are bounded as if they used every row,
+/// as the prover allocates every polynomial at full size either way.
+/// Each polynomial is kept in lagrange and coefficient form of size 2^k,
+/// and on the extended domain for the quotient.
+/// Budgets are over-reserved for small functions rather than running out of memory.
+pub fn memory_upper_bound_mib(verification_parameters: &VerificationParameters) -> Result<u32> {
+    let k = SrsParam::decode(&verification_parameters.param)?.k;
+    let shape = circuit_shape();
+    let n = 1u64 << k;
+    let bytes = FIELD_BYTES * shape.polys * (2 * n + (n << shape.extended_k));
+    Ok(u32::try_from(bytes >> 20).unwrap_or(u32::MAX).max(1))
+}
+
+/// Memory budget of proving in MiB, shared by concurrent proofs.
+#[derive(Clone)]
+pub struct MemoryBudget {
+    total_mib: u32,
+    available: Arc<Semaphore>,
+}
+
+impl MemoryBudget {
+    pub fn new(total_mib: u32) -> Self {
+        let total_mib = total_mib.max(1);
+        Self {
+            total_mib,
+            available: Arc::new(Semaphore::new(total_mib as usize)),
+        }
+    }
+
+    /// wait until `mib` is available, in fifo order so that big tasks are not starved.
+    /// tasks larger than the whole budget run alone.
+    pub async fn acquire(&self, mib: u32) -> OwnedSemaphorePermit {
+        self.available
+            .clone()
+            .acquire_many_owned(mib.min(self.total_mib))
+            .await
+            .expect("memory budget is never closed")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::time::timeout;
+
+    /// whether `mib` can be acquired without waiting.
+    async fn available(budget: &MemoryBudget, mib: u32) -> bool {
+        timeout(Duration::from_millis(50), budget.acquire(mib))
+            .await
+            .is_ok()
+    }
+
+    #[tokio::test]
+    async fn test_memory_is_released_with_permits() {
+        let budget = MemoryBudget::new(10);
+        let four = budget.acquire(4).await;
+        let six = budget.acquire(6).await;
+        assert!(!available(&budget, 1).await);
+        drop(four);
+        assert!(available(&budget, 4).await);
+        assert!(!available(&budget, 5).await);
+        drop(six);
+        assert!(available(&budget, 10).await);
+    }
+
+    #[tokio::test]
+    async fn test_large_tasks_run_alone() {
+        let budget = MemoryBudget::new(10);
+        let one = budget.acquire(1).await;
+        assert!(!available(&budget, 20).await);
+        drop(one);
+        let large = budget.acquire(20).await;
+        assert!(!available(&budget, 1).await);
+        drop(large);
+        assert!(available(&budget, 10).await);
+    }
+
+    #[tokio::test]
+    async fn test_large_tasks_are_not_starved() {
+        let budget = MemoryBudget::new(10);
+        let six = budget.acquire(6).await;
+        let large = tokio::spawn({
+            let budget = budget.clone();
+            async move {
+                budget.acquire(8).await;
+            }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        // 4 MiB are left, but the large task waits in front.
+        assert!(!available(&budget, 2).await);
+        drop(six);
+        large.await.unwrap();
+        assert!(available(&budget, 10).await);
+    }
+
+    #[test]
+    fn test_upper_bound_grows_with_k() {
+        let bound = |k| {
+            memory_upper_bound_mib(&VerificationParameters {
+                config: vec![],
+                param: SrsParam {
+                    k,
+                    srs_hash: vec![],
+                }
+                .encode(),
+                vk: vec![],
+                instance_layout: vec![],
+            })
+            .unwrap()
+        };
+        assert!(bound(20) > bound(16));
+        assert!(bound(21) >= 2 * bound(20));
+    }
+}
@@ -0,0 +1,183 @@
+use crate::{verify, ProvingKeyCache, ProvingKeys};
+use agger_contract_types::VerificationParameters;
+use agger_srs::SrsParam;
+use agger_types::QueryCircuit;
+use anyhow::{anyhow, Result};
+use halo2_proofs::{
+    dev::MockProver,
+    halo2curves::bn256::{Bn256, Fr, G1Affine},
+    plonk::create_proof,
+    poly::kzg::{commitment::KZGCommitmentScheme, multiopen::ProverSHPLONK},
+    transcript::{Blake2bWrite, Challenge255, TranscriptWriterBuffer},
+};
+use rand::rngs::OsRng;
+use std::sync::Arc;
+use tracing::error;
+use zkmove_vm_circuit::witness::Witness;
+
+/// Backend proving the circuits of queries.
+pub trait Prover: Send + Sync {
+    /// whether witness of queries is needed.
+    /// witness generation is skipped for provers which don't need it.
+    fn requires_witness(&self) -> bool {
+        true
+    }
+
+    /// prepare an entry function ahead of its first query.
+    fn precompute(&self, _verification_parameters: &VerificationParameters) -> Result<()> {
+        Ok(())
+    }
+
+    /// prove the circuit of `witness` with `instances` as public inputs, returns the proof.
+    fn prove(
+        &self,
+        witness: Option<Witness<Fr>>,
+        instances: &[Fr],
+        verification_parameters: &VerificationParameters,
+    ) -> Result<Vec<u8>>;
+
+    /// prove circuits of queries of the same entry function in one aggregated proof.
+    /// each circuit is given by its witness and instances, in the order of instances in the proof.
+    fn prove_batch(
+        &self,
+        circuits: Vec<(Option<Witness<Fr>>, Vec<Fr>)>,
+        verification_parameters: &VerificationParameters,
+    ) -> Result<Vec<u8>>;
+}
+
+/// Prover generating kzg proofs which can be verified onchain.
+pub struct KzgProver {
+    keys: Arc<ProvingKeyCache>,
+}
+
+impl KzgProver {
+    pub fn new(keys: Arc<ProvingKeyCache>) -> Self {
+        Self { keys }
+    }
+}
+
+impl Prover for KzgProver {
+    fn precompute(&self, verification_parameters: &VerificationParameters) -> Result<()> {
+        self.keys.precompute(verification_parameters)
+    }
+
+    fn prove(
+        &self,
+        witness: Option<Witness<Fr>>,
+        instances: &[Fr],
+        verification_parameters: &VerificationParameters,
+    ) -> Result<Vec<u8>> {
+        let witness = witness.ok_or_else(|| anyhow!("kzg prover requires witness"))?;
+        let circuit = QueryCircuit::new(witness, instances);
+        let keys = self.keys.get_or_setup(&circuit, verification_parameters)?;
+        create_kzg_proof(&keys, &[circuit], &[instances])
+    }
+
+    fn prove_batch(
+        &self,
+        circuits: Vec<(Option<Witness<Fr>>, Vec<Fr>)>,
+        verification_parameters: &VerificationParameters,
+    ) -> Result<Vec<u8>> {
+        let (circuits, instances): (Vec<_>, Vec<_>) = circuits
+            .into_iter()
+            .map(|(witness, instances)| {
+                let witness = witness.ok_or_else(|| anyhow!("kzg prover requires witness"))?;
+                let circuit = QueryCircuit::new(witness, &instances);
+                Ok((circuit, instances))
+            })
+            .collect::<Result<Vec<_>>>()?
+            .into_iter()
+            .unzip();
+        let circuit = circuits.first().ok_or_else(|| anyhow!("empty batch"))?;
+        let keys = self.keys.get_or_setup(circuit, verification_parameters)?;
+        let instances: Vec<&[Fr]> = instances.iter().map(|i| i.as_slice()).collect();
+        create_kzg_proof(&keys, &circuits, &instances)
+    }
+}
+
+/// prove `circuits` in one proof, `instances` has the instance column of each circuit.
+fn create_kzg_proof(
+    keys: &ProvingKeys,
+    circuits: &[QueryCircuit],
+    instances: &[&[Fr]],
+) -> Result<Vec<u8>> {
+    // one instance column of each circuit.
+    let columns: Vec<&[&[Fr]]> = instances.iter().map(std::slice::from_ref).collect();
+    let mut transcript = Blake2bWrite::<_, G1Affine, Challenge255<_>>::init(vec![]);
+    create_proof::<KZGCommitmentScheme<Bn256>, ProverSHPLONK<'_, Bn256>, _, _, _, _>(
+        &keys.params,
+        &keys.pk,
+        circuits,
+        &columns,
+        OsRng,
+        &mut transcript,
+    )
+    .map_err(|e| anyhow!("create proof error. {:?}", e))?;
+    let proof = transcript.finalize();
+    // vk of the cached keys is checked to be the onchain one.
+    if let Err(e) = verify::self_check(&keys.params, keys.pk.get_vk(), instances, &proof) {
+        error!("{}", e);
+        return Err(e.into());
+    }
+    Ok(proof)
+}
+
+/// Prover checking constraints of circuits with halo2 `MockProver`, without generating proofs.
+/// Only for development and testing, the empty proofs cannot be verified onchain.
+pub struct MockCircuitProver;
+
+impl Prover for MockCircuitProver {
+    fn prove(
+        &self,
+        witness: Option<Witness<Fr>>,
+        instances: &[Fr],
+        verification_parameters: &VerificationParameters,
+    ) -> Result<Vec<u8>> {
+        let witness = witness.ok_or_else(|| anyhow!("mock prover requires witness"))?;
+        let k = SrsParam::decode(&verification_parameters.param)?.k;
+        let circuit = QueryCircuit::new(witness, instances);
+        MockProver::run(k, &circuit, vec![instances.to_vec()])
+            .map_err(|e| anyhow!("mock prover error. {:?}", e))?
+            .verify()
+            .map_err(|e| anyhow!("circuit constraints are not satisfied. {:?}", e))?;
+        Ok(Vec::new())
+    }
+
+    fn prove_batch(
+        &self,
+        circuits: Vec<(Option<Witness<Fr>>, Vec<Fr>)>,
+        verification_parameters: &VerificationParameters,
+    ) -> Result<Vec<u8>> {
+        for (witness, instances) in circuits {
+            self.prove(witness, &instances, verification_parameters)?;
+        }
+        Ok(Vec::new())
+    }
+}
+
+/// Prover which only executes queries, neither witness nor proofs are generated.
+/// Only for development and testing, the empty proofs cannot be verified onchain.
+pub struct ExecuteOnlyProver;
+
+impl Prover for ExecuteOnlyProver {
+    fn requires_witness(&self) -> bool {
+        false
+    }
+
+    fn prove(
+        &self,
+        _witness: Option<Witness<Fr>>,
+        _instances: &[Fr],
+        _verification_parameters: &VerificationParameters,
+    ) -> Result<Vec<u8>> {
+        Ok(Vec::new())
+    }
+
+    fn prove_batch(
+        &self,
+        _circuits: Vec<(Option<Witness<Fr>>, Vec<Fr>)>,
+        _verification_parameters: &VerificationParameters,
+    ) -> Result<Vec<u8>> {
+        Ok(Vec::new())
+    }
+}
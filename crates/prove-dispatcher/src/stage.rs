@@ -0,0 +1,214 @@
+use crate::memory::MemoryBudget;
+use futures_util::{stream::FuturesUnordered, StreamExt};
+use std::{future::Future, sync::Arc};
+use threadpool::ThreadPool;
+use tokio::sync::{mpsc::Receiver, oneshot};
+use tracing::{error, info};
+
+/// Runs `job` of each input on `threadpool`, and handles outputs in order of completion.
+/// Inputs are only received when the threadpool has an idle thread,
+/// so that a slow handler pushes back to the senders of the stage.
+pub struct Stage<I, O> {
+    name: &'static str,
+    threadpool: ThreadPool,
+    job: Arc<dyn Fn(I) -> O + Send + Sync>,
+    /// memory budget jobs are scheduled against, and the memory in MiB each input takes.
+    budget: Option<(MemoryBudget, fn(&I) -> u32)>,
+}
+
+impl<I: Send + 'static, O: Send + 'static> Stage<I, O> {
+    pub fn new(
+        name: &'static str,
+        threadpool: ThreadPool,
+        job: impl Fn(I) -> O + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            name,
+            threadpool,
+            job: Arc::new(job),
+            budget: None,
+        }
+    }
+
+    /// only run jobs when the memory they take fits in `budget`.
+    pub fn with_budget(mut self, budget: MemoryBudget, memory_mib: fn(&I) -> u32) -> Self {
+        self.budget = Some((budget, memory_mib));
+        self
+    }
+
+    /// run until inputs are closed or `handle` returns false,
+    /// then wait any ongoing jobs to finish.
+    pub async fn run<F, Fut>(self, mut inputs: Receiver<I>, mut handle: F)
+    where
+        F: FnMut(O) -> Fut,
+        Fut: Future<Output = bool>,
+    {
+        let mut fs = FuturesUnordered::new();
+        loop {
+            if self.threadpool.active_count() == self.threadpool.max_count() {
+                // when ongoing jobs is full, we wait on one job finishing, then go on to receive new inputs.
+                if let Some(received_output) = fs.next().await {
+                    match received_output {
+                        Ok(output) => {
+                            if !handle(output).await {
+                                break;
+                            }
+                        },
+                        Err(_) => {
+                            error!("{} job ended without sending output", self.name);
+                        },
+                    }
+                }
+            }
+            let input = tokio::select! {
+                received_output = fs.next() => {
+                    match received_output {
+                        Some(Ok(output)) => {
+                            if !handle(output).await {
+                                // output receiver is gone, stop the stage
+                                break
+                            }
+                            continue
+                        }
+                        Some(Err(_)) => {
+                            error!("{} job ended without sending output", self.name);
+                            continue
+                        }
+                        None => {
+                            info!("empty {} queues, wait for new input", self.name);
+                            inputs.recv().await
+                        }
+                    }
+                }
+                input = inputs.recv() => {
+                    input
+                }
+            };
+            if let Some(input) = input {
+                // ongoing jobs release their memory without the outputs being handled.
+                let permit = match &self.budget {
+                    Some((budget, memory_mib)) => Some(budget.acquire(memory_mib(&input)).await),
+                    None => None,
+                };
+                let (tx, rx) = oneshot::channel();
+                fs.push(rx);
+                let job = self.job.clone();
+                self.threadpool.execute(move || {
+                    let output = job(input);
+                    drop(permit);
+                    if let Err(_v) = tx.send(output) {
+                        error!("job ended, but output receiver is lost");
+                    }
+                });
+            } else {
+                // all input sender is gone, just stop the stage.
+                break;
+            }
+        }
+        info!("{} stage is closing...", self.name);
+        // before closing, wait any queued or ongoing computations to finish.
+        while let Some(received_output) = fs.next().await {
+            match received_output {
+                Ok(output) => {
+                    if !handle(output).await {
+                        break;
+                    }
+                },
+                Err(_) => {
+                    error!("{} job ended without sending output", self.name);
+                },
+            }
+        }
+        info!("{} stage is closed", self.name);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            mpsc, Mutex,
+        },
+        time::Duration,
+    };
+    use tokio::sync::mpsc::{channel, unbounded_channel};
+
+    /// run a stage of `threads` over `inputs`, and collect outputs once inputs are closed.
+    async fn run_stage(
+        threads: usize,
+        job: impl Fn(u32) -> u32 + Send + Sync + 'static,
+        inputs: Receiver<u32>,
+    ) -> Vec<u32> {
+        let (output_sender, mut output_receiver) = unbounded_channel();
+        Stage::new("test", ThreadPool::new(threads), job)
+            .run(inputs, |output| {
+                let sent = output_sender.send(output).is_ok();
+                async move { sent }
+            })
+            .await;
+        drop(output_sender);
+        let mut outputs = vec![];
+        while let Some(output) = output_receiver.recv().await {
+            outputs.push(output);
+        }
+        outputs.sort();
+        outputs
+    }
+
+    #[tokio::test]
+    async fn test_inputs_are_pushed_back_when_threads_are_busy() {
+        let (release, released) = mpsc::channel::<()>();
+        let released = Mutex::new(released);
+        let (input_sender, inputs) = channel(1);
+        let stage = tokio::spawn(run_stage(
+            1,
+            move |input| {
+                released.lock().unwrap().recv().unwrap();
+                input
+            },
+            inputs,
+        ));
+
+        let sent = Arc::new(AtomicUsize::new(0));
+        let sender = {
+            let sent = sent.clone();
+            tokio::spawn(async move {
+                for input in 0..10 {
+                    input_sender.send(input).await.unwrap();
+                    sent.fetch_add(1, Ordering::SeqCst);
+                }
+            })
+        };
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        // the running job, at most one queued on the threadpool before it's seen busy,
+        // and the one buffered in the queue.
+        assert!(sent.load(Ordering::SeqCst) <= 3);
+
+        for _ in 0..10 {
+            release.send(()).unwrap();
+        }
+        sender.await.unwrap();
+        assert_eq!(stage.await.unwrap(), (0..10).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn test_panicking_jobs_do_not_stop_the_stage() {
+        let (input_sender, inputs) = channel(10);
+        for input in 0..10 {
+            input_sender.send(input).await.unwrap();
+        }
+        drop(input_sender);
+        let outputs = run_stage(
+            2,
+            |input| {
+                assert!(input % 2 == 1, "even input");
+                input
+            },
+            inputs,
+        )
+        .await;
+        assert_eq!(outputs, vec![1, 3, 5, 7, 9]);
+    }
+}
@@ -0,0 +1,133 @@
+//! Global resources as the vm reads them while executing a query.
+
+use agger_chain_source::{SourceError, SourceResult, StateSource};
+use agger_contract_types::{ReadSet, ResourceRead};
+use anyhow::{anyhow, Result};
+use move_core_types::{
+    account_address::AccountAddress, language_storage::StructTag, parser::parse_struct_tag,
+    resolver::ResourceResolver,
+};
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+};
+use tokio::runtime::Handle;
+use tracing::debug;
+
+/// Resources the vm reads through while executing.
+pub type Resources = Arc<dyn ResourceResolver<Error = anyhow::Error> + Send + Sync>;
+
+type ResourceKey = (AccountAddress, StructTag);
+
+/// Resources of a recorded read set, executions are replayed on.
+/// Reading a resource out of it fails, as the execution diverges from the recorded one.
+pub struct ReadSetResources {
+    resources: BTreeMap<ResourceKey, Option<Vec<u8>>>,
+}
+
+impl ReadSetResources {
+    pub fn new(read_set: &ReadSet) -> Result<Self> {
+        let resources = read_set
+            .resources
+            .iter()
+            .map(|r| {
+                let key = (
+                    AccountAddress::from_bytes(&r.address)?,
+                    parse_struct_tag(&r.struct_tag)?,
+                );
+                Ok((key, r.value.clone()))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { resources })
+    }
+}
+
+impl ResourceResolver for ReadSetResources {
+    type Error = anyhow::Error;
+
+    fn get_resource(&self, address: &AccountAddress, typ: &StructTag) -> Result<Option<Vec<u8>>> {
+        self.resources
+            .get(&(*address, typ.clone()))
+            .cloned()
+            .ok_or_else(|| anyhow!("resource {} of {} is not in the read set", typ, address))
+    }
+}
+
+/// Resources fetched from a state source at `version` when the vm first reads them.
+/// Every read is recorded, absent ones too, into the read set of the execution.
+pub struct SourceResources {
+    source: Arc<dyn StateSource>,
+    version: u64,
+    runtime: Handle,
+    reads: Mutex<BTreeMap<ResourceKey, Option<Vec<u8>>>>,
+    /// first failure of the source, reads are incomplete if set.
+    error: Mutex<Option<SourceError>>,
+}
+
+impl SourceResources {
+    /// `runtime` drives the source, the vm reads from threads outside of it.
+    pub fn new(source: Arc<dyn StateSource>, version: u64, runtime: Handle) -> Self {
+        Self {
+            source,
+            version,
+            runtime,
+            reads: Mutex::new(BTreeMap::new()),
+            error: Mutex::new(None),
+        }
+    }
+
+    /// reads so far, ordered by address and type.
+    pub fn read_set(&self) -> SourceResult<ReadSet> {
+        if let Some(e) = self.error.lock().unwrap().take() {
+            return Err(e);
+        }
+        let resources: Vec<_> = self
+            .reads
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|((address, struct_tag), value)| ResourceRead {
+                address: address.to_vec(),
+                struct_tag: struct_tag.to_string(),
+                value: value.clone(),
+            })
+            .collect();
+        debug!(
+            "read {} of {} resources at version {}",
+            resources.iter().filter(|r| r.value.is_some()).count(),
+            resources.len(),
+            self.version
+        );
+        Ok(ReadSet {
+            version: self.version,
+            resources,
+        })
+    }
+}
+
+impl ResourceResolver for SourceResources {
+    type Error = anyhow::Error;
+
+    fn get_resource(&self, address: &AccountAddress, typ: &StructTag) -> Result<Option<Vec<u8>>> {
+        let key = (*address, typ.clone());
+        if let Some(value) = self.reads.lock().unwrap().get(&key) {
+            return Ok(value.clone());
+        }
+        let fetched = self.runtime.block_on(self.source.get_resource(
+            address.to_vec(),
+            typ.to_string(),
+            self.version,
+        ));
+        match fetched {
+            Ok(value) => {
+                self.reads.lock().unwrap().insert(key, value.clone());
+                Ok(value)
+            },
+            Err(e) => {
+                let message = format!("read resource {} of {} error. {}", typ, address, e);
+                self.error.lock().unwrap().get_or_insert(e);
+                Err(anyhow!(message))
+            },
+        }
+    }
+}
@@ -0,0 +1,18 @@
+use crate::ProveError;
+use halo2_proofs::{
+    halo2curves::bn256::{Bn256, Fr, G1Affine},
+    plonk::VerifyingKey,
+    poly::kzg::commitment::ParamsKZG,
+};
+
+/// verify `proof` the same way agger-verifier does,
+/// so that invalid proofs are never stored or submitted.
+pub fn self_check(
+    params: &ParamsKZG<Bn256>,
+    vk: &VerifyingKey<G1Affine>,
+    instances: &[&[Fr]],
+    proof: &[u8],
+) -> Result<(), ProveError> {
+    agger_verifier::verify_kzg(params, vk, instances, proof)
+        .map_err(|e| ProveError::ProofSelfCheckFailed(e.to_string()))
+}
@@ -0,0 +1,140 @@
+//! Executing queries on zkmove-vm.
+//! Executing on chain state relies on zkmove-vm apis beyond the ones vk-generation uses,
+//! which zkmove-vm at the pinned rev doesn't expose yet:
+//! - `StateStore::with_resource_resolver`, to read resources lazily while executing,
+//! - `ExecutionTrace::{return_values, events}`, the outputs of the entry function,
+//! - `ExecutionTrace::{steps, stack_ops, locals_ops, global_ops}`, the circuit rows taken.
+//!
+//! They are only called from here, and only built with the `unreleased-zkmove-vm` feature,
+//! along with a zkmove-vm patch providing them.
+//! Without it, queries are not executed and fail with `ProveError::UnsupportedVm`.
+#![cfg_attr(not(feature = "unreleased-zkmove-vm"), allow(dead_code))]
+
+use crate::{state::Resources, ProveError};
+use agger_contract_types::QueryEvent;
+use halo2_proofs::halo2curves::bn256::Fr;
+use move_binary_format::{errors::VMError, CompiledModule};
+use move_core_types::{
+    identifier::Identifier,
+    language_storage::{ModuleId, TypeTag},
+};
+use movelang::argument::ScriptArguments;
+#[cfg(feature = "unreleased-zkmove-vm")]
+use zkmove_vm::{runtime::Runtime, state::StateStore};
+use zkmove_vm_circuit::witness::{CircuitConfig, Witness};
+
+/// Rows of each kind of execution record taken by an execution.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TraceUsage {
+    pub steps: usize,
+    pub stack_ops: usize,
+    pub locals_ops: usize,
+    pub global_ops: usize,
+}
+
+/// Entry function call of a query.
+pub struct EntryCall {
+    pub module_id: ModuleId,
+    pub function: Identifier,
+    pub ty_args: Vec<TypeTag>,
+    pub args: Option<ScriptArguments>,
+}
+
+/// Outputs of an execution, and its witness if asked for.
+pub struct Execution {
+    /// bcs serialized return values of the entry function.
+    pub return_values: Vec<Vec<u8>>,
+    pub events: Vec<QueryEvent>,
+    pub usage: TraceUsage,
+    pub witness: Option<Witness<Fr>>,
+}
+
+pub enum ExecuteError {
+    /// the execution itself failed, e.g. aborted.
+    Vm(VMError),
+    Other(anyhow::Error),
+}
+
+impl<E: Into<anyhow::Error>> From<E> for ExecuteError {
+    fn from(e: E) -> Self {
+        ExecuteError::Other(e.into())
+    }
+}
+
+/// execute `call` on `modules`, reading global resources from `resources`.
+/// `check` is called on the usage of the execution before the witness is generated,
+/// which takes much longer than execution. The witness is skipped without `witness_config`.
+#[cfg(feature = "unreleased-zkmove-vm")]
+pub fn execute(
+    modules: &[CompiledModule],
+    resources: Resources,
+    call: &EntryCall,
+    check: impl FnOnce(&TraceUsage) -> Result<(), ProveError>,
+    witness_config: Option<CircuitConfig>,
+) -> Result<Execution, ExecuteError> {
+    let mut state = StateStore::new().with_resource_resolver(resources);
+    for m in modules {
+        state.add_module(m.clone());
+    }
+    // todo: stop runaway queries once zkmove-vm can bound the steps of an execution,
+    // steps are only checked against the circuit after the execution ends.
+    let rt = Runtime::<Fr>::new();
+    let traces = rt
+        .execute_entry_function(
+            &call.module_id,
+            &call.function,
+            call.ty_args.clone(),
+            None,
+            call.args.clone(),
+            &mut state,
+        )
+        .map_err(ExecuteError::Vm)?;
+    let usage = TraceUsage {
+        steps: traces.steps.len(),
+        stack_ops: traces.stack_ops.len(),
+        locals_ops: traces.locals_ops.len(),
+        global_ops: traces.global_ops.len(),
+    };
+    check(&usage)?;
+    // outputs are taken before the traces are consumed by witness generation.
+    let return_values = traces.return_values.clone();
+    let events = traces
+        .events
+        .iter()
+        .map(|(key, sequence_number, type_tag, data)| QueryEvent {
+            key: key.to_vec(),
+            sequence_number: *sequence_number,
+            type_tag: type_tag.to_string(),
+            data: data.clone(),
+        })
+        .collect();
+    let witness = match witness_config {
+        Some(config) => Some(rt.process_execution_trace(
+            call.ty_args.clone(),
+            None,
+            Some((&call.module_id, &call.function)),
+            modules.to_vec(),
+            traces,
+            config,
+        )?),
+        None => None,
+    };
+    Ok(Execution {
+        return_values,
+        events,
+        usage,
+        witness,
+    })
+}
+
+/// zkmove-vm at the pinned rev cannot execute on chain state, nor return outputs.
+#[cfg(not(feature = "unreleased-zkmove-vm"))]
+pub fn execute(
+    _modules: &[CompiledModule],
+    _resources: Resources,
+    _call: &EntryCall,
+    _check: impl FnOnce(&TraceUsage) -> Result<(), ProveError>,
+    _witness_config: Option<CircuitConfig>,
+) -> Result<Execution, ExecuteError> {
+    Err(ProveError::UnsupportedVm.into())
+}
@@ -1,36 +1,58 @@
-use agger_contract_types::UserQuery;
+use crate::{
+    limits::ExecutionLimits,
+    state::Resources,
+    vm::{self, EntryCall, ExecuteError},
+};
+use agger_contract_types::{ExecutionFailure, ExecutionLocation, QueryEvent, UserQuery};
 use anyhow::anyhow;
 use halo2_proofs::halo2curves::bn256::Fr;
-use move_binary_format::CompiledModule;
+use move_binary_format::{
+    access::ModuleAccess,
+    errors::{Location, VMError},
+    CompiledModule,
+};
 use move_core_types::{
     identifier::Identifier,
     language_storage::ModuleId,
     parser::{parse_transaction_argument, parse_type_tags},
+    vm_status::StatusCode,
 };
+use move_helpers::access_ext::ModuleAccessExt;
 use movelang::argument::ScriptArguments;
-use zkmove_vm::{runtime::Runtime, state::StateStore};
-use zkmove_vm_circuit::witness::Witness;
+use zkmove_vm_circuit::witness::{CircuitConfig, Witness};
+
+/// Witness of a query, and the outputs of its execution.
+pub struct QueryExecution {
+    /// absent if witness generation is skipped.
+    pub witness: Option<Witness<Fr>>,
+    /// index of the entry function in its module.
+    pub function_index: u16,
+    /// bcs serialized return values of the entry function.
+    pub return_values: Vec<Vec<u8>>,
+    pub events: Vec<QueryEvent>,
+    /// rows of the circuit taken by the execution steps.
+    pub rows: usize,
+}
 
 pub fn witness(
-    query: UserQuery,
+    query: &UserQuery,
     modules: Vec<Vec<u8>>,
-    config: Vec<u8>,
-) -> anyhow::Result<Witness<Fr>> {
-    let mut state = StateStore::new();
-    let mut compiled_modules = Vec::default();
-    for m in &modules {
-        let m = CompiledModule::deserialize(m)?;
-        compiled_modules.push(m.clone());
-        state.add_module(m);
-        // todo: replace it with execute entry_function
-    }
-    let rt = Runtime::<Fr>::new();
+    resources: Resources,
+    config: &[u8],
+    with_witness: bool,
+) -> anyhow::Result<QueryExecution> {
+    let circuit_config: CircuitConfig = bcs::from_bytes(config)?;
+    let limits = ExecutionLimits::from_config(&circuit_config);
+    let compiled_modules = modules
+        .iter()
+        .map(|m| CompiledModule::deserialize(m))
+        .collect::<Result<Vec<_>, _>>()?;
     let ty_args = query
         .query
         .ty_args
-        .into_iter()
+        .iter()
         .map(|t| {
-            let s = String::from_utf8(t)?;
+            let s = String::from_utf8(t.clone())?;
             let mut ts = parse_type_tags(s.as_str())?;
             ts.pop().ok_or_else(|| anyhow!("parse type arg failure"))
         })
@@ -38,9 +60,9 @@ pub fn witness(
     let args = query
         .query
         .args
-        .into_iter()
+        .iter()
         .map(|arg| {
-            let s = String::from_utf8(arg)?;
+            let s = String::from_utf8(arg.clone())?;
             let ta = parse_transaction_argument(s.as_str())?;
             Ok(ta)
         })
@@ -48,30 +70,89 @@ pub fn witness(
     let entry_module_address =
         move_core_types::account_address::AccountAddress::from_bytes(&query.query.module_address)?;
     let entry_module_name = Identifier::from_utf8(query.query.module_name.clone())?;
-    let entry_function_name = Identifier::from_utf8(query.query.function_name)?;
+    let entry_function_name = Identifier::from_utf8(query.query.function_name.clone())?;
     let entry_module_id = ModuleId::new(entry_module_address, entry_module_name);
-    let traces = rt
-        .execute_entry_function(
-            &entry_module_id,
-            &entry_function_name,
-            ty_args.clone(),
-            None,
-            if args.is_empty() {
-                None
-            } else {
-                Some(ScriptArguments::new(args))
-            },
-            &mut state,
-        )
-        .unwrap();
-
-    let witness = rt.process_execution_trace(
+    let function_index = compiled_modules
+        .iter()
+        .find(|m| m.self_id() == entry_module_id)
+        .and_then(|m| m.find_function_def_by_name(&entry_function_name))
+        .ok_or_else(|| {
+            anyhow!(
+                "function {}::{} not found",
+                &entry_module_id,
+                &entry_function_name
+            )
+        })?
+        .function
+        .0;
+    let call = EntryCall {
+        module_id: entry_module_id,
+        function: entry_function_name,
         ty_args,
-        None,
-        Some((&entry_module_id, &entry_function_name)),
-        compiled_modules.clone(),
-        traces,
-        bcs::from_bytes(config.as_slice())?,
-    )?;
-    Ok(witness)
+        args: if args.is_empty() {
+            None
+        } else {
+            Some(ScriptArguments::new(args))
+        },
+    };
+    let execution = vm::execute(
+        &compiled_modules,
+        resources,
+        &call,
+        |usage| limits.check(usage),
+        with_witness.then_some(circuit_config),
+    )
+    .map_err(|e| match e {
+        ExecuteError::Vm(e) => execution_failure(&e, &compiled_modules).into(),
+        ExecuteError::Other(e) => e,
+    })?;
+    Ok(QueryExecution {
+        witness: execution.witness,
+        function_index,
+        return_values: execution.return_values,
+        events: execution.events,
+        rows: execution.usage.steps,
+    })
+}
+
+/// turn vm errors of executing user queries into failures which can be replied to users.
+fn execution_failure(e: &VMError, modules: &[CompiledModule]) -> ExecutionFailure {
+    let status = e.major_status();
+    let location = match (e.location(), e.offsets().first()) {
+        (Location::Module(id), Some((function_index, code_offset))) => {
+            let function = modules
+                .iter()
+                .find(|m| &m.self_id() == id)
+                .and_then(|m| {
+                    m.function_defs()
+                        .get(function_index.0 as usize)
+                        .map(|f| (m, f))
+                })
+                .map(|(m, f)| {
+                    m.identifier_at(m.function_handle_at(f.function).name)
+                        .to_string()
+                })
+                .unwrap_or_else(|| format!("#{}", function_index.0));
+            Some(ExecutionLocation {
+                module_id: agger_contract_types::ModuleId {
+                    addr: id.address().to_vec(),
+                    name: id.name().to_string(),
+                },
+                function,
+                code_offset: *code_offset,
+            })
+        },
+        _ => None,
+    };
+    ExecutionFailure {
+        vm_status: format!("{:?}", status),
+        status_code: status as u64,
+        abort_code: if status == StatusCode::ABORTED {
+            e.sub_status()
+        } else {
+            None
+        },
+        location,
+        message: e.message().cloned(),
+    }
 }
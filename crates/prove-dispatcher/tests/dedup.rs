@@ -0,0 +1,235 @@
+use agger_contract_types::{
+    query_binding_hash, Query, ReadSet, ResourceRead, UserQuery, VerificationParameters,
+};
+use agger_prove_dispatcher::{
+    proving_input_hash, InputHash, ProveOutput, ProveTask, ProvedInput, ProvedInputs,
+    QueryDeduplicator, QueryOutput,
+};
+use anyhow::{anyhow, Result};
+use aptos_move_core_types::account_address::AccountAddress;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    sync::mpsc::{self, Receiver, Sender},
+    time::timeout,
+};
+
+const TEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Default)]
+struct MemoryProvedInputs {
+    inputs: Mutex<HashMap<InputHash, ProvedInput>>,
+}
+
+impl ProvedInputs for MemoryProvedInputs {
+    fn get(&self, input: &InputHash) -> Result<Option<ProvedInput>> {
+        Ok(self.inputs.lock().unwrap().get(input).cloned())
+    }
+
+    fn put(&self, input: &InputHash, proved: &ProvedInput) -> Result<()> {
+        self.inputs
+            .lock()
+            .unwrap()
+            .insert(input.clone(), proved.clone());
+        Ok(())
+    }
+}
+
+fn task(user: &str, id: u64, version: u64, args: &[&str]) -> ProveTask {
+    ProveTask {
+        query: UserQuery {
+            version,
+            sequence_number: id,
+            user: AccountAddress::from_hex_literal(user).unwrap(),
+            id,
+            query: Query {
+                module_address: AccountAddress::from_hex_literal("0xcafe").unwrap().to_vec(),
+                module_name: b"adder".to_vec(),
+                function_name: b"add".to_vec(),
+                deadline: 0,
+                args: args.iter().map(|a| a.as_bytes().to_vec()).collect(),
+                ty_args: vec![],
+                success: None,
+                result: None,
+            },
+            read_at_latest: None,
+        },
+        modules: vec![vec![1, 2, 3]],
+        verification_parameters: VerificationParameters {
+            config: vec![],
+            param: vec![],
+            vk: vec![4, 5, 6],
+            instance_layout: vec![],
+        },
+        read_set: ReadSet {
+            version,
+            resources: vec![],
+        },
+    }
+}
+
+fn output(query: &UserQuery, proof: Vec<u8>) -> ProveOutput {
+    let return_values = vec![vec![7]];
+    ProveOutput {
+        binding_hash: query_binding_hash(query, &return_values, &[], query.version, &proof),
+        return_values,
+        events: vec![],
+        state_version: query.version,
+        read_set_hash: vec![0; 32],
+        proof,
+        batch: None,
+    }
+}
+
+/// channels of a deduplicator, in place of the dispatcher behind it.
+struct Harness {
+    tasks: Sender<ProveTask>,
+    dispatched: Receiver<ProveTask>,
+    proved: Sender<QueryOutput>,
+    outputs: Receiver<QueryOutput>,
+}
+
+fn start(proved_inputs: Arc<MemoryProvedInputs>) -> Harness {
+    let (tasks, task_receiver) = mpsc::channel(8);
+    let (dispatch_sender, dispatched) = mpsc::channel(8);
+    let (proved, proved_receiver) = mpsc::channel(8);
+    let (output_sender, outputs) = mpsc::channel(8);
+    tokio::spawn(QueryDeduplicator::new(proved_inputs).run(
+        task_receiver,
+        dispatch_sender,
+        proved_receiver,
+        output_sender,
+    ));
+    Harness {
+        tasks,
+        dispatched,
+        proved,
+        outputs,
+    }
+}
+
+async fn recv<T>(receiver: &mut Receiver<T>) -> T {
+    timeout(TEST_TIMEOUT, receiver.recv())
+        .await
+        .unwrap()
+        .unwrap()
+}
+
+#[test]
+fn test_proving_input_hash() {
+    let hash = proving_input_hash(&task("0xa11ce", 0, 1, &["1u64"]));
+    // who sent the query, and the version it reads resources at, are left out.
+    assert_eq!(hash, proving_input_hash(&task("0xb0b", 3, 9, &["1u64"])));
+    // args are hashed as sent.
+    assert_ne!(hash, proving_input_hash(&task("0xa11ce", 0, 1, &["1"])));
+
+    let mut other_resources = task("0xa11ce", 0, 1, &["1u64"]);
+    other_resources.read_set.resources.push(ResourceRead {
+        address: vec![0xB0],
+        struct_tag: "0xcafe::counter::Counter".to_string(),
+        value: None,
+    });
+    assert_ne!(hash, proving_input_hash(&other_resources));
+
+    let mut other_vk = task("0xa11ce", 0, 1, &["1u64"]);
+    other_vk.verification_parameters.vk = vec![];
+    assert_ne!(hash, proving_input_hash(&other_vk));
+}
+
+#[tokio::test]
+async fn test_duplicates_in_flight_are_coalesced() {
+    let proved_inputs = Arc::new(MemoryProvedInputs::default());
+    let mut harness = start(proved_inputs.clone());
+
+    let first = task("0xa11ce", 0, 1, &["1u64"]);
+    let second = task("0xb0b", 1, 2, &["1u64"]);
+    harness.tasks.send(first.clone()).await.unwrap();
+    harness.tasks.send(second.clone()).await.unwrap();
+    // a different query is dispatched behind the first, the duplicate is not.
+    harness
+        .tasks
+        .send(task("0xa11ce", 2, 1, &["2u64"]))
+        .await
+        .unwrap();
+    let dispatched = recv(&mut harness.dispatched).await;
+    assert_eq!(dispatched.query.id, 0);
+    let dispatched = recv(&mut harness.dispatched).await;
+    assert_eq!(dispatched.query.id, 2);
+
+    let proved = output(&first.query, vec![9, 9]);
+    harness
+        .proved
+        .send((first.query.clone(), Ok(proved.clone())))
+        .await
+        .unwrap();
+    let (query, result) = recv(&mut harness.outputs).await;
+    assert_eq!(query.id, 0);
+    assert_eq!(result.unwrap().binding_hash, proved.binding_hash);
+    // the duplicate shares results and proof, bound to its own user and id.
+    let (query, result) = recv(&mut harness.outputs).await;
+    assert_eq!(query.id, 1);
+    let result = result.unwrap();
+    assert_eq!(result.return_values, proved.return_values);
+    assert_eq!(result.proof, proved.proof);
+    // and replies the version of its own state.
+    assert_eq!(result.state_version, 2);
+    assert_eq!(
+        result.binding_hash,
+        query_binding_hash(
+            &second.query,
+            &proved.return_values,
+            &[],
+            second.query.version,
+            &proved.proof
+        )
+    );
+
+    // identical queries later are replied from the stored input, without proving.
+    assert_eq!(proved_inputs.inputs.lock().unwrap().len(), 1);
+    harness
+        .tasks
+        .send(task("0xca11", 3, 5, &["1u64"]))
+        .await
+        .unwrap();
+    let (query, result) = recv(&mut harness.outputs).await;
+    assert_eq!(query.id, 3);
+    assert_eq!(result.unwrap().proof, proved.proof);
+    assert!(harness.dispatched.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn test_duplicates_share_failures() {
+    let proved_inputs = Arc::new(MemoryProvedInputs::default());
+    let mut harness = start(proved_inputs.clone());
+
+    let first = task("0xa11ce", 0, 1, &["1u64"]);
+    harness.tasks.send(first.clone()).await.unwrap();
+    harness
+        .tasks
+        .send(task("0xb0b", 1, 1, &["1u64"]))
+        .await
+        .unwrap();
+    recv(&mut harness.dispatched).await;
+
+    harness
+        .proved
+        .send((first.query, Err(anyhow!("prover is down"))))
+        .await
+        .unwrap();
+    for id in [0, 1] {
+        let (query, result) = recv(&mut harness.outputs).await;
+        assert_eq!(query.id, id);
+        assert!(result.unwrap_err().to_string().contains("prover is down"));
+    }
+    // failures are not stored, the input is proved again next time.
+    assert!(proved_inputs.inputs.lock().unwrap().is_empty());
+    harness
+        .tasks
+        .send(task("0xca11", 2, 1, &["1u64"]))
+        .await
+        .unwrap();
+    assert_eq!(recv(&mut harness.dispatched).await.query.id, 2);
+}
@@ -0,0 +1,131 @@
+use agger_contract_types::VerificationParameters;
+use agger_prove_dispatcher::{ProvingKeyCache, ProvingKeys};
+use agger_srs::Srs;
+use agger_types::{CircuitConfig, DemoRunConfig, EntryFunctionConfig};
+use agger_verifier::keygen_circuit;
+use agger_vk_generation::gen_vks;
+use move_core_types::parser::parse_transaction_argument;
+use movelang::argument::ScriptArguments;
+use sha3::{Digest, Sha3_256};
+use std::{path::PathBuf, sync::Arc};
+use test_helpers::compile_module_bytes;
+
+/// functions of different shapes, so that their vks differ.
+const MODULE: &str = r#"
+module 0xcafe::shapes {
+    public entry fun noop() {}
+
+    public entry fun sum(n: u64) {
+        let i = 0;
+        let s = 0;
+        while (i < n) {
+            s = s + i;
+            i = i + 1;
+        };
+        let _ = s;
+    }
+}
+"#;
+
+fn entry_function(name: &str, args: &[&str]) -> EntryFunctionConfig {
+    EntryFunctionConfig {
+        entry_module_address: "0xcafe".to_string(),
+        entry_module_name: "shapes".to_string(),
+        entry_function: name.to_string(),
+        demo_run_config: DemoRunConfig {
+            args: Some(ScriptArguments::new(
+                args.iter()
+                    .map(|a| parse_transaction_argument(a).unwrap())
+                    .collect(),
+            )),
+            ty_args: None,
+        },
+        circuit_config: CircuitConfig::default(),
+    }
+}
+
+/// verification parameters of `noop` and `sum`, as registered.
+fn registered(srs: &Srs) -> (VerificationParameters, VerificationParameters) {
+    let vks = gen_vks(srs, vec![compile_module_bytes(MODULE)], vec![
+        entry_function("noop", &[]),
+        entry_function("sum", &["100u64"]),
+    ])
+    .unwrap();
+    let mut registered = vks.into_iter().map(|vp| VerificationParameters {
+        config: vp.config,
+        param: vp.param,
+        vk: vp.vk,
+        instance_layout: vp.instance_layout,
+    });
+    let noop = registered.next().unwrap();
+    let sum = registered.next().unwrap();
+    assert_ne!(noop.vk, sum.vk);
+    (noop, sum)
+}
+
+/// keys of `vp`, setup from its registered config if not cached.
+fn keys(cache: &ProvingKeyCache, vp: &VerificationParameters) -> Arc<ProvingKeys> {
+    let circuit = keygen_circuit(&vp.config, &vp.instance_layout).unwrap();
+    cache.get_or_setup(&circuit, vp).unwrap()
+}
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("agger-keys-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    dir
+}
+
+fn file_name(vp: &VerificationParameters) -> String {
+    hex::encode(Sha3_256::digest(&vp.vk))
+}
+
+#[test]
+fn test_least_recently_used_keys_are_evicted() {
+    let srs = Arc::new(Srs::insecure_dev());
+    let (noop, sum) = registered(&srs);
+    let cache = ProvingKeyCache::new(1, srs.clone());
+
+    let noop_keys = keys(&cache, &noop);
+    // cached keys are shared.
+    assert!(Arc::ptr_eq(&noop_keys, &keys(&cache, &noop)));
+    // keys of another function evict them, they are setup again.
+    keys(&cache, &sum);
+    let setup_again = keys(&cache, &noop);
+    assert!(!Arc::ptr_eq(&noop_keys, &setup_again));
+    assert_eq!(setup_again.verification_parameters, noop);
+
+    // both are kept with room for them.
+    let cache = ProvingKeyCache::new(2, srs);
+    let noop_keys = keys(&cache, &noop);
+    keys(&cache, &sum);
+    assert!(Arc::ptr_eq(&noop_keys, &keys(&cache, &noop)));
+}
+
+#[test]
+fn test_keys_are_persisted() {
+    let srs = Arc::new(Srs::insecure_dev());
+    let (noop, sum) = registered(&srs);
+    let dir = temp_dir("persisted");
+    let cache = ProvingKeyCache::new(1, srs.clone()).with_dir(&dir).unwrap();
+    keys(&cache, &noop);
+    let name = file_name(&noop);
+    assert!(dir.join(&name).with_extension("params").exists());
+    assert!(dir.join(&name).with_extension("pk").exists());
+
+    // keys are loaded rather than setup: the circuit of `sum` cannot setup keys of `noop`.
+    let sum_circuit = keygen_circuit(&sum.config, &sum.instance_layout).unwrap();
+    let restarted = ProvingKeyCache::new(1, srs.clone()).with_dir(&dir).unwrap();
+    assert!(ProvingKeyCache::new(1, srs.clone())
+        .get_or_setup(&sum_circuit, &noop)
+        .is_err());
+    let loaded = restarted.get_or_setup(&sum_circuit, &noop).unwrap();
+    assert_eq!(loaded.verification_parameters, noop);
+
+    // corrupted files are setup again.
+    std::fs::write(dir.join(&name).with_extension("pk"), b"corrupted").unwrap();
+    let restarted = ProvingKeyCache::new(1, srs).with_dir(&dir).unwrap();
+    assert!(restarted.get_or_setup(&sum_circuit, &noop).is_err());
+    keys(&restarted, &noop);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
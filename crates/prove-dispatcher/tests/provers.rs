@@ -0,0 +1,184 @@
+use agger_contract_types::{ExecutionFailure, Query, ReadSet, UserQuery, VerificationParameters};
+use agger_prove_dispatcher::{
+    ExecuteOnlyProver, KzgProver, MockCircuitProver, ProveTask, Prover, ProvingKeyCache,
+    ProvingTaskDispatcher, QueryOutput,
+};
+use agger_srs::{Srs, SrsParam};
+use agger_types::{CircuitConfig, DemoRunConfig, EntryFunctionConfig};
+use agger_vk_generation::gen_vks;
+use move_core_types::{account_address::AccountAddress, parser::parse_transaction_argument};
+use movelang::argument::ScriptArguments;
+use std::{sync::Arc, time::Duration};
+use test_helpers::compile_module_bytes;
+use threadpool::ThreadPool;
+use tokio::{sync::mpsc, time::timeout};
+
+const TEST_TIMEOUT: Duration = Duration::from_secs(600);
+
+const ADD_MODULE: &str = r#"
+module 0xcafe::adder {
+    public entry fun add(a: u64, b: u64) {
+        let _ = a + b;
+    }
+
+    public entry fun fail() {
+        abort 7
+    }
+}
+"#;
+
+fn query(function: &str, args: &[&str]) -> UserQuery {
+    UserQuery {
+        version: 1,
+        sequence_number: 0,
+        user: aptos_move_core_types::account_address::AccountAddress::ONE,
+        id: 0,
+        query: Query {
+            module_address: AccountAddress::from_hex_literal("0xcafe").unwrap().to_vec(),
+            module_name: b"adder".to_vec(),
+            function_name: function.as_bytes().to_vec(),
+            deadline: 0,
+            args: args.iter().map(|a| a.as_bytes().to_vec()).collect(),
+            ty_args: vec![],
+            success: None,
+            result: None,
+        },
+        read_at_latest: None,
+    }
+}
+
+/// verification parameters of `add` generated from its demo run, as registered.
+fn generated_parameters(srs: &Srs, module: Vec<u8>) -> VerificationParameters {
+    let args = ["1u64", "2u64"]
+        .iter()
+        .map(|a| parse_transaction_argument(a).unwrap())
+        .collect();
+    let vks = gen_vks(srs, vec![module], vec![EntryFunctionConfig {
+        entry_module_address: "0xcafe".to_string(),
+        entry_module_name: "adder".to_string(),
+        entry_function: "add".to_string(),
+        demo_run_config: DemoRunConfig {
+            args: Some(ScriptArguments::new(args)),
+            ty_args: None,
+        },
+        circuit_config: CircuitConfig::default(),
+    }])
+    .unwrap();
+    VerificationParameters {
+        config: vks[0].config.clone(),
+        param: vks[0].param.clone(),
+        vk: vks[0].vk.clone(),
+        instance_layout: vks[0].instance_layout.clone(),
+    }
+}
+
+/// parameters good enough to execute, without a generated vk.
+fn execution_parameters() -> VerificationParameters {
+    VerificationParameters {
+        config: bcs::to_bytes(&zkmove_vm_circuit::witness::CircuitConfig::default()).unwrap(),
+        param: SrsParam {
+            k: 10,
+            srs_hash: vec![],
+        }
+        .encode(),
+        vk: vec![],
+        instance_layout: vec![],
+    }
+}
+
+/// run `query` through a dispatcher proving with `prover`.
+async fn run(
+    prover: Arc<dyn Prover>,
+    query: UserQuery,
+    module: Vec<u8>,
+    verification_parameters: VerificationParameters,
+) -> QueryOutput {
+    let (task_sender, task_receiver) = mpsc::channel(1);
+    let (output_sender, mut output_receiver) = mpsc::channel(1);
+    let dispatcher = ProvingTaskDispatcher::new(
+        ThreadPool::new(1),
+        ThreadPool::new(1),
+        1,
+        None,
+        task_receiver,
+        output_sender,
+        prover,
+    );
+    tokio::spawn(dispatcher.run());
+    task_sender
+        .send(ProveTask {
+            query,
+            modules: vec![module],
+            verification_parameters,
+            read_set: ReadSet::default(),
+        })
+        .await
+        .unwrap();
+    timeout(TEST_TIMEOUT, output_receiver.recv())
+        .await
+        .unwrap()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_mock_prover_checks_circuit() {
+    let srs = Srs::insecure_dev();
+    let module = compile_module_bytes(ADD_MODULE);
+    let verification_parameters = generated_parameters(&srs, module.clone());
+
+    let (_, output) = run(
+        Arc::new(MockCircuitProver),
+        query("add", &["3u64", "4u64"]),
+        module,
+        verification_parameters.clone(),
+    )
+    .await;
+    // constraints are satisfied, but there is no proof to reply.
+    assert!(output.unwrap().proof.is_empty());
+
+    // circuits are only checked on witness.
+    assert!(MockCircuitProver
+        .prove(None, &[], &verification_parameters)
+        .is_err());
+}
+
+#[tokio::test]
+async fn test_execute_only_prover_skips_witness() {
+    assert!(!ExecuteOnlyProver.requires_witness());
+    let module = compile_module_bytes(ADD_MODULE);
+
+    let (_, output) = run(
+        Arc::new(ExecuteOnlyProver),
+        query("add", &["3u64", "4u64"]),
+        module.clone(),
+        execution_parameters(),
+    )
+    .await;
+    assert!(output.unwrap().proof.is_empty());
+
+    // execution failures are still replied as such.
+    let (_, output) = run(
+        Arc::new(ExecuteOnlyProver),
+        query("fail", &[]),
+        module,
+        execution_parameters(),
+    )
+    .await;
+    let e = output.unwrap_err();
+    let failure = e.downcast_ref::<ExecutionFailure>().unwrap();
+    assert_eq!(failure.abort_code, Some(7));
+}
+
+#[tokio::test]
+async fn test_kzg_prover_requires_witness() {
+    let srs = Arc::new(Srs::insecure_dev());
+    let module = compile_module_bytes(ADD_MODULE);
+    let verification_parameters = generated_parameters(&srs, module);
+    let prover = KzgProver::new(Arc::new(ProvingKeyCache::new(1, srs)));
+
+    assert!(prover.requires_witness());
+    assert!(prover.prove(None, &[], &verification_parameters).is_err());
+    assert!(prover
+        .prove_batch(vec![(None, vec![])], &verification_parameters)
+        .is_err());
+}
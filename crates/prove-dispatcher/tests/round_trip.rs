@@ -0,0 +1,173 @@
+use agger_contract_types::{
+    read_set_hash, Query, QueryResult, ReadSet, UserQuery, VerificationParameters,
+};
+use agger_prove_dispatcher::{
+    KzgProver, ProveTask, ProvingKeyCache, ProvingTaskDispatcher, QueryOutput,
+};
+use agger_srs::Srs;
+use agger_types::{CircuitConfig, DemoRunConfig, EntryFunctionConfig};
+use agger_verifier::{entry_function_index, AggerVerifier};
+use agger_vk_generation::gen_vks;
+use move_core_types::{account_address::AccountAddress, parser::parse_transaction_argument};
+use movelang::argument::ScriptArguments;
+use std::{sync::Arc, time::Duration};
+use test_helpers::compile_module_bytes;
+use threadpool::ThreadPool;
+use tokio::{sync::mpsc, time::timeout};
+
+const TEST_TIMEOUT: Duration = Duration::from_secs(600);
+
+const ADD_MODULE: &str = r#"
+module 0xcafe::adder {
+    public entry fun noop() {}
+
+    public entry fun add(a: u64, b: u64) {
+        let _ = a + b;
+    }
+}
+"#;
+
+fn args(args: &[&str]) -> ScriptArguments {
+    ScriptArguments::new(
+        args.iter()
+            .map(|a| parse_transaction_argument(a).unwrap())
+            .collect(),
+    )
+}
+
+/// what `registry::register_module` stores of the vk-generation output:
+/// the function index is popped off the registered vk, high byte first.
+fn register(vp: &agger_types::VerificationParameters) -> (u16, VerificationParameters) {
+    let mut vk = vp.registered_vk();
+    let hi = vk.pop().unwrap() as u16;
+    let lo = vk.pop().unwrap() as u16;
+    let registered = VerificationParameters {
+        config: vp.config.clone(),
+        param: vp.param.clone(),
+        vk,
+        instance_layout: vp.instance_layout.clone(),
+    };
+    (lo + (hi << 8), registered)
+}
+
+fn query(id: u64, a: u64, b: u64) -> UserQuery {
+    UserQuery {
+        version: 1,
+        sequence_number: id,
+        user: aptos_move_core_types::account_address::AccountAddress::ONE,
+        id,
+        query: Query {
+            module_address: AccountAddress::from_hex_literal("0xcafe").unwrap().to_vec(),
+            module_name: b"adder".to_vec(),
+            function_name: b"add".to_vec(),
+            deadline: 0,
+            args: vec![
+                format!("{}u64", a).into_bytes(),
+                format!("{}u64", b).into_bytes(),
+            ],
+            ty_args: vec![],
+            success: None,
+            result: None,
+        },
+        read_at_latest: None,
+    }
+}
+
+#[tokio::test]
+async fn test_prove_and_verify_registered_function() {
+    let srs = Arc::new(Srs::insecure_dev());
+    let module = compile_module_bytes(ADD_MODULE);
+    let vks = gen_vks(&srs, vec![module.clone()], vec![EntryFunctionConfig {
+        entry_module_address: "0xcafe".to_string(),
+        entry_module_name: "adder".to_string(),
+        entry_function: "add".to_string(),
+        demo_run_config: DemoRunConfig {
+            args: Some(args(&["1u64", "2u64"])),
+            ty_args: None,
+        },
+        circuit_config: CircuitConfig::default(),
+    }])
+    .unwrap();
+    let (registered_index, verification_parameters) = register(&vks[0]);
+    // `add` is not the first function of the module.
+    assert_eq!(registered_index, 1);
+    assert_eq!(verification_parameters.vk, vks[0].vk);
+
+    let keys = Arc::new(ProvingKeyCache::new(1, srs.clone()));
+    let (task_sender, task_receiver) = mpsc::channel(1);
+    let (output_sender, mut output_receiver) = mpsc::channel::<QueryOutput>(1);
+    let dispatcher = ProvingTaskDispatcher::new(
+        ThreadPool::new(1),
+        ThreadPool::new(1),
+        1,
+        None,
+        task_receiver,
+        output_sender,
+        Arc::new(KzgProver::new(keys)),
+    );
+    tokio::spawn(dispatcher.run());
+    task_sender
+        .send(ProveTask {
+            query: query(0, 3, 4),
+            modules: vec![module.clone()],
+            verification_parameters: verification_parameters.clone(),
+            read_set: ReadSet::default(),
+        })
+        .await
+        .unwrap();
+    let (query, output) = timeout(TEST_TIMEOUT, output_receiver.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    let output = output.unwrap();
+
+    let function_index = entry_function_index(&module, &query.query).unwrap();
+    assert_eq!(function_index, registered_index);
+    let verifier = AggerVerifier::new(srs);
+    let result = output.result();
+    assert_eq!(result.read_set_hash, read_set_hash(&ReadSet::default()));
+    assert!(verifier
+        .verify(
+            &verification_parameters,
+            function_index,
+            &query,
+            &result,
+            &output.proof
+        )
+        .unwrap());
+    // public inputs are committed to by the proof, it doesn't verify with others.
+    // this doesn't show they are constrained to the execution, which they are not yet,
+    // see `QueryCircuit`.
+    // the proof doesn't verify as a proof of another function.
+    assert!(!verifier
+        .verify(&verification_parameters, 0, &query, &result, &output.proof)
+        .unwrap());
+    // nor with results claimed at another state version.
+    let other_version = QueryResult {
+        state_version: result.state_version + 1,
+        ..result.clone()
+    };
+    assert!(!verifier
+        .verify(
+            &verification_parameters,
+            function_index,
+            &query,
+            &other_version,
+            &output.proof
+        )
+        .unwrap());
+    // nor as a proof on other resources.
+    let other_read_set = QueryResult {
+        read_set_hash: vec![0; 32],
+        ..result
+    };
+    assert!(!verifier
+        .verify(
+            &verification_parameters,
+            function_index,
+            &query,
+            &other_read_set,
+            &output.proof
+        )
+        .unwrap());
+}
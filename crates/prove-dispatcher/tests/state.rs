@@ -0,0 +1,202 @@
+use agger_chain_source::{
+    replay::{RecordedResource, ReplayFixture, ReplaySource},
+    SourceResult, StateSource,
+};
+use agger_contract_types::{Query, ReadSet, UserQuery, VerificationParameters};
+use agger_prove_dispatcher::{
+    record_read_set, ExecuteOnlyProver, ProveTask, ProvingTaskDispatcher, QueryOutput,
+};
+use agger_srs::SrsParam;
+use async_trait::async_trait;
+use move_core_types::{
+    account_address::AccountAddress, language_storage::StructTag, parser::parse_struct_tag,
+};
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use test_helpers::compile_module_bytes;
+use threadpool::ThreadPool;
+use tokio::{sync::mpsc, time::timeout};
+use zkmove_vm_circuit::witness::CircuitConfig;
+
+const TEST_TIMEOUT: Duration = Duration::from_secs(30);
+const VERSION: u64 = 42;
+
+/// the counter is read at an address only known at runtime.
+const POINTER_MODULE: &str = r#"
+module 0xcafe::pointer {
+    struct Pointer has key { to: address }
+    struct Counter has key { value: u64 }
+
+    public entry fun read(owner: address) acquires Pointer, Counter {
+        let to = borrow_global<Pointer>(owner).to;
+        let _ = borrow_global<Counter>(to).value;
+    }
+}
+"#;
+
+/// state source counting the resources fetched from it.
+struct CountingSource {
+    inner: ReplaySource,
+    fetched: Mutex<Vec<(Vec<u8>, String, u64)>>,
+}
+
+#[async_trait]
+impl StateSource for CountingSource {
+    async fn get_resource(
+        &self,
+        address: Vec<u8>,
+        struct_tag: String,
+        version: u64,
+    ) -> SourceResult<Option<Vec<u8>>> {
+        self.fetched
+            .lock()
+            .unwrap()
+            .push((address.clone(), struct_tag.clone(), version));
+        self.inner.get_resource(address, struct_tag, version).await
+    }
+}
+
+fn address(a: &str) -> AccountAddress {
+    AccountAddress::from_hex_literal(a).unwrap()
+}
+
+fn struct_tag(s: &str) -> StructTag {
+    parse_struct_tag(s).unwrap()
+}
+
+fn resource(address: AccountAddress, struct_tag: &str, value: Vec<u8>) -> RecordedResource {
+    RecordedResource {
+        address: address.to_vec(),
+        // in the form the vm reads it.
+        struct_tag: self::struct_tag(struct_tag).to_string(),
+        version: 1,
+        value: Some(value),
+    }
+}
+
+fn query(owner: &str) -> UserQuery {
+    UserQuery {
+        version: VERSION,
+        sequence_number: 0,
+        user: aptos_move_core_types::account_address::AccountAddress::ONE,
+        id: 0,
+        query: Query {
+            module_address: address("0xcafe").to_vec(),
+            module_name: b"pointer".to_vec(),
+            function_name: b"read".to_vec(),
+            deadline: 0,
+            args: vec![owner.as_bytes().to_vec()],
+            ty_args: vec![],
+            success: None,
+            result: None,
+        },
+        read_at_latest: None,
+    }
+}
+
+fn verification_parameters() -> VerificationParameters {
+    VerificationParameters {
+        config: bcs::to_bytes(&CircuitConfig::default()).unwrap(),
+        param: SrsParam {
+            k: 10,
+            srs_hash: vec![],
+        }
+        .encode(),
+        vk: vec![],
+        instance_layout: vec![],
+    }
+}
+
+/// execute `read_set` through the dispatcher, without proving.
+async fn execute(module: Vec<u8>, read_set: ReadSet) -> QueryOutput {
+    let (task_sender, task_receiver) = mpsc::channel(1);
+    let (output_sender, mut output_receiver) = mpsc::channel(1);
+    let dispatcher = ProvingTaskDispatcher::new(
+        ThreadPool::new(1),
+        ThreadPool::new(1),
+        1,
+        None,
+        task_receiver,
+        output_sender,
+        Arc::new(ExecuteOnlyProver),
+    );
+    tokio::spawn(dispatcher.run());
+    task_sender
+        .send(ProveTask {
+            query: query("0xb0b"),
+            modules: vec![module],
+            verification_parameters: verification_parameters(),
+            read_set,
+        })
+        .await
+        .unwrap();
+    timeout(TEST_TIMEOUT, output_receiver.recv())
+        .await
+        .unwrap()
+        .unwrap()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_read_set_follows_runtime_addresses() {
+    let module = compile_module_bytes(POINTER_MODULE);
+    let source = Arc::new(CountingSource {
+        inner: ReplaySource::new(ReplayFixture {
+            resources: vec![
+                resource(
+                    address("0xb0b"),
+                    "0xcafe::pointer::Pointer",
+                    bcs::to_bytes(&address("0xa11ce")).unwrap(),
+                ),
+                resource(
+                    address("0xa11ce"),
+                    "0xcafe::pointer::Counter",
+                    bcs::to_bytes(&7u64).unwrap(),
+                ),
+            ],
+            ..Default::default()
+        }),
+        fetched: Mutex::new(vec![]),
+    });
+
+    let read_set = record_read_set(
+        query("0xb0b"),
+        vec![module.clone()],
+        &verification_parameters(),
+        source.clone(),
+        VERSION,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(read_set.version, VERSION);
+    let reads: Vec<_> = read_set
+        .resources
+        .iter()
+        .map(|r| (r.address.clone(), struct_tag(&r.struct_tag)))
+        .collect();
+    assert_eq!(reads, vec![
+        (
+            address("0xa11ce").to_vec(),
+            struct_tag("0xcafe::pointer::Counter")
+        ),
+        (
+            address("0xb0b").to_vec(),
+            struct_tag("0xcafe::pointer::Pointer")
+        ),
+    ]);
+    // each resource is fetched once, at the version of the query.
+    let fetched = source.fetched.lock().unwrap().clone();
+    assert_eq!(fetched.len(), 2);
+    assert!(fetched.iter().all(|(_, _, version)| *version == VERSION));
+
+    let (_, output) = execute(module.clone(), read_set.clone()).await;
+    assert!(output.is_ok());
+
+    // the replay fails if the read set misses a resource the execution reads.
+    let mut partial = read_set;
+    partial.resources.pop();
+    let (_, output) = execute(module, partial).await;
+    assert!(output.is_err());
+}
@@ -0,0 +1,110 @@
+use agger_prove_dispatcher::{
+    BatchConfig, ExecuteOnlyProver, KzgProver, MemoryBudget, MockCircuitProver, ProveError,
+    ProveTask, Prover, ProvingKeyCache, ProvingTaskDispatcher, QueryOutput, EXECUTES_QUERIES,
+};
+use agger_srs::Srs;
+use anyhow::Result;
+use clap::{Args, ValueEnum};
+use std::{path::PathBuf, sync::Arc, time::Duration};
+use tokio::sync::mpsc::{Receiver, Sender};
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum ProverBackend {
+    /// kzg proofs of the registered vk.
+    Kzg,
+    /// check circuit constraints with halo2 mock prover, without proofs. for dev and test.
+    Mock,
+    /// only execute queries, without witness and proofs. for dev and test.
+    ExecuteOnly,
+}
+
+/// Options of proving queries locally, shared by the node and prover workers.
+#[derive(Args, Clone, Debug)]
+pub struct ProverArgs {
+    /// max number of functions whose proving keys are cached in memory.
+    #[arg(long, default_value_t = 16)]
+    pub pk_cache_size: usize,
+    /// persist proving keys into the dir,
+    /// so that they are not setup again after eviction or restart.
+    #[arg(long)]
+    pub pk_cache_dir: Option<PathBuf>,
+    /// kzg srs file in halo2 format, the one verification parameters are generated from.
+    #[arg(long)]
+    pub srs: Option<PathBuf>,
+    /// number of threads proving queries, each proof takes a lot of memory.
+    /// it can be raised along with a memory budget, so that small circuits prove in parallel.
+    #[arg(long, default_value_t = 1)]
+    pub prover_threads: usize,
+    /// max number of witnessed queries waiting to be proved.
+    #[arg(long, default_value_t = 4)]
+    pub witness_queue_size: usize,
+    /// memory in MiB concurrent proofs may take, estimated from the k of their circuit.
+    /// proofs are only limited by prover threads if not set.
+    #[arg(long)]
+    pub memory_budget_mib: Option<u32>,
+    /// backend proving queries.
+    #[arg(long, value_enum, default_value_t = ProverBackend::Kzg)]
+    pub prover: ProverBackend,
+    /// aggregate proofs of queries of the same function witnessed within the window,
+    /// so that they are replied in one transaction. 0 to prove each query alone.
+    /// queries of different functions are never aggregated, see `BatchConfig`.
+    #[arg(long, default_value_t = 0)]
+    pub batch_window_ms: u64,
+    /// max number of queries aggregated in one proof.
+    #[arg(long, default_value_t = 8)]
+    pub max_batch_size: usize,
+}
+
+impl ProverArgs {
+    pub fn prover(&self) -> Result<Arc<dyn Prover>> {
+        // refuse to start rather than reply every query with failure.
+        if !EXECUTES_QUERIES {
+            return Err(ProveError::UnsupportedVm.into());
+        }
+        let prover: Arc<dyn Prover> = match self.prover {
+            ProverBackend::Kzg => {
+                let srs = Arc::new(Srs::load_or_dev(self.srs.as_ref())?);
+                let mut proving_keys = ProvingKeyCache::new(self.pk_cache_size, srs);
+                if let Some(dir) = &self.pk_cache_dir {
+                    proving_keys = proving_keys.with_dir(dir)?;
+                }
+                Arc::new(KzgProver::new(Arc::new(proving_keys)))
+            },
+            ProverBackend::Mock => Arc::new(MockCircuitProver),
+            ProverBackend::ExecuteOnly => Arc::new(ExecuteOnlyProver),
+        };
+        Ok(prover)
+    }
+
+    pub fn dispatcher(
+        &self,
+        prover: Arc<dyn Prover>,
+        task_receiver: Receiver<ProveTask>,
+        output_sender: Sender<QueryOutput>,
+    ) -> ProvingTaskDispatcher {
+        // execution and witness generation are light, use all cores by default.
+        let witness_threadpool = threadpool::Builder::new()
+            .thread_name("witnesses".to_string())
+            .build();
+        let prover_threadpool = threadpool::Builder::new()
+            .thread_name("provers".to_string())
+            .num_threads(self.prover_threads.max(1))
+            .build();
+        let dispatcher = ProvingTaskDispatcher::new(
+            witness_threadpool,
+            prover_threadpool,
+            self.witness_queue_size,
+            self.memory_budget_mib.map(MemoryBudget::new),
+            task_receiver,
+            output_sender,
+            prover,
+        );
+        match self.batch_window_ms {
+            0 => dispatcher,
+            window => dispatcher.with_batching(BatchConfig {
+                window: Duration::from_millis(window),
+                max_size: self.max_batch_size,
+            }),
+        }
+    }
+}
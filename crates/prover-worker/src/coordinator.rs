@@ -0,0 +1,307 @@
+use crate::protocol::{from_failure, read_frame, write_frame, CoordinatorMessage, WorkerMessage};
+use agger_contract_types::UserQuery;
+use agger_metrics::{ACTIVE_WORKERS, QUEUE_DEPTH};
+use agger_prove_dispatcher::{ProveOutput, ProveTask, QueryOutput};
+use anyhow::{bail, Result};
+use std::{
+    collections::{HashMap, VecDeque},
+    net::SocketAddr,
+    time::Duration,
+};
+use tokio::{
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+    select,
+    sync::mpsc::{self, Receiver, Sender, UnboundedSender},
+    time::timeout,
+};
+use tracing::{error, info, warn};
+
+/// workers are considered lost if nothing is heard from them for this long.
+const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(30);
+
+type WorkerId = u64;
+/// user and id of a query.
+type QueryKey = (Vec<u8>, u64);
+
+fn query_key(query: &UserQuery) -> QueryKey {
+    (query.user.to_vec(), query.id)
+}
+
+enum Event {
+    Connected(WorkerId, SocketAddr, UnboundedSender<ProveTask>),
+    Pull(WorkerId, u32),
+    Output(WorkerId, QueryOutput),
+    Lost(WorkerId),
+}
+
+struct Worker {
+    addr: SocketAddr,
+    /// number of tasks the worker pulled but not sent yet.
+    credits: u32,
+    /// tasks sent to the worker, re-queued if it's lost.
+    in_flight: HashMap<QueryKey, ProveTask>,
+    /// outputs of aggregated proofs, held by batch id until the whole batch is in,
+    /// so that a batch partly output by a lost worker is re-queued as a whole.
+    batches: HashMap<Vec<u8>, Vec<QueryOutput>>,
+    tasks: UnboundedSender<ProveTask>,
+}
+
+/// Tasks waiting for workers, and tasks being proved by each worker.
+#[derive(Default)]
+struct Scheduler {
+    pending: VecDeque<ProveTask>,
+    workers: HashMap<WorkerId, Worker>,
+}
+
+impl Scheduler {
+    /// send pending tasks to workers with credits, the least loaded first.
+    fn dispatch(&mut self) {
+        while !self.pending.is_empty() {
+            let Some(worker) = self
+                .workers
+                .values_mut()
+                .filter(|w| w.credits > 0)
+                .max_by_key(|w| w.credits)
+            else {
+                break;
+            };
+            let task = self
+                .pending
+                .pop_front()
+                .expect("pending tasks is not empty");
+            worker.credits -= 1;
+            worker
+                .in_flight
+                .insert(query_key(&task.query), task.clone());
+            // if the connection is closing, the task is re-queued once it's lost.
+            let _ = worker.tasks.send(task);
+        }
+    }
+
+    /// outputs to forward, those of a batch are forwarded together once all of them are in.
+    fn output(&mut self, id: WorkerId, output: QueryOutput) -> Vec<QueryOutput> {
+        let Some(worker) = self.workers.get_mut(&id) else {
+            return vec![];
+        };
+        let key = query_key(&output.0);
+        if !worker.in_flight.contains_key(&key) {
+            warn!(
+                parent: &output.0.span(),
+                "worker {} returned a query which is not sent to it",
+                worker.addr
+            );
+            return vec![];
+        }
+        let batch = match &output.1 {
+            Ok(ProveOutput {
+                batch: Some(batch), ..
+            }) => batch.clone(),
+            _ => {
+                worker.in_flight.remove(&key);
+                return vec![output];
+            },
+        };
+        // workers output no duplicates, the deduplicator adds them after the coordinator.
+        let outputs = worker.batches.entry(batch.id.clone()).or_default();
+        outputs.push(output);
+        if outputs.len() < batch.size as usize {
+            return vec![];
+        }
+        let outputs = worker.batches.remove(&batch.id).expect("batch is held");
+        for (query, _) in &outputs {
+            worker.in_flight.remove(&query_key(query));
+        }
+        outputs
+    }
+
+    /// re-queue tasks of the lost worker before others, as they are the oldest.
+    fn lost(&mut self, id: WorkerId) {
+        if let Some(worker) = self.workers.remove(&id) {
+            ACTIVE_WORKERS.dec();
+            // tasks of held outputs are still in flight, their batches are proved again.
+            warn!(
+                "worker {} is lost, re-queue {} tasks, drop {} incomplete batches",
+                worker.addr,
+                worker.in_flight.len(),
+                worker.batches.len()
+            );
+            let mut tasks: Vec<_> = worker.in_flight.into_values().collect();
+            tasks.sort_by_key(|t| t.query.sequence_number);
+            for task in tasks.into_iter().rev() {
+                self.pending.push_front(task);
+            }
+        }
+    }
+
+    fn is_idle(&self) -> bool {
+        self.pending.is_empty() && self.workers.values().all(|w| w.in_flight.is_empty())
+    }
+}
+
+/// Coordinator dispatching tasks to prover workers connected over tcp.
+/// Tasks of lost workers are re-queued and proved by other workers,
+/// along with the rest of the batches they proved but didn't output in full.
+pub struct Coordinator {
+    listener: TcpListener,
+    heartbeat_timeout: Duration,
+}
+
+impl Coordinator {
+    pub async fn bind(addr: impl ToSocketAddrs) -> Result<Self> {
+        let listener = TcpListener::bind(addr).await?;
+        info!("coordinator listening on {}", listener.local_addr()?);
+        Ok(Self {
+            listener,
+            heartbeat_timeout: HEARTBEAT_TIMEOUT,
+        })
+    }
+
+    pub fn with_heartbeat_timeout(mut self, heartbeat_timeout: Duration) -> Self {
+        self.heartbeat_timeout = heartbeat_timeout;
+        self
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// run until tasks are closed and all of them are proved, or outputs are closed.
+    pub async fn run(
+        self,
+        mut task_receiver: Receiver<ProveTask>,
+        output_sender: Sender<QueryOutput>,
+    ) {
+        let (event_sender, mut events) = mpsc::channel(64);
+        let accept = tokio::spawn(accept(self.listener, event_sender, self.heartbeat_timeout));
+        let mut scheduler = Scheduler::default();
+        let mut tasks_closed = false;
+        'events: while !(tasks_closed && scheduler.is_idle()) {
+            let event = select! {
+                task = task_receiver.recv(), if !tasks_closed => {
+                    match task {
+                        Some(task) => {
+                            scheduler.pending.push_back(task);
+                            scheduler.dispatch();
+                            QUEUE_DEPTH
+                                .with_label_values(&["coordinator"])
+                                .set(scheduler.pending.len() as i64);
+                        }
+                        None => tasks_closed = true,
+                    }
+                    continue;
+                }
+                event = events.recv() => event,
+            };
+            let Some(event) = event else {
+                error!("coordinator stops accepting workers");
+                break;
+            };
+            match event {
+                Event::Connected(id, addr, tasks) => {
+                    info!("worker {} connected", addr);
+                    ACTIVE_WORKERS.inc();
+                    scheduler.workers.insert(id, Worker {
+                        addr,
+                        credits: 0,
+                        in_flight: HashMap::new(),
+                        batches: HashMap::new(),
+                        tasks,
+                    });
+                },
+                Event::Pull(id, n) => {
+                    if let Some(worker) = scheduler.workers.get_mut(&id) {
+                        worker.credits = worker.credits.saturating_add(n);
+                    }
+                },
+                Event::Output(id, output) => {
+                    for output in scheduler.output(id, output) {
+                        if let Err(_output) = output_sender.send(output).await {
+                            // output receiver is gone, stop dispatching
+                            break 'events;
+                        }
+                    }
+                },
+                Event::Lost(id) => scheduler.lost(id),
+            }
+            scheduler.dispatch();
+            QUEUE_DEPTH
+                .with_label_values(&["coordinator"])
+                .set(scheduler.pending.len() as i64);
+        }
+        accept.abort();
+        info!("coordinator is closed");
+    }
+}
+
+async fn accept(listener: TcpListener, events: Sender<Event>, heartbeat_timeout: Duration) {
+    let mut next_id: WorkerId = 0;
+    loop {
+        match listener.accept().await {
+            Ok((stream, addr)) => {
+                next_id += 1;
+                tokio::spawn(serve_worker(
+                    next_id,
+                    stream,
+                    addr,
+                    events.clone(),
+                    heartbeat_timeout,
+                ));
+            },
+            Err(e) => {
+                error!("accept worker error. {:?}", e);
+            },
+        }
+    }
+}
+
+async fn serve_worker(
+    id: WorkerId,
+    stream: TcpStream,
+    addr: SocketAddr,
+    events: Sender<Event>,
+    heartbeat_timeout: Duration,
+) {
+    let (mut reader, mut writer) = stream.into_split();
+    let (task_sender, mut task_receiver) = mpsc::unbounded_channel();
+    if events
+        .send(Event::Connected(id, addr, task_sender))
+        .await
+        .is_err()
+    {
+        return;
+    }
+    let write = async {
+        while let Some(task) = task_receiver.recv().await {
+            write_frame(&mut writer, &CoordinatorMessage::Task(task)).await?;
+        }
+        anyhow::Ok(())
+    };
+    let read = async {
+        loop {
+            let message = match timeout(heartbeat_timeout, read_frame(&mut reader)).await {
+                Ok(message) => message?,
+                Err(_) => bail!("no heartbeat in {:?}", heartbeat_timeout),
+            };
+            let event = match message {
+                Some(WorkerMessage::Pull(n)) => Event::Pull(id, n),
+                Some(WorkerMessage::Heartbeat) => continue,
+                Some(WorkerMessage::Output(query, result)) => {
+                    Event::Output(id, (query, result.map_err(from_failure)))
+                },
+                None => return Ok(()),
+            };
+            if events.send(event).await.is_err() {
+                return Ok(());
+            }
+        }
+    };
+    let result = select! {
+        result = write => result,
+        result = read => result,
+    };
+    match result {
+        Ok(()) => info!("worker {} disconnected", addr),
+        Err(e) => warn!("worker {} connection error. {:?}", addr, e),
+    }
+    let _ = events.send(Event::Lost(id)).await;
+}
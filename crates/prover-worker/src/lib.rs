@@ -0,0 +1,12 @@
+//! Proving queries in standalone worker processes.
+//! The node runs a `Coordinator`, and each `agger-prover-worker` connects to it over tcp,
+//! pulls tasks into its local dispatcher, and returns their outputs.
+
+mod args;
+mod coordinator;
+pub mod protocol;
+mod worker;
+
+pub use args::{ProverArgs, ProverBackend};
+pub use coordinator::Coordinator;
+pub use worker::ProverWorker;
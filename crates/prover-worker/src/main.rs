@@ -0,0 +1,52 @@
+use agger_metrics::serve_metrics;
+use agger_prover_worker::{ProverArgs, ProverWorker};
+use clap::Parser;
+use log_helpers::{init_logger, LogFormat};
+use std::net::SocketAddr;
+use tokio::sync::mpsc;
+use tracing::{error, info};
+
+/// Worker proving queries for an agger node.
+#[derive(Parser, Debug)]
+struct Cli {
+    /// address the node listens on for workers.
+    #[arg(long)]
+    coordinator: String,
+    /// max number of tasks pulled at once.
+    #[arg(long, default_value_t = 2)]
+    capacity: u32,
+    /// serve prometheus metrics at `/metrics` of the address.
+    #[arg(long)]
+    metrics_addr: Option<SocketAddr>,
+    /// json for log pipelines.
+    #[arg(long, value_enum, default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
+    #[command(flatten)]
+    prover: ProverArgs,
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+    init_logger(cli.log_format)?;
+    info!("cmd: {:?}", &cli);
+    if let Some(addr) = cli.metrics_addr {
+        tokio::spawn(async move {
+            if let Err(e) = serve_metrics(addr).await {
+                error!("metrics server error. {:?}", e);
+            }
+        });
+    }
+    let prover = cli.prover.prover()?;
+    let (task_sender, task_receiver) = mpsc::channel(cli.capacity.max(1) as usize);
+    let (output_sender, output_receiver) = mpsc::channel(cli.capacity.max(1) as usize);
+    let dispatcher = cli.prover.dispatcher(prover, task_receiver, output_sender);
+    let dispatcher = tokio::spawn(dispatcher.run());
+    ProverWorker::new(cli.capacity)
+        .run(cli.coordinator, task_sender, output_receiver)
+        .await?;
+    // tasks held by the worker are re-queued by the coordinator, no need to wait them.
+    dispatcher.abort();
+    info!("agger prover worker stopped");
+    Ok(())
+}
@@ -0,0 +1,72 @@
+//! Messages between the coordinator and prover workers.
+//! Each message is bcs serialized, and framed with its length in u32 little endian.
+
+use agger_contract_types::{ExecutionFailure, QueryFailure, UserQuery};
+use agger_prove_dispatcher::{ProveOutput, ProveTask};
+use anyhow::{anyhow, ensure, Result};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::io::ErrorKind;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// tasks carry modules, and outputs carry proofs, which are far smaller than this.
+const MAX_FRAME_SIZE: u32 = 256 << 20;
+
+#[derive(Debug, Deserialize, Serialize)]
+pub enum CoordinatorMessage {
+    Task(ProveTask),
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub enum WorkerMessage {
+    /// ask for more tasks, the worker never holds more than it pulled.
+    Pull(u32),
+    /// the worker is alive, sent periodically even when it is busy proving.
+    Heartbeat,
+    Output(UserQuery, Result<ProveOutput, QueryFailure>),
+}
+
+pub async fn write_frame<T: Serialize>(
+    writer: &mut (impl AsyncWrite + Unpin),
+    message: &T,
+) -> Result<()> {
+    let data = bcs::to_bytes(message)?;
+    ensure!(
+        data.len() <= MAX_FRAME_SIZE as usize,
+        "frame of {} bytes is too large",
+        data.len()
+    );
+    writer.write_u32_le(data.len() as u32).await?;
+    writer.write_all(&data).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+/// read a frame, or none if the peer closed the connection.
+pub async fn read_frame<T: DeserializeOwned>(
+    reader: &mut (impl AsyncRead + Unpin),
+) -> Result<Option<T>> {
+    let len = match reader.read_u32_le().await {
+        Ok(len) => len,
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    ensure!(len <= MAX_FRAME_SIZE, "frame of {} bytes is too large", len);
+    let mut data = vec![0; len as usize];
+    reader.read_exact(&mut data).await?;
+    Ok(Some(bcs::from_bytes(&data)?))
+}
+
+/// failure to send back to the coordinator, the same way it is stored.
+pub fn to_failure(e: &anyhow::Error) -> QueryFailure {
+    match e.downcast_ref::<ExecutionFailure>() {
+        Some(f) => QueryFailure::Execution(f.clone()),
+        None => QueryFailure::Message(e.root_cause().to_string()),
+    }
+}
+
+pub fn from_failure(failure: QueryFailure) -> anyhow::Error {
+    match failure {
+        QueryFailure::Execution(f) => f.into(),
+        QueryFailure::Message(m) => anyhow!(m),
+    }
+}
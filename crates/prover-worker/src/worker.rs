@@ -0,0 +1,83 @@
+use crate::protocol::{read_frame, to_failure, write_frame, CoordinatorMessage, WorkerMessage};
+use agger_prove_dispatcher::{ProveTask, QueryOutput};
+use anyhow::{bail, Result};
+use std::time::Duration;
+use tokio::{
+    net::{TcpStream, ToSocketAddrs},
+    select,
+    sync::mpsc::{Receiver, Sender},
+    time::interval,
+};
+use tracing::info;
+
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Worker pulling tasks from a coordinator into a local dispatcher,
+/// and returning outputs of the dispatcher to the coordinator.
+pub struct ProverWorker {
+    /// max number of tasks the worker holds at once.
+    capacity: u32,
+    heartbeat_interval: Duration,
+}
+
+impl ProverWorker {
+    pub fn new(capacity: u32) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            heartbeat_interval: HEARTBEAT_INTERVAL,
+        }
+    }
+
+    pub fn with_heartbeat_interval(mut self, heartbeat_interval: Duration) -> Self {
+        self.heartbeat_interval = heartbeat_interval;
+        self
+    }
+
+    /// run until the coordinator or the local dispatcher is gone.
+    /// tasks held by the worker are re-queued by the coordinator when it stops.
+    pub async fn run(
+        self,
+        coordinator: impl ToSocketAddrs,
+        task_sender: Sender<ProveTask>,
+        mut output_receiver: Receiver<QueryOutput>,
+    ) -> Result<()> {
+        let stream = TcpStream::connect(coordinator).await?;
+        info!("connected to coordinator {}", stream.peer_addr()?);
+        let (mut reader, mut writer) = stream.into_split();
+        // tasks are read apart from writing, as reading frames cannot be cancelled halfway.
+        let mut read = tokio::spawn(async move {
+            while let Some(CoordinatorMessage::Task(task)) = read_frame(&mut reader).await? {
+                info!(parent: &task.query.span(), "pulled query task");
+                if task_sender.send(task).await.is_err() {
+                    bail!("prover dispatcher is down");
+                }
+            }
+            anyhow::Ok(())
+        });
+
+        write_frame(&mut writer, &WorkerMessage::Pull(self.capacity)).await?;
+        let mut heartbeat = interval(self.heartbeat_interval);
+        loop {
+            select! {
+                _ = heartbeat.tick() => {
+                    write_frame(&mut writer, &WorkerMessage::Heartbeat).await?;
+                }
+                output = output_receiver.recv() => {
+                    let Some((query, result)) = output else {
+                        break;
+                    };
+                    let result = result.map_err(|e| to_failure(&e));
+                    write_frame(&mut writer, &WorkerMessage::Output(query, result)).await?;
+                    write_frame(&mut writer, &WorkerMessage::Pull(1)).await?;
+                }
+                result = &mut read => {
+                    result??;
+                    info!("coordinator closed the connection");
+                    break;
+                }
+            }
+        }
+        read.abort();
+        Ok(())
+    }
+}
@@ -0,0 +1,240 @@
+use agger_contract_types::{
+    ExecutionFailure, Query, QueryFailure, ReadSet, UserQuery, VerificationParameters,
+};
+use agger_prove_dispatcher::{ProofBatch, ProveOutput, ProveTask, QueryOutput};
+use agger_prover_worker::{
+    protocol::{read_frame, to_failure, write_frame, CoordinatorMessage, WorkerMessage},
+    Coordinator, ProverWorker,
+};
+use aptos_move_core_types::account_address::AccountAddress;
+use std::{collections::HashSet, net::SocketAddr, time::Duration};
+use tokio::{
+    net::TcpStream,
+    sync::mpsc::{self, Receiver, Sender},
+    time::timeout,
+};
+
+const TEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+fn task(id: u64) -> ProveTask {
+    ProveTask {
+        query: UserQuery {
+            version: 1,
+            sequence_number: id,
+            user: AccountAddress::ONE,
+            id,
+            query: Query {
+                module_address: AccountAddress::ONE.to_vec(),
+                module_name: b"m".to_vec(),
+                function_name: b"f".to_vec(),
+                deadline: 0,
+                args: vec![id.to_string().into_bytes()],
+                ty_args: vec![],
+                success: None,
+                result: None,
+            },
+            read_at_latest: None,
+        },
+        modules: vec![],
+        verification_parameters: VerificationParameters {
+            config: vec![],
+            param: vec![],
+            vk: vec![],
+            instance_layout: vec![],
+        },
+        read_set: ReadSet::default(),
+    }
+}
+
+/// stands in for the local dispatcher of a worker, odd queries fail to execute.
+fn fake_dispatcher() -> (Sender<ProveTask>, Receiver<QueryOutput>) {
+    let (task_sender, mut task_receiver) = mpsc::channel::<ProveTask>(4);
+    let (output_sender, output_receiver) = mpsc::channel(4);
+    tokio::spawn(async move {
+        while let Some(task) = task_receiver.recv().await {
+            let result = if task.query.id % 2 == 0 {
+                Ok(ProveOutput {
+                    return_values: vec![task.query.id.to_le_bytes().to_vec()],
+                    events: vec![],
+                    state_version: task.query.version,
+                    read_set_hash: vec![],
+                    proof: vec![1, 2, 3],
+                    binding_hash: vec![],
+                    batch: None,
+                })
+            } else {
+                Err(ExecutionFailure {
+                    vm_status: "ABORTED".to_string(),
+                    status_code: 4016,
+                    abort_code: Some(task.query.id),
+                    location: None,
+                    message: None,
+                }
+                .into())
+            };
+            if output_sender.send((task.query, result)).await.is_err() {
+                break;
+            }
+        }
+    });
+    (task_sender, output_receiver)
+}
+
+fn spawn_worker(addr: SocketAddr, capacity: u32) {
+    let (task_sender, output_receiver) = fake_dispatcher();
+    tokio::spawn(
+        ProverWorker::new(capacity)
+            .with_heartbeat_interval(Duration::from_millis(100))
+            .run(addr, task_sender, output_receiver),
+    );
+}
+
+async fn start_coordinator() -> (SocketAddr, Sender<ProveTask>, Receiver<QueryOutput>) {
+    let coordinator = Coordinator::bind("127.0.0.1:0")
+        .await
+        .unwrap()
+        .with_heartbeat_timeout(Duration::from_secs(1));
+    let addr = coordinator.local_addr().unwrap();
+    let (task_sender, task_receiver) = mpsc::channel(64);
+    let (output_sender, output_receiver) = mpsc::channel(64);
+    tokio::spawn(coordinator.run(task_receiver, output_sender));
+    (addr, task_sender, output_receiver)
+}
+
+async fn collect_outputs(outputs: &mut Receiver<QueryOutput>, n: u64) -> HashSet<u64> {
+    let mut ids = HashSet::new();
+    for _ in 0..n {
+        let (query, result) = timeout(TEST_TIMEOUT, outputs.recv())
+            .await
+            .expect("outputs in time")
+            .expect("coordinator is running");
+        match result {
+            Ok(output) => {
+                assert_eq!(query.id % 2, 0);
+                assert_eq!(output.return_values, vec![query.id.to_le_bytes().to_vec()]);
+            },
+            Err(e) => {
+                assert_eq!(query.id % 2, 1);
+                // execution failures survive the round trip, so that they're replied the same.
+                match to_failure(&e) {
+                    QueryFailure::Execution(f) => assert_eq!(f.abort_code, Some(query.id)),
+                    QueryFailure::Message(m) => panic!("unexpected failure {}", m),
+                }
+            },
+        }
+        assert!(ids.insert(query.id), "query {} is output twice", query.id);
+    }
+    ids
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn several_workers_prove_all_tasks() {
+    let (addr, tasks, mut outputs) = start_coordinator().await;
+    for capacity in [1, 2, 3] {
+        spawn_worker(addr, capacity);
+    }
+    for id in 0..50 {
+        tasks.send(task(id)).await.unwrap();
+    }
+    let ids = collect_outputs(&mut outputs, 50).await;
+    assert_eq!(ids, (0..50).collect());
+
+    // coordinator stops once all tasks are proved.
+    drop(tasks);
+    assert!(timeout(TEST_TIMEOUT, outputs.recv())
+        .await
+        .unwrap()
+        .is_none());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn tasks_of_lost_workers_are_requeued() {
+    let (addr, tasks, mut outputs) = start_coordinator().await;
+    for id in 0..10 {
+        tasks.send(task(id)).await.unwrap();
+    }
+
+    // a worker pulls tasks and dies without returning them.
+    let mut lost = TcpStream::connect(addr).await.unwrap();
+    write_frame(&mut lost, &WorkerMessage::Pull(3))
+        .await
+        .unwrap();
+    for _ in 0..3 {
+        let message: Option<CoordinatorMessage> = timeout(TEST_TIMEOUT, read_frame(&mut lost))
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(matches!(message, Some(CoordinatorMessage::Task(_))));
+    }
+    drop(lost);
+
+    // a worker which pulls tasks but stops heartbeating is lost as well.
+    let mut silent = TcpStream::connect(addr).await.unwrap();
+    write_frame(&mut silent, &WorkerMessage::Pull(2))
+        .await
+        .unwrap();
+
+    spawn_worker(addr, 2);
+    spawn_worker(addr, 2);
+    let ids = collect_outputs(&mut outputs, 10).await;
+    assert_eq!(ids, (0..10).collect());
+    drop(silent);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn batches_of_lost_workers_are_requeued_whole() {
+    let (addr, tasks, mut outputs) = start_coordinator().await;
+    for id in 0..10 {
+        tasks.send(task(id * 2)).await.unwrap();
+    }
+
+    // a worker proves 3 queries in one batch, and dies after outputting 2 of them.
+    let mut lost = TcpStream::connect(addr).await.unwrap();
+    write_frame(&mut lost, &WorkerMessage::Pull(3))
+        .await
+        .unwrap();
+    let mut batched = vec![];
+    for _ in 0..3 {
+        let message: Option<CoordinatorMessage> = timeout(TEST_TIMEOUT, read_frame(&mut lost))
+            .await
+            .unwrap()
+            .unwrap();
+        let Some(CoordinatorMessage::Task(task)) = message else {
+            panic!("task is expected");
+        };
+        batched.push(task.query);
+    }
+    for (index, query) in batched.into_iter().take(2).enumerate() {
+        let output = ProveOutput {
+            return_values: vec![query.id.to_le_bytes().to_vec()],
+            events: vec![],
+            state_version: query.version,
+            read_set_hash: vec![],
+            proof: vec![4, 5, 6],
+            binding_hash: vec![],
+            batch: Some(ProofBatch {
+                id: vec![7],
+                size: 3,
+                instance_index: index as u32,
+                duplicate: false,
+            }),
+        };
+        write_frame(&mut lost, &WorkerMessage::Output(query, Ok(output)))
+            .await
+            .unwrap();
+    }
+    drop(lost);
+
+    // the incomplete batch is never output, all of its queries are proved again.
+    spawn_worker(addr, 2);
+    let mut ids = HashSet::new();
+    for _ in 0..10 {
+        let (query, result) = timeout(TEST_TIMEOUT, outputs.recv())
+            .await
+            .expect("outputs in time")
+            .expect("coordinator is running");
+        assert_eq!(result.unwrap().batch, None);
+        assert!(ids.insert(query.id), "query {} is output twice", query.id);
+    }
+    assert_eq!(ids, (0..10).map(|id| id * 2).collect());
+}
@@ -0,0 +1,241 @@
+use agger_chain_source::{
+    ModuleSource, SourceError, SourceResult, StateSource, VerificationParameters,
+};
+use agger_contract_types::{
+    AGGER_REGISTRY_FUNC_NAME_GET_MODULE, AGGER_REGISTRY_FUNC_NAME_GET_VERIFICATION_PARAMETERS,
+    AGGER_REGISTRY_MODULE_NAME,
+};
+use anyhow::anyhow;
+use aptos_client_pool::AptosClientPool;
+use aptos_sdk::{
+    move_types::identifier::Identifier as AptosIdentifier,
+    rest_client::{
+        aptos_api_types::{
+            AptosError, AptosErrorCode, EntryFunctionId, HexEncodedBytes, IdentifierWrapper,
+            MoveModuleId, ViewRequest,
+        },
+        error::{AptosErrorResponse, RestError},
+        Response,
+    },
+    types::account_address::AccountAddress as AptosAccountAddress,
+};
+use async_trait::async_trait;
+use serde::Deserialize;
+
+/// Read registered modules and zk parameters from agger registry on aptos.
+#[derive(Clone, Debug)]
+pub struct AptosModuleSource {
+    client: AptosClientPool,
+    agger_address: AptosAccountAddress,
+}
+
+impl AptosModuleSource {
+    pub fn new(client: AptosClientPool, agger_address: AptosAccountAddress) -> Self {
+        Self {
+            client,
+            agger_address,
+        }
+    }
+}
+
+#[async_trait]
+impl ModuleSource for AptosModuleSource {
+    async fn get_module(
+        &self,
+        module_address: Vec<u8>,
+        module_name: Vec<u8>,
+        version: u64,
+    ) -> SourceResult<Option<Vec<u8>>> {
+        let req = ViewRequest {
+            function: EntryFunctionId {
+                module: MoveModuleId {
+                    address: self.agger_address.into(),
+                    name: IdentifierWrapper(
+                        AptosIdentifier::new(AGGER_REGISTRY_MODULE_NAME).unwrap(),
+                    ),
+                },
+                name: IdentifierWrapper(
+                    AptosIdentifier::new(AGGER_REGISTRY_FUNC_NAME_GET_MODULE).unwrap(),
+                ),
+            },
+            type_arguments: vec![],
+            arguments: vec![
+                HexEncodedBytes(module_address).json().unwrap(),
+                HexEncodedBytes(module_name).json().unwrap(),
+            ],
+        };
+
+        let response = match self
+            .client
+            .call_at_version(version, |c| {
+                let req = &req;
+                async move { c.view(req, Some(version)).await }
+            })
+            .await
+        {
+            Ok(response) => response,
+            Err(e) if is_not_registered(&e) => return Ok(None),
+            Err(e) => return Err(into_source_error(e)),
+        };
+        decode_view_value(response, AGGER_REGISTRY_FUNC_NAME_GET_MODULE).map(Some)
+    }
+
+    async fn get_verification_parameters(
+        &self,
+        module_address: Vec<u8>,
+        module_name: Vec<u8>,
+        function_index: u16,
+        version: u64,
+    ) -> SourceResult<Option<VerificationParameters>> {
+        // read all parameters in one view call, so that they are from the same state.
+        // the module is read apart, see `AggerModuleResolver::get_vk_for_entry_function`.
+        let req = ViewRequest {
+            function: EntryFunctionId {
+                module: MoveModuleId {
+                    address: self.agger_address.into(),
+                    name: IdentifierWrapper(
+                        AptosIdentifier::new(AGGER_REGISTRY_MODULE_NAME).unwrap(),
+                    ),
+                },
+                name: IdentifierWrapper(
+                    AptosIdentifier::new(AGGER_REGISTRY_FUNC_NAME_GET_VERIFICATION_PARAMETERS)
+                        .unwrap(),
+                ),
+            },
+            type_arguments: vec![],
+            arguments: vec![
+                HexEncodedBytes(module_address).json().unwrap(),
+                HexEncodedBytes(module_name).json().unwrap(),
+                serde_json::to_value(function_index).unwrap(),
+            ],
+        };
+
+        let response = match self
+            .client
+            .call_at_version(version, |c| {
+                let req = &req;
+                async move { c.view(req, Some(version)).await }
+            })
+            .await
+        {
+            Ok(response) => response,
+            Err(e) if is_not_registered(&e) => return Ok(None),
+            Err(e) => return Err(into_source_error(e)),
+        };
+        let value = response.into_inner().pop().ok_or_else(|| {
+            SourceError::Decode(anyhow!(
+                "view {} returns nothing",
+                AGGER_REGISTRY_FUNC_NAME_GET_VERIFICATION_PARAMETERS
+            ))
+        })?;
+        let VerificationParametersView {
+            config,
+            param,
+            vk,
+            instance_layout,
+        } = serde_json::from_value(value).map_err(|e| SourceError::Decode(e.into()))?;
+        Ok(Some(VerificationParameters {
+            config: config.0,
+            param: param.0,
+            vk: vk.0,
+            instance_layout: instance_layout.0,
+        }))
+    }
+}
+
+/// Read global resources of accounts from aptos.
+#[derive(Clone, Debug)]
+pub struct AptosStateSource {
+    client: AptosClientPool,
+}
+
+impl AptosStateSource {
+    pub fn new(client: AptosClientPool) -> Self {
+        Self { client }
+    }
+}
+
+#[async_trait]
+impl StateSource for AptosStateSource {
+    async fn get_resource(
+        &self,
+        address: Vec<u8>,
+        struct_tag: String,
+        version: u64,
+    ) -> SourceResult<Option<Vec<u8>>> {
+        let address =
+            AptosAccountAddress::from_bytes(address).map_err(|e| SourceError::Decode(e.into()))?;
+        let response = self
+            .client
+            .call_at_version(version, |c| {
+                let struct_tag = &struct_tag;
+                async move {
+                    c.get_account_resource_at_version_bytes(address, struct_tag, version)
+                        .await
+                }
+            })
+            .await;
+        match response {
+            Ok(response) => Ok(Some(response.into_inner())),
+            Err(e) if is_not_found(&e) => Ok(None),
+            Err(e) => Err(into_source_error(e)),
+        }
+    }
+}
+
+/// json form of `registry::VerificationParameters` returned by view functions.
+#[derive(Deserialize)]
+struct VerificationParametersView {
+    config: HexEncodedBytes,
+    param: HexEncodedBytes,
+    vk: HexEncodedBytes,
+    instance_layout: HexEncodedBytes,
+}
+
+/// registry view functions abort if the module or function is not registered.
+fn is_not_registered(e: &RestError) -> bool {
+    matches!(
+        e,
+        RestError::Api(AptosErrorResponse {
+            error: AptosError {
+                error_code: AptosErrorCode::VmError,
+                ..
+            },
+            ..
+        })
+    )
+}
+
+/// the account doesn't hold the resource, or doesn't exist at all.
+fn is_not_found(e: &RestError) -> bool {
+    matches!(
+        e,
+        RestError::Api(AptosErrorResponse {
+            error: AptosError {
+                error_code: AptosErrorCode::ResourceNotFound | AptosErrorCode::AccountNotFound,
+                ..
+            },
+            ..
+        })
+    )
+}
+
+fn into_source_error(e: RestError) -> SourceError {
+    match e {
+        RestError::Bcs(_) | RestError::Json(_) => SourceError::Decode(e.into()),
+        e => SourceError::Transport(e.into()),
+    }
+}
+
+fn decode_view_value(
+    response: Response<Vec<serde_json::Value>>,
+    func_name: &str,
+) -> SourceResult<Vec<u8>> {
+    let value = response
+        .into_inner()
+        .pop()
+        .ok_or_else(|| SourceError::Decode(anyhow!("view {} returns nothing", func_name)))?;
+    let bytes: HexEncodedBytes =
+        serde_json::from_value(value).map_err(|e| SourceError::Decode(e.into()))?;
+    Ok(bytes.0)
+}
@@ -0,0 +1,51 @@
+use crate::signature::SignatureMismatch;
+use agger_chain_source::SourceError;
+
+pub type ResolverResult<T> = Result<T, ResolverError>;
+
+/// Errors of resolving the modules and zk parameters of a query.
+/// Messages are returned to users, when their queries cannot be answered.
+#[derive(Debug, thiserror::Error)]
+pub enum ResolverError {
+    #[error("module {0} is not registered")]
+    ModuleNotRegistered(String),
+    #[error("function {function} not found in module {module}")]
+    FunctionNotFound { module: String, function: String },
+    #[error("verification key of function {function} in module {module} is not registered")]
+    VkNotRegistered { module: String, function: String },
+    #[error("invalid query of function {function} in module {module}: {reason}")]
+    InvalidQuery {
+        module: String,
+        function: String,
+        reason: SignatureMismatch,
+    },
+    #[error("transport error: {0}")]
+    Transport(anyhow::Error),
+    #[error("decode error: {0}")]
+    Decode(anyhow::Error),
+}
+
+impl ResolverError {
+    /// transient errors may go away if retried later.
+    /// They are not caused by the query, the others are.
+    pub fn is_transient(&self) -> bool {
+        matches!(self, Self::Transport(_))
+    }
+}
+
+impl From<SourceError> for ResolverError {
+    fn from(e: SourceError) -> Self {
+        match e {
+            SourceError::Transport(e) => Self::Transport(e),
+            SourceError::Decode(e) => Self::Decode(e),
+        }
+    }
+}
+
+pub(crate) fn display_module(module_address: &[u8], module_name: &[u8]) -> String {
+    format!(
+        "0x{}::{}",
+        hex::encode(module_address),
+        String::from_utf8_lossy(module_name)
+    )
+}
@@ -0,0 +1,26 @@
+use anyhow::anyhow;
+use move_binary_format::{access::ModuleAccess, CompiledModule};
+use move_core_types::language_storage::ModuleId;
+use std::{collections::BTreeMap, sync::OnceLock};
+
+/// Aptos framework modules, e.g. at 0x1 and 0x3, are not registered in agger registry,
+/// they are taken from the release bundle of the aptos-core agger is built with.
+pub fn framework_module(id: &ModuleId) -> anyhow::Result<Option<Vec<u8>>> {
+    static FRAMEWORK: OnceLock<Result<BTreeMap<ModuleId, Vec<u8>>, String>> = OnceLock::new();
+    let framework = FRAMEWORK
+        .get_or_init(|| load_framework().map_err(|e| format!("{:?}", e)))
+        .as_ref()
+        .map_err(|e| anyhow!("load bundled aptos framework failure: {}", e))?;
+    Ok(framework.get(id).cloned())
+}
+
+fn load_framework() -> anyhow::Result<BTreeMap<ModuleId, Vec<u8>>> {
+    aptos_cached_packages::head_release_bundle()
+        .code()
+        .into_iter()
+        .map(|code| {
+            let module = CompiledModule::deserialize(code)?;
+            Ok((module.self_id(), code.to_vec()))
+        })
+        .collect()
+}
@@ -1,40 +1,43 @@
-use agger_contract_types::{
-    AGGER_REGISTRY_FUNC_NAME_GET_CONFIG, AGGER_REGISTRY_FUNC_NAME_GET_MODULE,
-    AGGER_REGISTRY_FUNC_NAME_GET_PARAM, AGGER_REGISTRY_FUNC_NAME_GET_VK,
-    AGGER_REGISTRY_MODULE_NAME,
-};
-use anyhow::anyhow;
-use aptos_sdk::{
-    move_types::identifier::Identifier as AptosIdentifier,
-    rest_client::{
-        aptos_api_types::{
-            EntryFunctionId, HexEncodedBytes, IdentifierWrapper, MoveModuleId, ViewRequest,
-        },
-        error::RestError,
-        Client,
-    },
-};
-pub use aptos_sdk::{
-    rest_client::AptosBaseUrl, types::account_address::AccountAddress as AptosAccountAddress,
-};
-use futures_util::try_join;
-use move_binary_format::CompiledModule;
-use move_core_types::identifier::Identifier;
+use agger_chain_source::ModuleSource;
+pub use agger_chain_source::VerificationParameters;
+use agger_contract_types::Query;
+pub use aptos::{AptosModuleSource, AptosStateSource};
+use error::display_module;
+pub use error::{ResolverError, ResolverResult};
+use move_binary_format::{access::ModuleAccess, CompiledModule};
+use move_core_types::{identifier::Identifier, language_storage::ModuleId};
 use move_helpers::access_ext::ModuleAccessExt;
+pub use signature::SignatureMismatch;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    sync::{Arc, RwLock},
+};
+use tracing::{debug, info};
 
-//type AptosResult<T> = Result<T, RestError>;
+mod aptos;
+mod error;
+mod framework;
+mod signature;
 
-#[derive(Clone, Debug)]
+#[derive(Clone)]
 pub struct AggerModuleResolver {
-    client: Client,
-    agger_address: AptosAccountAddress,
+    source: Arc<dyn ModuleSource>,
+    cache: LocalCache,
+}
+
+/// Registered modules and verification keys cannot be changed once registered,
+/// so they are safe to be cached regardless of the version they are read at.
+#[derive(Clone, Debug, Default)]
+struct LocalCache {
+    modules: Arc<RwLock<HashMap<(Vec<u8>, Vec<u8>), Vec<u8>>>>,
+    verification_parameters: Arc<RwLock<HashMap<(Vec<u8>, Vec<u8>, u16), VerificationParameters>>>,
 }
 
 impl AggerModuleResolver {
-    pub fn new(aptos_url: AptosBaseUrl, agger_address: AptosAccountAddress) -> Self {
+    pub fn new(source: Arc<dyn ModuleSource>) -> Self {
         Self {
-            client: Client::builder(aptos_url).build(),
-            agger_address,
+            source,
+            cache: LocalCache::default(),
         }
     }
 
@@ -43,153 +46,242 @@ impl AggerModuleResolver {
         module_address: Vec<u8>,
         module_name: Vec<u8>,
         version: u64,
-    ) -> anyhow::Result<Option<Vec<u8>>> {
-        let req = ViewRequest {
-            function: EntryFunctionId {
-                module: MoveModuleId {
-                    address: self.agger_address.into(),
-                    name: IdentifierWrapper(
-                        AptosIdentifier::new(AGGER_REGISTRY_MODULE_NAME).unwrap(),
-                    ),
-                },
-                name: IdentifierWrapper(
-                    AptosIdentifier::new(AGGER_REGISTRY_FUNC_NAME_GET_MODULE).unwrap(),
-                ),
-            },
-            type_arguments: vec![],
-            arguments: vec![
-                HexEncodedBytes(module_address).json().unwrap(),
-                HexEncodedBytes(module_name).json().unwrap(),
-            ],
-        };
+    ) -> ResolverResult<Option<Vec<u8>>> {
+        let key = (module_address, module_name);
+        if let Some(m) = self.cache.modules.read().unwrap().get(&key) {
+            return Ok(Some(m.clone()));
+        }
+        let (module_address, module_name) = key;
+        let module_byte = self
+            .source
+            .get_module(module_address.clone(), module_name.clone(), version)
+            .await?;
+        if let Some(m) = &module_byte {
+            self.cache
+                .modules
+                .write()
+                .unwrap()
+                .insert((module_address, module_name), m.clone());
+        }
+        Ok(module_byte)
+    }
 
-        let response = self
-            .client
-            .view(&req, Some(version))
+    /// fetch module and verification parameters of all its registered entry functions
+    /// into local cache,
+    /// return the verification parameters by function index.
+    pub async fn prewarm_module(
+        self,
+        module_address: Vec<u8>,
+        module_name: Vec<u8>,
+        version: u64,
+    ) -> ResolverResult<Vec<(u16, VerificationParameters)>> {
+        let target_module_bytes = self
+            .get_module_at_version(module_address.clone(), module_name.clone(), version)
             .await?
-            .into_inner()
-            .pop();
-        let module_byte: Option<HexEncodedBytes> =
-            response.map(serde_json::from_value).transpose()?;
-        //.expect("view get_module should return one value");
-
-        Ok(module_byte.map(|b| b.0))
+            .ok_or_else(|| {
+                ResolverError::ModuleNotRegistered(display_module(&module_address, &module_name))
+            })?;
+        let target_module = CompiledModule::deserialize(&target_module_bytes)
+            .map_err(|e| ResolverError::Decode(e.into()))?;
+        let mut verification_parameters = Vec::new();
+        for function_def in target_module.function_defs().iter().filter(|f| f.is_entry) {
+            let function_index = function_def.function.0;
+            // not every entry function has its vk registered.
+            match self
+                .get_verification_parameters_at_version(
+                    module_address.clone(),
+                    module_name.clone(),
+                    function_index,
+                    version,
+                )
+                .await
+            {
+                Ok(Some(p)) => verification_parameters.push((function_index, p)),
+                Ok(None) => {
+                    debug!(
+                        "function {} of module {} has no vk registered",
+                        target_module.identifier_at(
+                            target_module.function_handle_at(function_def.function).name
+                        ),
+                        target_module.self_id(),
+                    );
+                },
+                Err(e) => {
+                    info!(
+                        "skip function {} of module {}: {:?}",
+                        target_module.identifier_at(
+                            target_module.function_handle_at(function_def.function).name
+                        ),
+                        target_module.self_id(),
+                        e
+                    );
+                },
+            }
+        }
+        Ok(verification_parameters)
     }
 
-    ///return (entry_module, zk_params) for a user query
+    ///return (modules, verification parameters) for a user query,
+    /// modules are the entry module and its dependencies in topological order.
+    /// The module and verification parameters take two view calls, both at `version`:
+    /// the registry keys parameters by function index, which is only known once the module is
+    /// decoded, and its tables cannot be iterated to return the parameters of all functions.
+    /// Both are cached once read, as registrations never change.
     pub async fn get_vk_for_entry_function(
         self,
         module_address: Vec<u8>,
         module_name: Vec<u8>,
         function_name: Vec<u8>,
         version: u64,
-    ) -> anyhow::Result<(Vec<u8>, EntryFunctionZkParameters)> {
+    ) -> ResolverResult<(Vec<Vec<u8>>, VerificationParameters)> {
+        let module = display_module(&module_address, &module_name);
         let target_module_bytes = self
             .get_module_at_version(module_address.clone(), module_name.clone(), version)
             .await?
-            .ok_or(anyhow!(
-                "module {}::{} not exists",
-                &hex::encode(module_address.as_slice()),
-                String::from_utf8_lossy(&module_name)
-            ))?;
+            .ok_or_else(|| ResolverError::ModuleNotRegistered(module.clone()))?;
 
+        let target_module = CompiledModule::deserialize(&target_module_bytes)
+            .map_err(|e| ResolverError::Decode(e.into()))?;
+        let function_not_found = || ResolverError::FunctionNotFound {
+            module: module.clone(),
+            function: String::from_utf8_lossy(&function_name).to_string(),
+        };
         let function_index = {
-            let target_module = CompiledModule::deserialize(&target_module_bytes)
-                .map_err(|e| RestError::Unknown(anyhow::Error::new(e)))?;
-            let function_name = Identifier::from_utf8(function_name)?;
+            let function_name =
+                Identifier::from_utf8(function_name.clone()).map_err(|_| function_not_found())?;
             let function_def = target_module
                 .find_function_def_by_name(function_name.as_ident_str())
-                .ok_or(anyhow!(
-                    "function {} not found in module {}",
-                    function_name.as_str(),
-                    target_module.self_id()
-                ))?;
+                .ok_or_else(function_not_found)?;
             function_def.function.0
         };
-        let reqs: Vec<_> = vec![
-            (
-                AGGER_REGISTRY_FUNC_NAME_GET_CONFIG,
-                vec![
-                    HexEncodedBytes(module_address.clone()).json().unwrap(),
-                    HexEncodedBytes(module_name.clone()).json().unwrap(),
-                    serde_json::to_value(function_index).unwrap(),
-                ],
-            ),
-            (
-                AGGER_REGISTRY_FUNC_NAME_GET_VK,
-                vec![
-                    HexEncodedBytes(module_address.clone()).json().unwrap(),
-                    HexEncodedBytes(module_name.clone()).json().unwrap(),
-                    serde_json::to_value(function_index).unwrap(),
-                ],
-            ),
-            (
-                AGGER_REGISTRY_FUNC_NAME_GET_PARAM,
-                vec![
-                    HexEncodedBytes(module_address.clone()).json().unwrap(),
-                    HexEncodedBytes(module_name.clone()).json().unwrap(),
-                    serde_json::to_value(function_index).unwrap(),
-                ],
-            ),
-        ]
-        .into_iter()
-        .map(|(func_name, args)| ViewRequest {
-            function: EntryFunctionId {
-                module: MoveModuleId {
-                    address: self.agger_address.into(),
-                    name: IdentifierWrapper(
-                        AptosIdentifier::new(AGGER_REGISTRY_MODULE_NAME).unwrap(),
-                    ),
-                },
-                name: IdentifierWrapper(AptosIdentifier::new(func_name).unwrap()),
-            },
-            type_arguments: vec![],
-            arguments: args,
-        })
-        .collect();
+        let verification_parameters = self
+            .get_verification_parameters_at_version(
+                module_address,
+                module_name,
+                function_index,
+                version,
+            )
+            .await?
+            .ok_or_else(|| ResolverError::VkNotRegistered {
+                module: module.clone(),
+                function: String::from_utf8_lossy(&function_name).to_string(),
+            })?;
+        let modules = self
+            .resolve_dependencies(target_module, target_module_bytes, version)
+            .await?;
+        Ok((modules, verification_parameters))
+    }
+
+    /// resolve modules and verification parameters of the entry function `query` calls,
+    /// and check query arguments match the function signature.
+    pub async fn resolve_query(
+        self,
+        query: &Query,
+        version: u64,
+    ) -> ResolverResult<(Vec<Vec<u8>>, VerificationParameters)> {
+        let (modules, verification_parameters) = self
+            .get_vk_for_entry_function(
+                query.module_address.clone(),
+                query.module_name.clone(),
+                query.function_name.clone(),
+                version,
+            )
+            .await?;
+        signature::validate_query(&modules, query)?;
+        Ok((modules, verification_parameters))
+    }
 
-        let mut reqs: Vec<_> = reqs
-            .iter()
-            .map(|req| self.client.view(req, Some(version)))
-            .collect();
+    /// return the dependency closure of `target_module` in topological order,
+    /// dependencies come before their dependents, and target module is the last.
+    pub async fn resolve_dependencies(
+        &self,
+        target_module: CompiledModule,
+        target_module_bytes: Vec<u8>,
+        version: u64,
+    ) -> ResolverResult<Vec<Vec<u8>>> {
+        let target_id = target_module.self_id();
+        let mut to_fetch = target_module.immediate_dependencies();
+        let mut modules = BTreeMap::new();
+        modules.insert(target_id.clone(), (target_module, target_module_bytes));
+        while let Some(id) = to_fetch.pop() {
+            if modules.contains_key(&id) {
+                continue;
+            }
+            let bytes = self.get_dependency_at_version(&id, version).await?;
+            let m =
+                CompiledModule::deserialize(&bytes).map_err(|e| ResolverError::Decode(e.into()))?;
+            to_fetch.extend(m.immediate_dependencies());
+            modules.insert(id, (m, bytes));
+        }
 
-        let (param, vk, config) = try_join!(
-            reqs.pop().unwrap(),
-            reqs.pop().unwrap(),
-            reqs.pop().unwrap()
-        )?;
+        fn visit(
+            id: &ModuleId,
+            modules: &BTreeMap<ModuleId, (CompiledModule, Vec<u8>)>,
+            visited: &mut BTreeSet<ModuleId>,
+            ordered: &mut Vec<Vec<u8>>,
+        ) {
+            if !visited.insert(id.clone()) {
+                return;
+            }
+            let (m, bytes) = &modules[id];
+            for dep in m.immediate_dependencies() {
+                visit(&dep, modules, visited, ordered);
+            }
+            ordered.push(bytes.clone());
+        }
+        let mut ordered = Vec::with_capacity(modules.len());
+        visit(&target_id, &modules, &mut BTreeSet::new(), &mut ordered);
+        Ok(ordered)
+    }
 
-        let param: HexEncodedBytes = param
-            .into_inner()
-            .pop()
-            .map(serde_json::from_value)
-            .transpose()?
-            .expect("view get_param return value");
-        let vk: HexEncodedBytes = vk
-            .into_inner()
-            .pop()
-            .map(serde_json::from_value)
-            .transpose()?
-            .expect("view get_vk return value");
-        let config: HexEncodedBytes = config
-            .into_inner()
-            .pop()
-            .map(serde_json::from_value)
-            .transpose()?
-            .expect("view get_config return value");
-        Ok((
-            target_module_bytes,
-            EntryFunctionZkParameters {
-                config: config.0,
-                vk: vk.0,
-                param: param.0,
+    async fn get_dependency_at_version(
+        &self,
+        id: &ModuleId,
+        version: u64,
+    ) -> ResolverResult<Vec<u8>> {
+        // framework addresses are reserved, users cannot register modules there.
+        let module = match framework::framework_module(id).map_err(ResolverError::Decode)? {
+            Some(module) => Some(module),
+            None => {
+                self.get_module_at_version(
+                    id.address().to_vec(),
+                    id.name().as_bytes().to_vec(),
+                    version,
+                )
+                .await?
             },
-        ))
+        };
+        module.ok_or_else(|| ResolverError::ModuleNotRegistered(id.to_string()))
     }
-}
 
-pub struct EntryFunctionZkParameters {
-    pub config: Vec<u8>,
-    pub vk: Vec<u8>,
-    pub param: Vec<u8>,
+    pub async fn get_verification_parameters_at_version(
+        &self,
+        module_address: Vec<u8>,
+        module_name: Vec<u8>,
+        function_index: u16,
+        version: u64,
+    ) -> ResolverResult<Option<VerificationParameters>> {
+        let key = (module_address, module_name, function_index);
+        if let Some(p) = self.cache.verification_parameters.read().unwrap().get(&key) {
+            return Ok(Some(p.clone()));
+        }
+        let (module_address, module_name, function_index) = key;
+        let verification_parameters = self
+            .source
+            .get_verification_parameters(
+                module_address.clone(),
+                module_name.clone(),
+                function_index,
+                version,
+            )
+            .await?;
+        if let Some(p) = &verification_parameters {
+            self.cache
+                .verification_parameters
+                .write()
+                .unwrap()
+                .insert((module_address, module_name, function_index), p.clone());
+        }
+        Ok(verification_parameters)
+    }
 }
@@ -0,0 +1,231 @@
+//! Validate query arguments against the signature of the entry function,
+//! so that malformed queries are rejected before execution.
+
+use crate::{ResolverError, ResolverResult};
+use agger_contract_types::Query;
+use move_binary_format::{
+    access::ModuleAccess,
+    file_format::{Ability, AbilitySet, SignatureToken},
+    CompiledModule,
+};
+use move_core_types::{
+    identifier::Identifier,
+    language_storage::{ModuleId, TypeTag},
+    parser::{parse_transaction_argument, parse_type_tag},
+    transaction_argument::TransactionArgument,
+};
+use move_helpers::access_ext::ModuleAccessExt;
+use std::collections::BTreeMap;
+
+#[derive(Debug, thiserror::Error)]
+pub enum SignatureMismatch {
+    #[error("not an entry function")]
+    NotEntryFunction,
+    #[error("expect {expected} type arguments, got {actual}")]
+    TypeArgumentCount { expected: usize, actual: usize },
+    #[error("invalid type argument {index}: {reason}")]
+    InvalidTypeArgument { index: usize, reason: String },
+    #[error("type argument {index} `{type_arg}` misses abilities {missing:?}")]
+    MissingAbilities {
+        index: usize,
+        type_arg: String,
+        missing: Vec<Ability>,
+    },
+    #[error("expect {expected} arguments, got {actual}")]
+    ArgumentCount { expected: usize, actual: usize },
+    #[error("invalid argument {index}: {reason}")]
+    InvalidArgument { index: usize, reason: String },
+    #[error("argument {index} should be of type {expected}")]
+    ArgumentTypeMismatch { index: usize, expected: String },
+    #[error("parameter {index} of type {param} cannot be passed by queries")]
+    UnsupportedParameter { index: usize, param: String },
+}
+
+/// validate `query` against the entry function it calls.
+/// `modules` are the entry module and its dependencies, as returned by the resolver.
+pub fn validate_query(modules: &[Vec<u8>], query: &Query) -> ResolverResult<()> {
+    let modules = modules
+        .iter()
+        .map(|m| {
+            let m = CompiledModule::deserialize(m).map_err(|e| ResolverError::Decode(e.into()))?;
+            Ok((m.self_id(), m))
+        })
+        .collect::<ResolverResult<BTreeMap<_, _>>>()?;
+    let function_name = String::from_utf8_lossy(&query.function_name).to_string();
+    let module_id = ModuleId::new(
+        move_core_types::account_address::AccountAddress::from_bytes(&query.module_address)
+            .map_err(|e| ResolverError::Decode(e.into()))?,
+        Identifier::from_utf8(query.module_name.clone())
+            .map_err(|e| ResolverError::Decode(e.into()))?,
+    );
+    let function_not_found = || ResolverError::FunctionNotFound {
+        module: module_id.to_string(),
+        function: function_name.clone(),
+    };
+    let module = modules.get(&module_id).ok_or_else(function_not_found)?;
+    let function_def = Identifier::new(function_name.as_str())
+        .ok()
+        .and_then(|name| module.find_function_def_by_name(name.as_ident_str()))
+        .ok_or_else(function_not_found)?;
+
+    let check = || -> Result<(), SignatureMismatch> {
+        if !module.is_entry_function(function_def) {
+            return Err(SignatureMismatch::NotEntryFunction);
+        }
+        let ty_args = check_type_arguments(
+            module.function_type_parameter_abilities(function_def),
+            &query.ty_args,
+            &modules,
+        )?;
+        check_arguments(
+            module.function_parameter_types(function_def),
+            &query.args,
+            &ty_args,
+        )
+    };
+    check().map_err(|reason| ResolverError::InvalidQuery {
+        module: module_id.to_string(),
+        function: function_name.clone(),
+        reason,
+    })
+}
+
+fn check_type_arguments(
+    constraints: &[AbilitySet],
+    ty_args: &[Vec<u8>],
+    modules: &BTreeMap<ModuleId, CompiledModule>,
+) -> Result<Vec<TypeTag>, SignatureMismatch> {
+    if constraints.len() != ty_args.len() {
+        return Err(SignatureMismatch::TypeArgumentCount {
+            expected: constraints.len(),
+            actual: ty_args.len(),
+        });
+    }
+    let mut tags = Vec::with_capacity(ty_args.len());
+    for (index, (&constraint, ty_arg)) in constraints.iter().zip(ty_args).enumerate() {
+        let invalid = |reason: String| SignatureMismatch::InvalidTypeArgument { index, reason };
+        let s = std::str::from_utf8(ty_arg).map_err(|e| invalid(e.to_string()))?;
+        let tag = parse_type_tag(s).map_err(|e| invalid(e.to_string()))?;
+        let abilities = type_tag_abilities(&tag, modules).map_err(invalid)?;
+        let missing: Vec<_> = constraint
+            .into_iter()
+            .filter(|a| !abilities.has_ability(*a))
+            .collect();
+        if !missing.is_empty() {
+            return Err(SignatureMismatch::MissingAbilities {
+                index,
+                type_arg: tag.to_string(),
+                missing,
+            });
+        }
+        tags.push(tag);
+    }
+    Ok(tags)
+}
+
+fn type_tag_abilities(
+    tag: &TypeTag,
+    modules: &BTreeMap<ModuleId, CompiledModule>,
+) -> Result<AbilitySet, String> {
+    match tag {
+        TypeTag::Signer => Ok(AbilitySet::SIGNER),
+        TypeTag::Vector(t) => {
+            AbilitySet::polymorphic_abilities(AbilitySet::VECTOR, vec![false], vec![
+                type_tag_abilities(t, modules)?,
+            ])
+            .map_err(|e| format!("{:?}", e))
+        },
+        TypeTag::Struct(s) => {
+            let module_id = ModuleId::new(s.address, s.module.clone());
+            let handle = modules
+                .get(&module_id)
+                .and_then(|m| m.find_struct_handle_by_name(s.name.as_ident_str()))
+                .ok_or_else(|| format!("struct {}::{} not found", module_id, s.name))?;
+            if handle.type_parameters.len() != s.type_params.len() {
+                return Err(format!(
+                    "struct {}::{} expects {} type arguments",
+                    module_id,
+                    s.name,
+                    handle.type_parameters.len()
+                ));
+            }
+            let type_args = s
+                .type_params
+                .iter()
+                .map(|t| type_tag_abilities(t, modules))
+                .collect::<Result<Vec<_>, _>>()?;
+            AbilitySet::polymorphic_abilities(
+                handle.abilities,
+                handle.type_parameters.iter().map(|p| p.is_phantom),
+                type_args,
+            )
+            .map_err(|e| format!("{:?}", e))
+        },
+        // bool, integers and address
+        _ => Ok(AbilitySet::PRIMITIVES),
+    }
+}
+
+fn check_arguments(
+    params: &[SignatureToken],
+    args: &[Vec<u8>],
+    ty_args: &[TypeTag],
+) -> Result<(), SignatureMismatch> {
+    if params.len() != args.len() {
+        return Err(SignatureMismatch::ArgumentCount {
+            expected: params.len(),
+            actual: args.len(),
+        });
+    }
+    for (index, (param, arg)) in params.iter().zip(args).enumerate() {
+        let expected = parameter_type_tag(param, ty_args).ok_or_else(|| {
+            SignatureMismatch::UnsupportedParameter {
+                index,
+                param: format!("{:?}", param),
+            }
+        })?;
+        let invalid = |reason: String| SignatureMismatch::InvalidArgument { index, reason };
+        let s = std::str::from_utf8(arg).map_err(|e| invalid(e.to_string()))?;
+        let arg = parse_transaction_argument(s).map_err(|e| invalid(e.to_string()))?;
+        if argument_type_tag(&arg) != expected {
+            return Err(SignatureMismatch::ArgumentTypeMismatch {
+                index,
+                expected: expected.to_string(),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// type of parameter which can be passed as a transaction argument.
+fn parameter_type_tag(param: &SignatureToken, ty_args: &[TypeTag]) -> Option<TypeTag> {
+    match param {
+        SignatureToken::Bool => Some(TypeTag::Bool),
+        SignatureToken::U8 => Some(TypeTag::U8),
+        SignatureToken::U16 => Some(TypeTag::U16),
+        SignatureToken::U32 => Some(TypeTag::U32),
+        SignatureToken::U64 => Some(TypeTag::U64),
+        SignatureToken::U128 => Some(TypeTag::U128),
+        SignatureToken::U256 => Some(TypeTag::U256),
+        SignatureToken::Address => Some(TypeTag::Address),
+        SignatureToken::Vector(t) => {
+            parameter_type_tag(t, ty_args).map(|t| TypeTag::Vector(Box::new(t)))
+        },
+        SignatureToken::TypeParameter(i) => ty_args.get(*i as usize).cloned(),
+        _ => None,
+    }
+}
+
+fn argument_type_tag(arg: &TransactionArgument) -> TypeTag {
+    match arg {
+        TransactionArgument::Bool(_) => TypeTag::Bool,
+        TransactionArgument::U8(_) => TypeTag::U8,
+        TransactionArgument::U16(_) => TypeTag::U16,
+        TransactionArgument::U32(_) => TypeTag::U32,
+        TransactionArgument::U64(_) => TypeTag::U64,
+        TransactionArgument::U128(_) => TypeTag::U128,
+        TransactionArgument::U256(_) => TypeTag::U256,
+        TransactionArgument::Address(_) => TypeTag::Address,
+        TransactionArgument::U8Vector(_) => TypeTag::Vector(Box::new(TypeTag::U8)),
+    }
+}
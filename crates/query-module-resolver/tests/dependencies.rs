@@ -0,0 +1,119 @@
+use agger_chain_source::replay::{RegisteredModule, ReplayFixture, ReplaySource};
+use agger_contract_types::ModuleId as RegisteredModuleId;
+use move_binary_format::{
+    access::ModuleAccess,
+    file_format::{empty_module, AddressIdentifierIndex, IdentifierIndex, ModuleHandle},
+    CompiledModule,
+};
+use move_core_types::{account_address::AccountAddress, identifier::Identifier};
+use query_module_resolver::AggerModuleResolver;
+use std::sync::Arc;
+
+const VERSION: u64 = 42;
+
+fn address() -> AccountAddress {
+    AccountAddress::from_hex_literal("0xcafe").unwrap()
+}
+
+/// module `0xcafe::{name}` depending on modules `0xcafe::{deps}`, without any code.
+/// modules are built rather than compiled, as the compiler rejects cyclic dependencies.
+fn module(name: &str, deps: &[&str]) -> CompiledModule {
+    let mut m = empty_module();
+    m.address_identifiers[0] = address();
+    m.identifiers[0] = Identifier::new(name).unwrap();
+    for dep in deps {
+        m.identifiers.push(Identifier::new(*dep).unwrap());
+        m.module_handles.push(ModuleHandle {
+            address: AddressIdentifierIndex(0),
+            name: IdentifierIndex((m.identifiers.len() - 1) as u16),
+        });
+    }
+    m
+}
+
+fn serialize(m: &CompiledModule) -> Vec<u8> {
+    let mut bytes = vec![];
+    m.serialize(&mut bytes).unwrap();
+    bytes
+}
+
+fn resolver(modules: &[CompiledModule]) -> AggerModuleResolver {
+    let fixture = ReplayFixture {
+        modules: modules
+            .iter()
+            .map(|m| RegisteredModule {
+                module_id: RegisteredModuleId {
+                    addr: address().to_vec(),
+                    name: m.self_id().name().to_string(),
+                },
+                code: serialize(m),
+            })
+            .collect(),
+        ..Default::default()
+    };
+    AggerModuleResolver::new(Arc::new(ReplaySource::new(fixture)))
+}
+
+fn names(modules: &[Vec<u8>]) -> Vec<String> {
+    modules
+        .iter()
+        .map(|m| {
+            CompiledModule::deserialize(m)
+                .unwrap()
+                .self_id()
+                .name()
+                .to_string()
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn test_diamond_dependencies() {
+    let modules = [
+        module("top", &["left", "right"]),
+        module("left", &["bottom"]),
+        module("right", &["bottom"]),
+        module("bottom", &[]),
+    ];
+    let top = modules[0].clone();
+    let ordered = resolver(&modules)
+        .resolve_dependencies(top.clone(), serialize(&top), VERSION)
+        .await
+        .unwrap();
+    let ordered = names(&ordered);
+
+    // the shared dependency is taken once, before both of its dependents.
+    assert_eq!(ordered.len(), 4);
+    let position = |name: &str| ordered.iter().position(|n| n == name).unwrap();
+    assert!(position("bottom") < position("left"));
+    assert!(position("bottom") < position("right"));
+    assert_eq!(ordered.last().unwrap(), "top");
+}
+
+#[tokio::test]
+async fn test_cyclic_dependencies() {
+    let modules = [
+        module("entry", &["a"]),
+        module("a", &["b"]),
+        module("b", &["a"]),
+    ];
+    let entry = modules[0].clone();
+    let ordered = resolver(&modules)
+        .resolve_dependencies(entry.clone(), serialize(&entry), VERSION)
+        .await
+        .unwrap();
+
+    // resolving stops at modules already visited, each module is taken once.
+    assert_eq!(names(&ordered), vec!["b", "a", "entry"]);
+}
+
+#[tokio::test]
+async fn test_missing_dependency() {
+    let modules = [module("entry", &["missing"])];
+    let entry = modules[0].clone();
+    let e = resolver(&modules)
+        .resolve_dependencies(entry.clone(), serialize(&entry), VERSION)
+        .await
+        .unwrap_err();
+    assert!(e.to_string().contains("missing"), "{}", e);
+}
@@ -0,0 +1,145 @@
+use agger_chain_source::replay::{
+    RegisteredFunction, RegisteredModule, ReplayFixture, ReplaySource,
+};
+use agger_contract_types::{ModuleId, Query, VerificationParameters};
+use move_binary_format::file_format::Ability;
+use move_core_types::account_address::AccountAddress;
+use query_module_resolver::{AggerModuleResolver, ResolverError, SignatureMismatch};
+use std::sync::Arc;
+use test_helpers::compile_module_bytes;
+
+const VERSION: u64 = 42;
+
+const MODULE: &str = r#"
+module 0xcafe::shapes {
+    struct Token has drop { value: u64 }
+
+    public entry fun add(a: u64, b: u64) {
+        let _ = a + b;
+    }
+
+    public entry fun keep<T: copy + drop>(_a: u64) {}
+
+    public fun helper(a: u64): u64 {
+        a
+    }
+}
+"#;
+
+fn address() -> Vec<u8> {
+    AccountAddress::from_hex_literal("0xcafe").unwrap().to_vec()
+}
+
+/// resolver of the module, with parameters registered for every function of it.
+fn resolver() -> AggerModuleResolver {
+    let module_id = ModuleId {
+        addr: address(),
+        name: "shapes".to_string(),
+    };
+    let fixture = ReplayFixture {
+        modules: vec![RegisteredModule {
+            module_id: module_id.clone(),
+            code: compile_module_bytes(MODULE),
+        }],
+        functions: (0..3)
+            .map(|function_index| RegisteredFunction {
+                module_id: module_id.clone(),
+                function_index,
+                verification_parameters: VerificationParameters {
+                    config: vec![],
+                    param: vec![],
+                    vk: vec![],
+                    instance_layout: vec![],
+                },
+            })
+            .collect(),
+        ..Default::default()
+    };
+    AggerModuleResolver::new(Arc::new(ReplaySource::new(fixture)))
+}
+
+fn query(function: &str, ty_args: &[&str], args: &[&str]) -> Query {
+    Query {
+        module_address: address(),
+        module_name: b"shapes".to_vec(),
+        function_name: function.as_bytes().to_vec(),
+        deadline: 0,
+        args: args.iter().map(|a| a.as_bytes().to_vec()).collect(),
+        ty_args: ty_args.iter().map(|t| t.as_bytes().to_vec()).collect(),
+        success: None,
+        result: None,
+    }
+}
+
+/// why `query` is rejected, none if it is valid.
+async fn mismatch(query: Query) -> Option<SignatureMismatch> {
+    match resolver().resolve_query(&query, VERSION).await {
+        Ok(_) => None,
+        Err(ResolverError::InvalidQuery { reason, .. }) => Some(reason),
+        Err(e) => panic!("unexpected error {}", e),
+    }
+}
+
+#[tokio::test]
+async fn test_valid_queries() {
+    assert!(mismatch(query("add", &[], &["1u64", "2u64"]))
+        .await
+        .is_none());
+    assert!(mismatch(query("keep", &["u64"], &["1u64"])).await.is_none());
+}
+
+#[tokio::test]
+async fn test_wrong_arity() {
+    match mismatch(query("add", &[], &["1u64"])).await {
+        Some(SignatureMismatch::ArgumentCount {
+            expected: 2,
+            actual: 1,
+        }) => {},
+        r => panic!("unexpected {:?}", r),
+    }
+    match mismatch(query("keep", &[], &["1u64"])).await {
+        Some(SignatureMismatch::TypeArgumentCount {
+            expected: 1,
+            actual: 0,
+        }) => {},
+        r => panic!("unexpected {:?}", r),
+    }
+}
+
+#[tokio::test]
+async fn test_wrong_type() {
+    match mismatch(query("add", &[], &["1u64", "true"])).await {
+        Some(SignatureMismatch::ArgumentTypeMismatch { index: 1, expected }) => {
+            assert_eq!(expected, "u64")
+        },
+        r => panic!("unexpected {:?}", r),
+    }
+    // args are parsed as transaction arguments.
+    match mismatch(query("add", &[], &["1u64", "two"])).await {
+        Some(SignatureMismatch::InvalidArgument { index: 1, .. }) => {},
+        r => panic!("unexpected {:?}", r),
+    }
+}
+
+#[tokio::test]
+async fn test_ability_violation() {
+    match mismatch(query("keep", &["0xcafe::shapes::Token"], &["1u64"])).await {
+        Some(SignatureMismatch::MissingAbilities {
+            index: 0, missing, ..
+        }) => assert_eq!(missing, vec![Ability::Copy]),
+        r => panic!("unexpected {:?}", r),
+    }
+    // vectors have the abilities of their elements.
+    match mismatch(query("keep", &["vector<0xcafe::shapes::Token>"], &["1u64"])).await {
+        Some(SignatureMismatch::MissingAbilities { index: 0, .. }) => {},
+        r => panic!("unexpected {:?}", r),
+    }
+}
+
+#[tokio::test]
+async fn test_non_entry_function() {
+    match mismatch(query("helper", &[], &["1u64"])).await {
+        Some(SignatureMismatch::NotEntryFunction) => {},
+        r => panic!("unexpected {:?}", r),
+    }
+}
@@ -0,0 +1,96 @@
+use agger_chain_source::StateSource;
+use aptos_client_pool::{AptosBaseUrl, AptosClientPool};
+use aptos_sdk::{
+    move_types::{language_storage::StructTag, parser::parse_struct_tag},
+    types::account_address::AccountAddress,
+};
+use query_module_resolver::AptosStateSource;
+use std::{
+    collections::BTreeMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+use test_helpers::fullnode::{
+    bad_request, ledger_version, path_segments, start_mock_fullnode, Ledger, StatusCode,
+};
+
+const VERSION: u64 = 42;
+
+/// Resources served by the mocked rest api, and ledger versions they are requested at.
+#[derive(Default)]
+struct MockChain {
+    resources: BTreeMap<(AccountAddress, StructTag), Vec<u8>>,
+    requested_versions: Mutex<Vec<u64>>,
+}
+
+/// serve `GET /v1/accounts/{address}/resource/{struct_tag}?ledger_version={version}` in bcs.
+async fn start_mock_chain(chain: Arc<MockChain>) -> SocketAddr {
+    let ledger = Ledger::default();
+    start_mock_fullnode(move |request| {
+        match (path_segments(&request).as_slice(), ledger_version(&request)) {
+            (["", "v1", "accounts", address, "resource", struct_tag], Some(version)) => {
+                chain.requested_versions.lock().unwrap().push(version);
+                let key = (
+                    address.parse::<AccountAddress>().unwrap(),
+                    parse_struct_tag(struct_tag).unwrap(),
+                );
+                match chain.resources.get(&key) {
+                    Some(value) => ledger.bcs(value.clone()),
+                    None => ledger.error(
+                        StatusCode::NOT_FOUND,
+                        "resource_not_found",
+                        "Resource not found",
+                    ),
+                }
+            },
+            _ => bad_request(),
+        }
+    })
+    .await
+}
+
+fn source(addr: SocketAddr) -> AptosStateSource {
+    let url = format!("http://{}", addr).parse().unwrap();
+    AptosStateSource::new(AptosClientPool::new(vec![AptosBaseUrl::Custom(url)]).unwrap())
+}
+
+fn address(a: &str) -> Vec<u8> {
+    AccountAddress::from_hex_literal(a).unwrap().to_vec()
+}
+
+#[tokio::test]
+async fn test_get_resource_at_version() {
+    let owner = AccountAddress::from_hex_literal("0xb0b").unwrap();
+    let counter = bcs::to_bytes(&7u64).unwrap();
+    let chain = Arc::new(MockChain {
+        resources: BTreeMap::from([(
+            (owner, parse_struct_tag("0xcafe::counter::Counter").unwrap()),
+            counter.clone(),
+        )]),
+        ..Default::default()
+    });
+    let addr = start_mock_chain(chain.clone()).await;
+    let source = source(addr);
+
+    let value = source
+        .get_resource(
+            address("0xb0b"),
+            "0xcafe::counter::Counter".to_string(),
+            VERSION,
+        )
+        .await
+        .unwrap();
+    assert_eq!(value, Some(counter));
+    // absent resources are read as none.
+    let value = source
+        .get_resource(
+            address("0xcafe"),
+            "0xcafe::counter::Counter".to_string(),
+            VERSION,
+        )
+        .await
+        .unwrap();
+    assert_eq!(value, None);
+    let requested_versions = chain.requested_versions.lock().unwrap().clone();
+    assert_eq!(requested_versions, vec![VERSION; 2]);
+}
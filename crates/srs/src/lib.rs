@@ -0,0 +1,160 @@
+//! KZG structured reference string management.
+//! Params of each circuit are downsized from one powers-of-tau srs,
+//! and the hash of the downsized params is registered onchain in `VerificationParameters.param`.
+
+use anyhow::{anyhow, ensure, Result};
+use halo2_proofs::{
+    halo2curves::bn256::Bn256,
+    poly::{commitment::Params, kzg::commitment::ParamsKZG},
+    SerdeFormat,
+};
+use serde::{Deserialize, Serialize};
+use sha3::{Digest, Sha3_256};
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::BufReader,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+use tracing::info;
+
+/// srs params of a circuit, bcs serialized in `VerificationParameters.param`.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct SrsParam {
+    pub k: u32,
+    /// sha3-256 of the params downsized to `k`, see `params_hash`.
+    pub srs_hash: Vec<u8>,
+}
+
+impl SrsParam {
+    pub fn decode(param: &[u8]) -> Result<Self> {
+        Ok(bcs::from_bytes(param)?)
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        bcs::to_bytes(self).expect("srs param is serializable")
+    }
+
+    /// check `params` are the ones recorded.
+    pub fn check(&self, params: &ParamsKZG<Bn256>) -> Result<()> {
+        ensure!(
+            params.k() == self.k,
+            "srs k mismatch, expect {}, got {}",
+            self.k,
+            params.k()
+        );
+        ensure!(params_hash(params) == self.srs_hash, "srs hash mismatch");
+        Ok(())
+    }
+}
+
+enum SrsSource {
+    /// powers of tau loaded from file.
+    File(ParamsKZG<Bn256>),
+    /// params generated with a fake rng, whose toxic waste is public.
+    #[cfg(feature = "insecure-dev-srs")]
+    InsecureDev,
+}
+
+/// Srs to derive params of circuits from.
+/// Downsized params are cached by k, as downsizing and hashing are not cheap.
+pub struct Srs {
+    source: SrsSource,
+    params: Mutex<HashMap<u32, Arc<(ParamsKZG<Bn256>, SrsParam)>>>,
+}
+
+impl Srs {
+    /// load powers of tau in halo2 raw bytes format, as written by `ParamsKZG::write`.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let params = ParamsKZG::<Bn256>::read_custom(
+            &mut BufReader::new(File::open(path.as_ref())?),
+            SerdeFormat::RawBytes,
+        )?;
+        info!(
+            "srs of k {} loaded from {}",
+            params.k(),
+            path.as_ref().display()
+        );
+        Ok(Self::new(SrsSource::File(params)))
+    }
+
+    /// srs generated from a fake rng, proofs are forgeable with it.
+    #[cfg(feature = "insecure-dev-srs")]
+    pub fn insecure_dev() -> Self {
+        tracing::warn!("insecure dev srs is used, proofs are forgeable");
+        Self::new(SrsSource::InsecureDev)
+    }
+
+    /// load srs from `path`, fall back to the insecure dev srs if it's enabled.
+    pub fn load_or_dev(path: Option<impl AsRef<Path>>) -> Result<Self> {
+        match path {
+            Some(path) => Self::load(path),
+            #[cfg(feature = "insecure-dev-srs")]
+            None => Ok(Self::insecure_dev()),
+            #[cfg(not(feature = "insecure-dev-srs"))]
+            None => Err(anyhow!(
+                "srs file is required, or enable feature `insecure-dev-srs` for development"
+            )),
+        }
+    }
+
+    fn new(source: SrsSource) -> Self {
+        Self {
+            source,
+            params: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// params of size `k`, and its record to register onchain.
+    pub fn params(&self, k: u32) -> Result<Arc<(ParamsKZG<Bn256>, SrsParam)>> {
+        if let Some(p) = self.params.lock().unwrap().get(&k) {
+            return Ok(p.clone());
+        }
+        let params = match &self.source {
+            SrsSource::File(srs) => {
+                ensure!(
+                    k <= srs.k(),
+                    "circuit needs srs of k {}, but the srs is only of k {}",
+                    k,
+                    srs.k()
+                );
+                let mut params = srs.clone();
+                params.downsize(k);
+                params
+            },
+            #[cfg(feature = "insecure-dev-srs")]
+            SrsSource::InsecureDev => ParamsKZG::<Bn256>::setup(k, fake_rng::CountingRng(42)),
+        };
+        let param = SrsParam {
+            k,
+            srs_hash: params_hash(&params),
+        };
+        let p = Arc::new((params, param));
+        self.params.lock().unwrap().insert(k, p.clone());
+        Ok(p)
+    }
+
+    /// params recorded in `param` of verification parameters.
+    pub fn params_for(&self, param: &[u8]) -> Result<ParamsKZG<Bn256>> {
+        let expected = SrsParam::decode(param)?;
+        let p = self.params(expected.k)?;
+        let (params, actual) = p.as_ref();
+        if actual != &expected {
+            return Err(anyhow!(
+                "srs of k {} differs from the registered one, wrong srs file?",
+                expected.k
+            ));
+        }
+        Ok(params.clone())
+    }
+}
+
+/// sha3-256 of params in raw bytes format.
+pub fn params_hash(params: &ParamsKZG<Bn256>) -> Vec<u8> {
+    let mut hasher = Sha3_256::new();
+    params
+        .write_custom(&mut hasher, SerdeFormat::RawBytes)
+        .expect("write to hasher never fails");
+    hasher.finalize().to_vec()
+}
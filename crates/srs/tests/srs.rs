@@ -0,0 +1,66 @@
+use agger_srs::{params_hash, Srs, SrsParam};
+use fake_rng::CountingRng;
+use halo2_proofs::{
+    halo2curves::bn256::Bn256,
+    poly::{commitment::Params, kzg::commitment::ParamsKZG},
+    SerdeFormat,
+};
+use std::{fs::File, io::BufWriter, path::PathBuf};
+
+/// write an srs of size `k` generated from `seed`, as powers of tau files are.
+fn write_srs(name: &str, k: u32, seed: u64) -> (PathBuf, ParamsKZG<Bn256>) {
+    let path = std::env::temp_dir().join(format!("agger-srs-{}-{}", name, std::process::id()));
+    let params = ParamsKZG::<Bn256>::setup(k, CountingRng(seed));
+    params
+        .write_custom(
+            &mut BufWriter::new(File::create(&path).unwrap()),
+            SerdeFormat::RawBytes,
+        )
+        .unwrap();
+    (path, params)
+}
+
+#[test]
+fn test_params_are_downsized() {
+    let (path, srs_params) = write_srs("downsized", 6, 1);
+    let srs = Srs::load(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let p = srs.params(4).unwrap();
+    let (params, param) = p.as_ref();
+    assert_eq!(params.k(), 4);
+    let mut downsized = srs_params.clone();
+    downsized.downsize(4);
+    assert_eq!(param, &SrsParam {
+        k: 4,
+        srs_hash: params_hash(&downsized),
+    });
+    param.check(params).unwrap();
+    // the full srs is not downsized.
+    assert_eq!(srs.params(6).unwrap().1.srs_hash, params_hash(&srs_params));
+    // it can't be upsized.
+    assert!(srs.params(7).is_err());
+    assert_eq!(
+        params_hash(&srs.params_for(&param.encode()).unwrap()),
+        param.srs_hash
+    );
+}
+
+#[test]
+fn test_params_of_another_srs_are_rejected() {
+    let (path, _) = write_srs("registered", 5, 1);
+    let registered = Srs::load(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let (path, _) = write_srs("another", 5, 2);
+    let another = Srs::load(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let p = registered.params(4).unwrap();
+    let param = &p.1;
+    let err = another.params_for(&param.encode()).unwrap_err();
+    assert!(err.to_string().contains("differs from the registered one"));
+    let err = param.check(&another.params(4).unwrap().0).unwrap_err();
+    assert!(err.to_string().contains("srs hash mismatch"));
+    let err = param.check(&registered.params(3).unwrap().0).unwrap_err();
+    assert!(err.to_string().contains("srs k mismatch"));
+}
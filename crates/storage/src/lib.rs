@@ -1,4 +1,6 @@
-use agger_contract_types::UserQuery;
+use agger_contract_types::{
+    ExecutionFailure, ModuleRegistration, QueryEvent, QueryFailure, QueryResult, UserQuery,
+};
 pub use aptos_schemadb as schemadb;
 use aptos_schemadb::{
     schema::{KeyCodec, Schema, ValueCodec},
@@ -24,6 +26,7 @@ impl AggerStore {
     pub fn new(db: DB) -> Self {
         Self { db }
     }
+
     pub fn last_proved_event_number(&self) -> anyhow::Result<Option<u64>> {
         let mut iters = self
             .db
@@ -32,16 +35,51 @@ impl AggerStore {
         let value = iters.next().transpose()?.map(|(k, _)| k.sequence_number);
         Ok(value)
     }
+
     pub fn last_seen_event(&self) -> anyhow::Result<Option<UserQuery>> {
         let mut iters = self.db.iter::<UserQuerySchema>(ReadOptions::default())?;
         iters.seek_to_last();
         let value = iters.next().transpose()?.map(|(_k, v)| v.query);
         Ok(value)
     }
+
+    /// sequence number of the last module registration prewarmed.
+    pub fn last_prewarmed_registration_number(&self) -> anyhow::Result<Option<u64>> {
+        let mut iters = self
+            .db
+            .iter::<ModuleRegistrationSchema>(ReadOptions::default())?;
+        iters.seek_to_last();
+        let value = iters.next().transpose()?.map(|(k, _)| k.sequence_number);
+        Ok(value)
+    }
+
+    /// stored results which are not submitted yet, along with their queries,
+    /// in the order of the queries.
+    pub fn unsubmitted_results(&self) -> anyhow::Result<Vec<(UserQuery, UserQueryProvingResult)>> {
+        let mut iters = self
+            .db
+            .iter::<UserQueryProofSchema>(ReadOptions::default())?;
+        iters.seek_to_first();
+        let mut results = vec![];
+        for item in iters {
+            let (key, value) = item?;
+            if value.is_submitted() {
+                continue;
+            }
+            let query = self
+                .db
+                .get::<UserQuerySchema>(&key)?
+                .ok_or_else(|| anyhow::anyhow!("query {} is not stored", key.sequence_number))?;
+            results.push((query.query, value));
+        }
+        Ok(results)
+    }
 }
 
 pub const QUERY_COLUMN_FAMILY_NAME: &str = "queries";
 pub const PROOF_COLUMN_FAMILY_NAME: &str = "proofs";
+pub const PROVED_INPUT_COLUMN_FAMILY_NAME: &str = "proved_inputs";
+pub const MODULE_REGISTRATION_COLUMN_FAMILY_NAME: &str = "module_registrations";
 
 #[derive(Clone, Debug)]
 pub struct UserQueryKey {
@@ -104,26 +142,77 @@ impl ValueCodec<UserQuerySchema> for UserQueryValue {
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct UserQueryProvingResult {
     success: bool,
+    /// bcs serialized `QueryResult` if success, `QueryFailure` otherwise.
     result: Vec<u8>,
+    proof: Vec<u8>,
     /// whether the proof is submitted to onchain.
     submitted: bool,
+    /// set if the proof is aggregated with other queries, which are replied together.
+    batch: Option<StoredBatch>,
 }
 
-impl From<anyhow::Result<Vec<u8>>> for UserQueryProvingResult {
-    fn from(value: anyhow::Result<Vec<u8>>) -> Self {
-        match value {
-            Err(e) => Self {
-                success: false,
-                result: e.root_cause().to_string().into_bytes(),
-                submitted: false,
-            },
-            Ok(v) => Self {
-                success: true,
-                result: v,
-                submitted: false,
-            },
+/// Aggregated proof a stored result is proved by, see `agger_prove_dispatcher::ProofBatch`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct StoredBatch {
+    pub id: Vec<u8>,
+    pub size: u32,
+    pub instance_index: u32,
+    pub duplicate: bool,
+}
+
+impl UserQueryProvingResult {
+    pub fn success(result: &QueryResult, proof: Vec<u8>) -> Self {
+        Self {
+            success: true,
+            result: bcs::to_bytes(result).expect("query result is serializable"),
+            proof,
+            submitted: false,
+            batch: None,
+        }
+    }
+
+    pub fn with_batch(mut self, batch: StoredBatch) -> Self {
+        self.batch = Some(batch);
+        self
+    }
+
+    pub fn failure(e: &anyhow::Error) -> Self {
+        let failure = match e.downcast_ref::<ExecutionFailure>() {
+            Some(f) => QueryFailure::Execution(f.clone()),
+            None => QueryFailure::Message(e.root_cause().to_string()),
+        };
+        Self {
+            success: false,
+            result: bcs::to_bytes(&failure).expect("query failure is serializable"),
+            proof: vec![],
+            submitted: false,
+            batch: None,
         }
     }
+
+    pub fn is_success(&self) -> bool {
+        self.success
+    }
+
+    pub fn result(&self) -> &[u8] {
+        &self.result
+    }
+
+    pub fn proof(&self) -> &[u8] {
+        &self.proof
+    }
+
+    pub fn batch(&self) -> Option<&StoredBatch> {
+        self.batch.as_ref()
+    }
+
+    pub fn is_submitted(&self) -> bool {
+        self.submitted
+    }
+
+    pub fn set_submitted(&mut self) {
+        self.submitted = true;
+    }
 }
 
 #[derive(Debug)]
@@ -157,3 +246,118 @@ impl ValueCodec<UserQueryProofSchema> for UserQueryProvingResult {
         Ok(bcs::from_bytes(data)?)
     }
 }
+
+/// hash of the proving inputs of a query, see `agger_prove_dispatcher::proving_input_hash`.
+#[derive(Clone, Debug)]
+pub struct ProvedInputKey {
+    input_hash: Vec<u8>,
+}
+
+impl From<Vec<u8>> for ProvedInputKey {
+    fn from(value: Vec<u8>) -> Self {
+        Self { input_hash: value }
+    }
+}
+
+/// Results and proof of proving inputs, reused by identical queries of other users.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ProvedInputValue {
+    pub return_values: Vec<Vec<u8>>,
+    pub events: Vec<QueryEvent>,
+    pub read_set_hash: Vec<u8>,
+    pub proof: Vec<u8>,
+}
+
+#[derive(Debug)]
+pub struct ProvedInputSchema;
+
+impl Schema for ProvedInputSchema {
+    type Key = ProvedInputKey;
+    type Value = ProvedInputValue;
+
+    const COLUMN_FAMILY_NAME: ColumnFamilyName = PROVED_INPUT_COLUMN_FAMILY_NAME;
+}
+
+impl KeyCodec<ProvedInputSchema> for ProvedInputKey {
+    fn encode_key(&self) -> anyhow::Result<Vec<u8>> {
+        Ok(self.input_hash.clone())
+    }
+
+    fn decode_key(data: &[u8]) -> anyhow::Result<Self> {
+        Ok(Self {
+            input_hash: data.to_vec(),
+        })
+    }
+}
+
+impl ValueCodec<ProvedInputSchema> for ProvedInputValue {
+    fn encode_value(&self) -> anyhow::Result<Vec<u8>> {
+        Ok(bcs::to_bytes(&self)?)
+    }
+
+    fn decode_value(data: &[u8]) -> anyhow::Result<Self> {
+        Ok(bcs::from_bytes(data)?)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ModuleRegistrationKey {
+    sequence_number: u64,
+}
+
+impl From<u64> for ModuleRegistrationKey {
+    fn from(value: u64) -> Self {
+        Self {
+            sequence_number: value,
+        }
+    }
+}
+
+/// Module registrations prewarmed, so that they're skipped after restart.
+#[derive(Clone, Debug)]
+pub struct ModuleRegistrationValue {
+    registration: ModuleRegistration,
+}
+
+impl From<ModuleRegistration> for ModuleRegistrationValue {
+    fn from(value: ModuleRegistration) -> Self {
+        Self {
+            registration: value,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ModuleRegistrationSchema;
+
+impl Schema for ModuleRegistrationSchema {
+    type Key = ModuleRegistrationKey;
+    type Value = ModuleRegistrationValue;
+
+    const COLUMN_FAMILY_NAME: ColumnFamilyName = MODULE_REGISTRATION_COLUMN_FAMILY_NAME;
+}
+
+impl KeyCodec<ModuleRegistrationSchema> for ModuleRegistrationKey {
+    // big endian, so that the last key is the largest sequence number.
+    fn encode_key(&self) -> anyhow::Result<Vec<u8>> {
+        Ok(self.sequence_number.to_be_bytes().to_vec())
+    }
+
+    fn decode_key(data: &[u8]) -> anyhow::Result<Self> {
+        Ok(Self {
+            sequence_number: u64::from_be_bytes(data.try_into()?),
+        })
+    }
+}
+
+impl ValueCodec<ModuleRegistrationSchema> for ModuleRegistrationValue {
+    fn encode_value(&self) -> anyhow::Result<Vec<u8>> {
+        Ok(bcs::to_bytes(&self.registration)?)
+    }
+
+    fn decode_value(data: &[u8]) -> anyhow::Result<Self> {
+        Ok(Self {
+            registration: bcs::from_bytes(data)?,
+        })
+    }
+}
@@ -0,0 +1,98 @@
+use halo2_proofs::{
+    circuit::{Layouter, Value},
+    halo2curves::bn256::Fr,
+    plonk::{Advice, Circuit, Column, ConstraintSystem, Error, Instance},
+};
+use zkmove_vm_circuit::{circuit::VmCircuit, witness::Witness};
+
+/// Circuit of a query: the vm circuit of its execution,
+/// and one instance column holding the public inputs of the query, see `query_instances`.
+/// The public inputs are not constrained to the execution yet, they are only committed to
+/// by the proof, so a prover can prove any public inputs alongside a valid execution.
+pub struct QueryCircuit {
+    pub vm: VmCircuit<Fr>,
+    /// values of the instance column, unknown for keygen.
+    /// only the number of them, decided by the instance layout, shapes the keys.
+    pub public_inputs: Vec<Value<Fr>>,
+}
+
+#[derive(Clone)]
+pub struct QueryCircuitConfig {
+    vm: <VmCircuit<Fr> as Circuit<Fr>>::Config,
+    public_inputs: Column<Advice>,
+    instance: Column<Instance>,
+}
+
+impl QueryCircuit {
+    pub fn new(witness: Witness<Fr>, public_inputs: &[Fr]) -> Self {
+        Self {
+            vm: VmCircuit { witness },
+            public_inputs: public_inputs.iter().copied().map(Value::known).collect(),
+        }
+    }
+
+    /// circuit for key generation of `witness`, with `num_public_inputs` unknown public inputs.
+    pub fn keygen(witness: Witness<Fr>, num_public_inputs: usize) -> Self {
+        Self {
+            vm: VmCircuit { witness },
+            public_inputs: vec![Value::unknown(); num_public_inputs],
+        }
+    }
+}
+
+impl Circuit<Fr> for QueryCircuit {
+    type Config = QueryCircuitConfig;
+    type FloorPlanner = <VmCircuit<Fr> as Circuit<Fr>>::FloorPlanner;
+
+    fn without_witnesses(&self) -> Self {
+        Self {
+            vm: self.vm.without_witnesses(),
+            public_inputs: vec![Value::unknown(); self.public_inputs.len()],
+        }
+    }
+
+    fn configure(meta: &mut ConstraintSystem<Fr>) -> Self::Config {
+        let vm = VmCircuit::<Fr>::configure(meta);
+        let public_inputs = meta.advice_column();
+        let instance = meta.instance_column();
+        meta.enable_equality(public_inputs);
+        meta.enable_equality(instance);
+        QueryCircuitConfig {
+            vm,
+            public_inputs,
+            instance,
+        }
+    }
+
+    fn synthesize(
+        &self,
+        config: Self::Config,
+        mut layouter: impl Layouter<Fr>,
+    ) -> Result<(), Error> {
+        self.vm.synthesize(config.vm, layouter.namespace(|| "vm"))?;
+        // todo: copy the cells of the entry function, its args, return values and global reads
+        // from the vm region once vm-circuit exposes them. until then the public inputs are
+        // free advice cells, nothing relates them to the execution.
+        let cells = layouter.assign_region(
+            || "public inputs",
+            |mut region| {
+                self.public_inputs
+                    .iter()
+                    .enumerate()
+                    .map(|(row, value)| {
+                        region.assign_advice(
+                            || "public input",
+                            config.public_inputs,
+                            row,
+                            || *value,
+                        )
+                    })
+                    .collect::<Result<Vec<_>, _>>()
+            },
+        )?;
+        for (row, cell) in cells.iter().enumerate() {
+            layouter.constrain_instance(cell.cell(), config.instance, row)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,50 @@
+use agger_contract_types::{InstanceField, InstanceLayout, Query};
+use halo2_proofs::{
+    arithmetic::Field,
+    halo2curves::{bn256::Fr, group::ff::PrimeField},
+};
+use sha3::{Digest, Sha3_256};
+
+/// public inputs of the proof of `query`, laid out as `layout`.
+/// prover and verifier must derive them the same way.
+/// They are not constrained to the execution by `QueryCircuit` yet.
+pub fn query_instances(
+    layout: &InstanceLayout,
+    query: &Query,
+    function_index: u16,
+    result_hash: &[u8],
+    read_set_hash: &[u8],
+) -> Vec<Fr> {
+    layout
+        .fields
+        .iter()
+        .map(|field| match field {
+            InstanceField::ModuleId => hash_to_field(
+                &bcs::to_bytes(&(&query.module_address, &query.module_name))
+                    .expect("module id is serializable"),
+            ),
+            InstanceField::FunctionIndex => Fr::from(function_index as u64),
+            InstanceField::Args => {
+                hash_to_field(&bcs::to_bytes(&query.args).expect("args are serializable"))
+            },
+            InstanceField::TyArgs => {
+                hash_to_field(&bcs::to_bytes(&query.ty_args).expect("ty args are serializable"))
+            },
+            InstanceField::ResultHash => hash_to_field(result_hash),
+            InstanceField::ReadSetHash => hash_to_field(read_set_hash),
+        })
+        .collect()
+}
+
+/// placeholder instances of `layout`, for keygen and circuit sizing where values don't matter.
+pub fn placeholder_instances(layout: &InstanceLayout) -> Vec<Fr> {
+    vec![Fr::zero(); layout.fields.len()]
+}
+
+/// sha3-256 truncated to 248 bits, so that it always fits in the scalar field.
+fn hash_to_field(data: &[u8]) -> Fr {
+    let hash = Sha3_256::digest(data);
+    let mut repr = [0u8; 32];
+    repr[..31].copy_from_slice(&hash[..31]);
+    Fr::from_repr(repr).expect("248 bits value is in field")
+}
@@ -1,6 +1,11 @@
+pub use circuit::{QueryCircuit, QueryCircuitConfig};
+pub use instance::{placeholder_instances, query_instances};
 use movelang::{argument::ScriptArguments, value::TypeTag};
 use zkmove_vm_circuit::witness::CircuitConfig as ZkMoveCircuitConfig;
 
+mod circuit;
+mod instance;
+
 #[derive(Copy, Clone, Debug, Default)]
 pub struct CircuitConfig {
     pub max_step_row: Option<usize>,
@@ -64,12 +69,36 @@ pub struct EntryFunctionConfig {
 #[derive(Clone, Debug)]
 pub struct VerificationParameters {
     pub config: Vec<u8>,
+    /// vk of the circuit, as stored by the registry.
     pub vk: Vec<u8>,
     pub param: Vec<u8>,
+    pub instance_layout: Vec<u8>,
+    /// index of the entry function in its module.
+    pub function_index: u16,
 }
 
 impl VerificationParameters {
-    pub fn new(config: Vec<u8>, vk: Vec<u8>, param: Vec<u8>) -> Self {
-        Self { config, vk, param }
+    pub fn new(
+        config: Vec<u8>,
+        vk: Vec<u8>,
+        param: Vec<u8>,
+        instance_layout: Vec<u8>,
+        function_index: u16,
+    ) -> Self {
+        Self {
+            config,
+            vk,
+            param,
+            instance_layout,
+            function_index,
+        }
+    }
+
+    /// vk with the function index appended in little endian,
+    /// as `registry::register_module` takes it. The registry pops the index off before storing.
+    pub fn registered_vk(&self) -> Vec<u8> {
+        let mut vk = self.vk.clone();
+        vk.extend_from_slice(&self.function_index.to_le_bytes());
+        vk
     }
 }
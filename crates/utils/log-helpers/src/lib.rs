@@ -0,0 +1,24 @@
+use anyhow::anyhow;
+use clap::ValueEnum;
+use tracing_subscriber::EnvFilter;
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default)]
+pub enum LogFormat {
+    /// human readable lines, prefixed with the spans they're in.
+    #[default]
+    Text,
+    /// one json object per line, with fields of the spans they're in.
+    Json,
+}
+
+/// init the global subscriber, filtered by `RUST_LOG`, or at info level if it's not set.
+/// records of dependencies still on `log` are forwarded to it as well.
+pub fn init_logger(format: LogFormat) -> anyhow::Result<()> {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+    match format {
+        LogFormat::Text => builder.try_init(),
+        LogFormat::Json => builder.json().with_current_span(true).try_init(),
+    }
+    .map_err(|e| anyhow!("init logger error. {}", e))
+}
@@ -1,4 +1,7 @@
-use move_binary_format::{access::ModuleAccess, file_format::FunctionDefinition};
+use move_binary_format::{
+    access::ModuleAccess,
+    file_format::{AbilitySet, FunctionDefinition, SignatureToken, StructHandle},
+};
 use move_core_types::identifier::IdentStr;
 
 pub trait ModuleAccessExt: ModuleAccess {
@@ -7,6 +10,29 @@ pub trait ModuleAccessExt: ModuleAccess {
             .iter()
             .find(|fd| self.identifier_at(self.function_handle_at(fd.function).name) == name)
     }
+
+    /// find struct defined in this module by name.
+    fn find_struct_handle_by_name(&self, name: &IdentStr) -> Option<&StructHandle> {
+        self.struct_defs()
+            .iter()
+            .map(|sd| self.struct_handle_at(sd.struct_handle))
+            .find(|sh| self.identifier_at(sh.name) == name)
+    }
+
+    fn function_parameter_types(&self, fd: &FunctionDefinition) -> &[SignatureToken] {
+        &self
+            .signature_at(self.function_handle_at(fd.function).parameters)
+            .0
+    }
+
+    /// ability constraints of each type parameter.
+    fn function_type_parameter_abilities(&self, fd: &FunctionDefinition) -> &[AbilitySet] {
+        &self.function_handle_at(fd.function).type_parameters
+    }
+
+    fn is_entry_function(&self, fd: &FunctionDefinition) -> bool {
+        fd.is_entry
+    }
 }
 
 impl<T> ModuleAccessExt for T where T: ModuleAccess {}
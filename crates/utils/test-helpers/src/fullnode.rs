@@ -0,0 +1,106 @@
+//! Mocked aptos fullnode rest api, serving responses of a handler on a local port.
+pub use hyper::StatusCode;
+use hyper::{
+    header::CONTENT_TYPE,
+    http::response::Builder,
+    service::{make_service_fn, service_fn},
+    Body, Request, Response, Server,
+};
+use std::{convert::Infallible, net::SocketAddr, sync::Arc};
+
+/// Ledger info of the mocked fullnode, sent in headers of every response.
+#[derive(Clone, Copy, Debug)]
+pub struct Ledger {
+    pub version: u64,
+    pub oldest_version: u64,
+}
+
+impl Default for Ledger {
+    fn default() -> Self {
+        Self {
+            version: 100,
+            oldest_version: 0,
+        }
+    }
+}
+
+impl Ledger {
+    /// headers the rest client reads ledger info from.
+    pub fn response(&self, status: StatusCode) -> Builder {
+        Response::builder()
+            .status(status)
+            .header("X-Aptos-Chain-Id", "4")
+            .header("X-Aptos-Ledger-Version", self.version.to_string())
+            .header(
+                "X-Aptos-Ledger-Oldest-Version",
+                self.oldest_version.to_string(),
+            )
+            .header("X-Aptos-Ledger-TimestampUsec", "0")
+            .header("X-Aptos-Epoch", "1")
+            .header("X-Aptos-Block-Height", "10")
+            .header("X-Aptos-Oldest-Block-Height", "0")
+    }
+
+    pub fn bcs(&self, data: Vec<u8>) -> Response<Body> {
+        self.response(StatusCode::OK)
+            .header(CONTENT_TYPE, "application/x-bcs")
+            .body(Body::from(data))
+            .unwrap()
+    }
+
+    /// error of the rest api, e.g. `resource_not_found` or `version_pruned`.
+    pub fn error(&self, status: StatusCode, error_code: &str, message: &str) -> Response<Body> {
+        let body = format!(
+            r#"{{"message":"{}","error_code":"{}","vm_error_code":null}}"#,
+            message, error_code
+        );
+        self.response(status)
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(body))
+            .unwrap()
+    }
+}
+
+/// response to requests the mock doesn't serve.
+pub fn bad_request() -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::BAD_REQUEST)
+        .body(Body::empty())
+        .unwrap()
+}
+
+/// segments of the request path, starting with an empty one, e.g. `["", "v1", "tables", ..]`.
+pub fn path_segments(request: &Request<Body>) -> Vec<&str> {
+    request.uri().path().split('/').collect()
+}
+
+/// `ledger_version` the request asks for, none if at latest.
+pub fn ledger_version(request: &Request<Body>) -> Option<u64> {
+    request
+        .uri()
+        .query()?
+        .split('&')
+        .find_map(|p| p.strip_prefix("ledger_version="))
+        .and_then(|v| v.parse().ok())
+}
+
+/// serve `handle` on a local port, and return its address.
+pub async fn start_mock_fullnode<F>(handle: F) -> SocketAddr
+where
+    F: Fn(Request<Body>) -> Response<Body> + Send + Sync + 'static,
+{
+    let handle = Arc::new(handle);
+    let make_service = make_service_fn(move |_| {
+        let handle = handle.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |r| {
+                let response = handle(r);
+                async move { Ok::<_, Infallible>(response) }
+            }))
+        }
+    });
+    let server = Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(make_service);
+    let addr = server.local_addr();
+    tokio::spawn(server);
+    addr
+}
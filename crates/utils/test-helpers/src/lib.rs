@@ -0,0 +1,52 @@
+//! Helpers shared by tests of agger crates, only used as a dev-dependency.
+pub mod fullnode;
+
+use move_binary_format::CompiledModule;
+use move_compiler::{compiled_unit::CompiledUnit, Compiler};
+use std::{
+    collections::BTreeMap,
+    path::Path,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+static SOURCE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// compile the move module in `source`, written to a temp file of its own,
+/// so that tests compiling in parallel don't overwrite each other's sources.
+pub fn compile_module(source: &str) -> CompiledModule {
+    let path = std::env::temp_dir().join(format!(
+        "agger-test-{}-{}.move",
+        std::process::id(),
+        SOURCE_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    std::fs::write(&path, source).unwrap();
+    let module = compile_module_file(&path);
+    std::fs::remove_file(&path).unwrap();
+    module
+}
+
+/// compile the move module in the source file at `path`.
+pub fn compile_module_file(path: &Path) -> CompiledModule {
+    let (_files, units) = Compiler::from_files(
+        vec![path.to_string_lossy().to_string()],
+        vec![],
+        BTreeMap::new(),
+    )
+    .build_and_report()
+    .unwrap();
+    match units.into_iter().next().unwrap().into_compiled_unit() {
+        CompiledUnit::Module(m) => m.module,
+        _ => panic!("expect a module"),
+    }
+}
+
+/// serialized `compile_module`.
+pub fn compile_module_bytes(source: &str) -> Vec<u8> {
+    serialize(&compile_module(source))
+}
+
+pub fn serialize(module: &CompiledModule) -> Vec<u8> {
+    let mut bytes = vec![];
+    module.serialize(&mut bytes).unwrap();
+    bytes
+}
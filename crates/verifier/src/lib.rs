@@ -0,0 +1,195 @@
+//! Offline verification of query proofs.
+//! Vk and instances are rebuilt the same way prove-dispatcher builds them,
+//! so that replies can be checked before `Query.result` is trusted.
+
+use agger_contract_types::{
+    query_binding_hash, query_result_hash, InstanceLayout, Query, QueryResult, UserQuery,
+    VerificationParameters,
+};
+use agger_srs::Srs;
+use agger_types::{query_instances, QueryCircuit};
+use anyhow::{anyhow, ensure, Result};
+use halo2_proofs::{
+    halo2curves::bn256::{Bn256, Fr, G1Affine},
+    plonk::{keygen_vk, verify_proof, VerifyingKey},
+    poly::{
+        commitment::ParamsProver,
+        kzg::{
+            commitment::{KZGCommitmentScheme, ParamsKZG},
+            multiopen::VerifierSHPLONK,
+            strategy::SingleStrategy,
+        },
+    },
+    transcript::{Blake2bRead, Challenge255, TranscriptReadBuffer},
+    SerdeFormat,
+};
+use move_binary_format::CompiledModule;
+use move_core_types::identifier::Identifier;
+use move_helpers::access_ext::ModuleAccessExt;
+use std::{collections::BTreeMap, sync::Arc};
+use zkmove_vm_circuit::witness::Witness;
+
+/// Verifier of query proofs against verification parameters of entry functions.
+pub struct AggerVerifier {
+    srs: Arc<Srs>,
+}
+
+impl AggerVerifier {
+    /// `srs` must be the one verification parameters are generated from.
+    pub fn new(srs: Arc<Srs>) -> Self {
+        Self { srs }
+    }
+
+    /// check `proof` of `query` and its `result`.
+    /// `verification_parameters` are the ones stored by the registry for the entry function
+    /// at `function_index`, see `entry_function_index`.
+    /// The proof only commits to the query, `result` and `result.read_set_hash` as public inputs,
+    /// which `QueryCircuit` doesn't constrain to the execution yet: it doesn't attest that
+    /// executing the query on those resources returns `result`, only that its prover
+    /// claimed so. Callers must trust the prover for the result until then.
+    /// Returns false if the proof or binding hash is invalid,
+    /// and errors if the verification parameters are malformed.
+    pub fn verify(
+        &self,
+        verification_parameters: &VerificationParameters,
+        function_index: u16,
+        query: &UserQuery,
+        result: &QueryResult,
+        proof: &[u8],
+    ) -> Result<bool> {
+        let params = self.srs.params_for(&verification_parameters.param)?;
+        let vk = rebuild_vk(&params, verification_parameters)?;
+        let layout = InstanceLayout::decode(&verification_parameters.instance_layout)?;
+        let instances = query_instances(
+            &layout,
+            &query.query,
+            function_index,
+            &query_result_hash(&result.return_values, &result.events),
+            &result.read_set_hash,
+        );
+        if query_binding_hash(
+            query,
+            &result.return_values,
+            &result.events,
+            result.state_version,
+            proof,
+        ) != result.binding_hash
+        {
+            return Ok(false);
+        }
+        Ok(verify_kzg(&params, &vk, &[&instances], proof).is_ok())
+    }
+
+    /// check an aggregated `proof` of queries of the same entry function.
+    /// Each reply has the index of its instances in the proof,
+    /// replies of identical queries may share the same index.
+    /// Returns false unless every instances of the proof are covered by the replies.
+    pub fn verify_batch(
+        &self,
+        verification_parameters: &VerificationParameters,
+        function_index: u16,
+        replies: &[(UserQuery, QueryResult, u32)],
+        proof: &[u8],
+    ) -> Result<bool> {
+        let params = self.srs.params_for(&verification_parameters.param)?;
+        let vk = rebuild_vk(&params, verification_parameters)?;
+        let layout = InstanceLayout::decode(&verification_parameters.instance_layout)?;
+        let mut instances: BTreeMap<u32, Vec<Fr>> = BTreeMap::new();
+        for (query, result, index) in replies {
+            if query_binding_hash(
+                query,
+                &result.return_values,
+                &result.events,
+                result.state_version,
+                proof,
+            ) != result.binding_hash
+            {
+                return Ok(false);
+            }
+            let expected = query_instances(
+                &layout,
+                &query.query,
+                function_index,
+                &query_result_hash(&result.return_values, &result.events),
+                &result.read_set_hash,
+            );
+            match instances.get(index) {
+                Some(existing) if existing != &expected => return Ok(false),
+                Some(_) => {},
+                None => {
+                    instances.insert(*index, expected);
+                },
+            }
+        }
+        // indices must be 0..n without gaps.
+        if instances.keys().copied().ne(0..instances.len() as u32) {
+            return Ok(false);
+        }
+        let instances: Vec<&[Fr]> = instances.values().map(|i| i.as_slice()).collect();
+        Ok(verify_kzg(&params, &vk, &instances, proof).is_ok())
+    }
+}
+
+/// index of the entry function of `query` in `module`, the entry module of the query.
+/// Verification parameters are registered and proved under this index.
+pub fn entry_function_index(module: &[u8], query: &Query) -> Result<u16> {
+    let module = CompiledModule::deserialize(module)?;
+    let function_name = Identifier::from_utf8(query.function_name.clone())?;
+    let function = module
+        .find_function_def_by_name(&function_name)
+        .ok_or_else(|| anyhow!("function {} not found", &function_name))?;
+    Ok(function.function.0)
+}
+
+/// Circuit used only for key generation.
+/// Keys only depend on the circuit config and the number of public inputs,
+/// witness values are dropped by keygen anyway.
+/// As the public inputs are not tied to the vm region, the keys don't tie them either.
+pub fn keygen_circuit(config: &[u8], instance_layout: &[u8]) -> Result<QueryCircuit> {
+    let witness = Witness {
+        circuit_config: bcs::from_bytes(config)?,
+        ..Default::default()
+    };
+    let layout = InstanceLayout::decode(instance_layout)?;
+    Ok(QueryCircuit::keygen(witness, layout.fields.len()))
+}
+
+/// rebuild vk of the registered circuit, and check it's the registered vk.
+pub fn rebuild_vk(
+    params: &ParamsKZG<Bn256>,
+    verification_parameters: &VerificationParameters,
+) -> Result<VerifyingKey<G1Affine>> {
+    let circuit = keygen_circuit(
+        &verification_parameters.config,
+        &verification_parameters.instance_layout,
+    )?;
+    let rebuilt = keygen_vk(params, &circuit)?;
+    ensure!(
+        rebuilt.to_bytes(SerdeFormat::Processed) == verification_parameters.vk,
+        "vk equality checking failure"
+    );
+    Ok(rebuilt)
+}
+
+/// verify `proof` with the halo2 kzg verifier, the same way it is verified onchain.
+/// `instances` has the instance column of each circuit proved by the proof.
+pub fn verify_kzg(
+    params: &ParamsKZG<Bn256>,
+    vk: &VerifyingKey<G1Affine>,
+    instances: &[&[Fr]],
+    proof: &[u8],
+) -> Result<()> {
+    let strategy = SingleStrategy::new(params);
+    let mut transcript = Blake2bRead::<_, G1Affine, Challenge255<_>>::init(proof);
+    verify_proof::<KZGCommitmentScheme<Bn256>, VerifierSHPLONK<'_, Bn256>, _, _, _>(
+        params.verifier_params(),
+        vk,
+        strategy,
+        &instances
+            .iter()
+            .map(std::slice::from_ref)
+            .collect::<Vec<_>>(),
+        &mut transcript,
+    )
+    .map_err(|e| anyhow!("{:?}", e))
+}
@@ -0,0 +1,70 @@
+use agger_srs::Srs;
+use agger_types::{CircuitConfig, DemoRunConfig, EntryFunctionConfig};
+use agger_verifier::keygen_circuit;
+use agger_vk_generation::gen_vks;
+use halo2_proofs::{plonk::keygen_vk, SerdeFormat};
+use move_core_types::parser::parse_transaction_argument;
+use movelang::argument::ScriptArguments;
+use test_helpers::compile_module_bytes;
+
+const MODULE: &str = r#"
+module 0xcafe::shapes {
+    public entry fun noop() {}
+
+    public entry fun add(a: u64, b: u64) {
+        let _ = a + b;
+    }
+
+    public entry fun sum(n: u64) {
+        let i = 0;
+        let s = 0;
+        while (i < n) {
+            s = s + i;
+            i = i + 1;
+        };
+        let _ = s;
+    }
+}
+"#;
+
+fn entry_function(name: &str, args: &[&str]) -> EntryFunctionConfig {
+    EntryFunctionConfig {
+        entry_module_address: "0xcafe".to_string(),
+        entry_module_name: "shapes".to_string(),
+        entry_function: name.to_string(),
+        demo_run_config: DemoRunConfig {
+            args: Some(ScriptArguments::new(
+                args.iter()
+                    .map(|a| parse_transaction_argument(a).unwrap())
+                    .collect(),
+            )),
+            ty_args: None,
+        },
+        circuit_config: CircuitConfig::default(),
+    }
+}
+
+/// verifiers rebuild the vk from the registered config and layout only,
+/// it must be the vk generated from the demo run.
+#[test]
+fn test_keygen_circuit_vk_equals_generated_vk() {
+    let srs = Srs::insecure_dev();
+    let vks = gen_vks(&srs, vec![compile_module_bytes(MODULE)], vec![
+        entry_function("noop", &[]),
+        entry_function("add", &["1u64", "2u64"]),
+        entry_function("sum", &["10u64"]),
+    ])
+    .unwrap();
+    assert_eq!(vks.len(), 3);
+    for vp in vks {
+        let params = srs.params_for(&vp.param).unwrap();
+        let circuit = keygen_circuit(&vp.config, &vp.instance_layout).unwrap();
+        let vk = keygen_vk(&params, &circuit).unwrap();
+        assert_eq!(
+            vk.to_bytes(SerdeFormat::Processed),
+            vp.vk,
+            "vk of function {} differs",
+            vp.function_index
+        );
+    }
+}
@@ -1,9 +1,13 @@
-use agger_types::{EntryFunctionConfig, VerificationParameters};
+use agger_contract_types::InstanceLayout;
+use agger_srs::Srs;
+use agger_types::{
+    placeholder_instances, EntryFunctionConfig, QueryCircuit, VerificationParameters,
+};
 use anyhow::anyhow;
-use fake_rng::CountingRng;
 use halo2_proofs::{
-    halo2curves::bn256::{Bn256, Fr},
-    poly::kzg::commitment::ParamsKZG,
+    dev::MockProver,
+    halo2curves::bn256::Fr,
+    plonk::{keygen_vk, Error},
     SerdeFormat,
 };
 use move_binary_format::CompiledModule;
@@ -16,9 +20,9 @@ use move_core_types::{
 use move_helpers::access_ext::ModuleAccessExt;
 use std::str::FromStr;
 use zkmove_vm::{runtime::Runtime, state::StateStore};
-use zkmove_vm_circuit::{circuit::VmCircuit, find_best_k, setup_vm_circuit};
 
 pub fn gen_vks(
+    srs: &Srs,
     modules: Vec<Vec<u8>>,
     entry_function_config: Vec<EntryFunctionConfig>,
 ) -> anyhow::Result<Vec<VerificationParameters>> {
@@ -30,7 +34,6 @@ pub fn gen_vks(
         compiled_modules.push(m.clone());
         state.add_module(m);
     }
-
     let mut vks = Vec::new();
     for EntryFunctionConfig {
         entry_module_address,
@@ -61,7 +64,14 @@ pub fn gen_vks(
                 demo_run_config.args.clone(),
                 &mut state,
             )
-            .unwrap();
+            .map_err(|e| {
+                anyhow!(
+                    "execute entry function {}::{} failure: {:?}",
+                    &entry_module,
+                    entry_function_name,
+                    e
+                )
+            })?;
 
         let witness = rt.process_execution_trace(
             demo_run_config.ty_args.clone().unwrap_or_default(),
@@ -74,15 +84,17 @@ pub fn gen_vks(
 
         let circuit_config = witness.circuit_config.clone();
 
-        let vm_circuit = VmCircuit { witness };
-        let k = find_best_k(&vm_circuit, vec![])?;
+        let instance_layout = InstanceLayout::default();
+        let instances = placeholder_instances(&instance_layout);
+        let circuit = QueryCircuit::new(witness, &instances);
+        let k = find_best_k(&circuit, instances)?;
 
-        // TODO: change the fake param
-        let params = ParamsKZG::<Bn256>::setup(k, CountingRng(42));
+        let params = srs.params(k)?;
+        let (params, srs_param) = params.as_ref();
 
-        let (vk, _) = setup_vm_circuit(&vm_circuit, &params)?;
+        let vk = keygen_vk(params, &circuit)?;
 
-        let mut vk = vk.to_bytes(SerdeFormat::Processed);
+        let vk = vk.to_bytes(SerdeFormat::Processed);
 
         let entry_function_index = compiled_module
             .find_function_def_by_name(entry_function_name)
@@ -93,22 +105,34 @@ pub fn gen_vks(
             .function
             .0;
 
-        extend_vk_with_func_info(&mut vk, entry_function_index);
-        // let params = {
-        //     let mut serialzied_param = Vec::new();
-        //     params.write_custom(&mut serialzied_param, SerdeFormat::Processed)?;
-        //     serialzied_param
-        // };
-
         vks.push(VerificationParameters {
             config: bcs::to_bytes(&circuit_config)?, // TODO: change to a more common serialization lib.
             vk,
-            param: bcs::to_bytes(&k)?,
+            param: srs_param.encode(),
+            instance_layout: bcs::to_bytes(&instance_layout)?,
+            function_index: entry_function_index,
         });
     }
     Ok(vks)
 }
 
-fn extend_vk_with_func_info(vk: &mut Vec<u8>, entry_function_index: u16) {
-    vk.append(&mut entry_function_index.to_le_bytes().to_vec());
+/// largest k of bn256 powers of tau.
+const MAX_K: u32 = 28;
+
+/// smallest k the query circuit fits in, and is satisfied with `instances`.
+/// Starts from the k of its vm circuit, the public inputs take a few more rows.
+pub fn find_best_k(circuit: &QueryCircuit, instances: Vec<Fr>) -> anyhow::Result<u32> {
+    let mut k = zkmove_vm_circuit::find_best_k(&circuit.vm, vec![])?;
+    loop {
+        match MockProver::run(k, circuit, vec![instances.clone()]) {
+            Ok(prover) => {
+                prover
+                    .verify()
+                    .map_err(|e| anyhow!("circuit constraints are not satisfied. {:?}", e))?;
+                return Ok(k);
+            },
+            Err(Error::NotEnoughRowsAvailable { .. }) if k < MAX_K => k += 1,
+            Err(e) => return Err(anyhow!("mock prover error. {:?}", e)),
+        }
+    }
 }
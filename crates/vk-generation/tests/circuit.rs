@@ -0,0 +1,68 @@
+use agger_types::QueryCircuit;
+use agger_vk_generation::find_best_k;
+use halo2_proofs::{arithmetic::Field, dev::MockProver, halo2curves::bn256::Fr};
+use move_core_types::{
+    account_address::AccountAddress, identifier::Identifier, language_storage::ModuleId,
+    parser::parse_transaction_argument,
+};
+use movelang::argument::ScriptArguments;
+use test_helpers::compile_module;
+use zkmove_vm::{runtime::Runtime, state::StateStore};
+use zkmove_vm_circuit::witness::{CircuitConfig, Witness};
+
+const ADD_MODULE: &str = r#"
+module 0xcafe::adder {
+    public entry fun add(a: u64, b: u64) {
+        let _ = a + b;
+    }
+}
+"#;
+
+/// witness of `0xcafe::adder::add(3, 4)`.
+fn add_witness() -> Witness<Fr> {
+    let module = compile_module(ADD_MODULE);
+    let mut state = StateStore::new();
+    state.add_module(module.clone());
+    let rt = Runtime::<Fr>::new();
+    let module_id = ModuleId::new(
+        AccountAddress::from_hex_literal("0xcafe").unwrap(),
+        Identifier::new("adder").unwrap(),
+    );
+    let function = Identifier::new("add").unwrap();
+    let args = ScriptArguments::new(vec![
+        parse_transaction_argument("3u64").unwrap(),
+        parse_transaction_argument("4u64").unwrap(),
+    ]);
+    let traces = rt
+        .execute_entry_function(&module_id, &function, vec![], None, Some(args), &mut state)
+        .unwrap();
+    rt.process_execution_trace(
+        vec![],
+        None,
+        Some((&module_id, &function)),
+        vec![module],
+        traces,
+        CircuitConfig::default(),
+    )
+    .unwrap()
+}
+
+#[test]
+fn test_tampered_instances_fail() {
+    let instances: Vec<Fr> = (1..=5u64).map(Fr::from).collect();
+    let circuit = QueryCircuit::new(add_witness(), &instances);
+    let k = find_best_k(&circuit, instances.clone()).unwrap();
+
+    let mut tampered = instances.clone();
+    tampered[4] += Fr::one();
+    let prover = MockProver::run(k, &circuit, vec![tampered]).unwrap();
+    assert!(prover.verify().is_err());
+
+    // instances of another query are rejected as well.
+    let other: Vec<Fr> = (2..=6u64).map(Fr::from).collect();
+    let prover = MockProver::run(k, &circuit, vec![other]).unwrap();
+    assert!(prover.verify().is_err());
+
+    let prover = MockProver::run(k, &circuit, vec![instances]).unwrap();
+    assert!(prover.verify().is_ok());
+}